@@ -0,0 +1,11 @@
+#!/usr/bin/env sh
+# Renders every shipped brush package through the scribble fixture and compares against the
+# reference images in testdata/golden.  Pass --bless to regenerate the references.
+# Uses mesa's software rasterizer, so no gpu is needed.
+
+export LIBGL_ALWAYS_SOFTWARE=1
+export EGL_PLATFORM=surfaceless
+mkdir -p target/golden testdata/golden
+cargo run --bin golden-brushes -- "$@" \
+  testdata/strokes/scribble.jsonl testdata/golden target/golden \
+  ../unibrushes/* ../assets/brushes ../assets/pointshaders ../assets/animshaders ../assets/interpolators
@@ -9,6 +9,11 @@ ffi.cdef[[
     float speedy;
     float distance;
     float counter;
+    float pressure;
+    float tilt;
+    float orientation;
+    float tool;
+    float buttons;
   };
 
   void lua_pushpoint(void *output, int queue, struct ShaderPaintPoint *point);
@@ -19,6 +24,10 @@ ffi.cdef[[
   void lua_pushcubicbezier(void *output, int queue, struct ShaderPaintPoint *a, struct ShaderPaintPoint *b, struct ShaderPaintPoint *c, struct ShaderPaintPoint *d);
   void lua_clearlayer(void *output, int layer);
   void lua_savelayers(void *output);
+  void lua_removelayer(void *output, int layer);
+  void lua_movelayer(void *output, int from, int to);
+  void lua_mergelayer(void *output, int layer);
+  void lua_duplicatelayer(void *output, int layer);
   void lua_saveundobuffer(void *output);
 ]]
 
@@ -60,8 +69,23 @@ function create_sandbox()
     pushcubicbezier = pushcubicbezier,
     clearlayer = clearlayer,
     savelayers = savelayers,
+    removelayer = removelayer,
+    movelayer = movelayer,
+    mergelayer = mergelayer,
+    duplicatelayer = duplicatelayer,
     saveundo = saveundo,
     ShaderPaintPoint = ShaderPaintPoint,
+    bit = { band = bit.band, bor = bit.bor },
+    TOOL_UNKNOWN = 0,
+    TOOL_FINGER = 1,
+    TOOL_STYLUS = 2,
+    TOOL_MOUSE = 3,
+    TOOL_ERASER = 4,
+    BUTTON_PRIMARY = 0x01,
+    BUTTON_SECONDARY = 0x02,
+    BUTTON_TERTIARY = 0x04,
+    BUTTON_STYLUS_PRIMARY = 0x20,
+    BUTTON_STYLUS_SECONDARY = 0x40,
   }
   return sandboxed
 end
@@ -28,6 +28,18 @@ end
 callbacks.savelayers = function()
   ffi.C.lua_savelayers(output)
 end
+callbacks.removelayer = function(layer)
+  ffi.C.lua_removelayer(output, layer)
+end
+callbacks.movelayer = function(from, to)
+  ffi.C.lua_movelayer(output, from, to)
+end
+callbacks.mergelayer = function(layer)
+  ffi.C.lua_mergelayer(output, layer)
+end
+callbacks.duplicatelayer = function(layer)
+  ffi.C.lua_duplicatelayer(output, layer)
+end
 callbacks.saveundo = function()
   ffi.C.lua_saveundobuffer(output)
 end
@@ -0,0 +1,139 @@
+// Golden-image regression check for brush packages.
+//
+// usage: golden-brushes [--bless] [--tolerance CHANNEL,FRACTION] [--size WxH]
+//                       <stroke file> <reference dir> <output dir> <package>...
+//
+// A package is a unibrush directory (one with a brush.json), a single file from one of the
+// assets/ directories, or a whole assets/<kind> directory.  Each is rendered with the strokes
+// from the stroke file and compared against <reference dir>/<name>.png.  Failures leave the
+// output and a diff image in <output dir>.  --bless overwrites the references instead.
+
+#![feature(core, env, io, path)]
+
+extern crate rustgl;
+
+use std::env;
+use std::borrow::IntoCow;
+use std::old_path::Path;
+use std::old_io::{stderr, Writer};
+use std::old_io::fs::{self, PathExtensions};
+use rustgl::{eglinit, golden, pointfile, unibrush};
+use rustgl::golden::{GoldenSettings, Tolerance};
+use rustgl::unibrush::UniBrushSource;
+use rustgl::glcommon::GLResult;
+
+fn complain(msg: &str) {
+    let _ = stderr().write_line(format!("golden-brushes: {}", msg).as_slice());
+}
+
+/// For problems with the arguments or environment, as opposed to failed checks.
+fn usage_error(msg: &str) {
+    complain(msg);
+    env::set_exit_status(2);
+}
+
+fn packages_at(path: &Path) -> GLResult<Vec<(String, UniBrushSource)>> {
+    let dirname = |p: &Path| p.dir_path().filename_str().unwrap_or("").to_string();
+    if path.join("brush.json").exists() {
+        let brush = try!(unibrush::load_from_dir(path));
+        Ok(vec![(format!("unibrush-{}", unibrush::package_name(path)), brush)])
+    } else if path.is_dir() {
+        let kind = unibrush::package_name(path);
+        let mut files = match fs::readdir(path) {
+            Ok(files) => files,
+            Err(e) => return Err(format!("can't list {}: {}", path.display(), e).into_cow()),
+        };
+        files.sort();
+        let mut packages = Vec::new();
+        for file in files.iter() {
+            let brush = try!(unibrush::load_asset(kind.as_slice(), file));
+            packages.push((format!("{}-{}", kind, unibrush::package_name(file)), brush));
+        }
+        Ok(packages)
+    } else {
+        let kind = dirname(path);
+        let brush = try!(unibrush::load_asset(kind.as_slice(), path));
+        Ok(vec![(format!("{}-{}", kind, unibrush::package_name(path)), brush)])
+    }
+}
+
+fn parse_tolerance(arg: &str) -> Option<Tolerance> {
+    let parts: Vec<&str> = arg.split(',').collect();
+    if parts.len() != 2 {
+        return None;
+    }
+    match (parts[0].parse::<u8>(), parts[1].parse::<f32>()) {
+        (Ok(channel), Ok(fraction)) => Some(Tolerance { channel: channel, fraction: fraction }),
+        _ => None,
+    }
+}
+
+fn parse_size(arg: &str) -> Option<(i32, i32)> {
+    let parts: Vec<&str> = arg.split('x').collect();
+    if parts.len() != 2 {
+        return None;
+    }
+    match (parts[0].parse::<i32>(), parts[1].parse::<i32>()) {
+        (Ok(w), Ok(h)) if w > 0 && h > 0 => Some((w, h)),
+        _ => None,
+    }
+}
+
+fn main() {
+    let mut settings = GoldenSettings {
+        dimensions: (256, 256),
+        brush_color: 0x00ffffff,
+        brush_size: 1f32,
+        tolerance: Tolerance { channel: 2, fraction: 0.001f32 },
+    };
+    let mut bless = false;
+    let mut positional = Vec::new();
+    let mut args = env::args().skip(1);
+    loop {
+        let arg = match args.next() { Some(arg) => arg, None => break };
+        match arg.as_slice() {
+            "--bless" => bless = true,
+            "--tolerance" => match args.next().and_then(|x| parse_tolerance(x.as_slice())) {
+                Some(t) => settings.tolerance = t,
+                None => return usage_error("--tolerance needs CHANNEL,FRACTION"),
+            },
+            "--size" => match args.next().and_then(|x| parse_size(x.as_slice())) {
+                Some(d) => settings.dimensions = d,
+                None => return usage_error("--size needs WIDTHxHEIGHT"),
+            },
+            _ => positional.push(arg),
+        }
+    }
+    if positional.len() < 4 {
+        return usage_error("usage: golden-brushes [--bless] [--tolerance CHANNEL,FRACTION] [--size WxH] <stroke file> <reference dir> <output dir> <package>...");
+    }
+    let strokes = match pointfile::load_from_file(positional[0].as_slice()) {
+        Ok(strokes) => strokes,
+        Err(e) => return usage_error(e.as_slice()),
+    };
+    let (refdir, outdir) = (Path::new(positional[1].as_slice()), Path::new(positional[2].as_slice()));
+    let (w, h) = settings.dimensions;
+    if !eglinit::egl_init_pbuffer(w, h) {
+        return usage_error("couldn't create an offscreen egl context");
+    }
+
+    let mut failures = 0;
+    for path in positional.slice_from(3).iter() {
+        let packages = match packages_at(&Path::new(path.as_slice())) {
+            Ok(packages) => packages,
+            Err(e) => { complain(format!("{}: {}", path, e).as_slice()); failures += 1; continue; },
+        };
+        for &(ref name, ref brush) in packages.iter() {
+            match golden::check_package(name.as_slice(), brush, strokes.as_slice(), &settings, &refdir, &outdir, bless) {
+                Ok(true) => { },
+                Ok(false) => failures += 1,
+                Err(e) => { complain(format!("{}: {}", name, e).as_slice()); failures += 1; },
+            }
+        }
+    }
+    eglinit::egl_finish();
+    if failures > 0 {
+        complain(format!("{} package(s) failed", failures).as_slice());
+        env::set_exit_status(1);
+    }
+}
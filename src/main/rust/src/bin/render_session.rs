@@ -0,0 +1,43 @@
+// Renders a saved session file to a PNG using an offscreen EGL pbuffer.
+//
+// usage: render-session <session file> <width> <height> <output png>
+//
+// Works with any EGL that can hand out GLES2 pbuffers, including mesa's llvmpipe, so it runs
+// on machines with neither a GPU nor an android device.
+
+#![feature(core, env, io)]
+
+extern crate rustgl;
+
+use std::env;
+use std::old_io::{stderr, Writer};
+use rustgl::{eglinit, headless, pngfile, sessionfile};
+
+fn fail(msg: &str) {
+    let _ = stderr().write_line(format!("render-session: {}", msg).as_slice());
+    env::set_exit_status(1);
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() != 5 {
+        return fail("usage: render-session <session file> <width> <height> <output png>");
+    }
+    let (w, h) = match (args[2].parse::<i32>(), args[3].parse::<i32>()) {
+        (Ok(w), Ok(h)) if w > 0 && h > 0 => (w, h),
+        _ => return fail("width and height must be positive integers"),
+    };
+    let session = match sessionfile::load_from_file(args[1].as_slice()) {
+        Ok(session) => session,
+        Err(e) => return fail(e.as_slice()),
+    };
+    if !eglinit::egl_init_pbuffer(w, h) {
+        return fail("couldn't create an offscreen egl context");
+    }
+    let result = headless::render_session(session, w, h)
+        .and_then(|pixels| pngfile::save_png(args[4].as_slice(), w, h, pixels.as_slice()));
+    eglinit::egl_finish();
+    if let Err(e) = result {
+        fail(e.as_slice());
+    }
+}
@@ -1,16 +1,14 @@
 /// Event log for a drawing session
 /// Menu selections must pass through here to ensure they can be referenced
 /// Motion events can be shoved in wherever, though
-/// TODO: replay
-/// use timestamps on point events, rather than Frame events, so writing can be done from one
-/// thread
-/// think about how to make shaders handle accelerated time
+/// Replay can be paced either by recorded Frame events or by point timestamps; see
+/// event_stream::ReplaySpeed
 ///
 // TODO: remove all this duplication
 
 use core::prelude::*;
 use core::borrow::ToOwned;
-//use collections::vec::Vec;
+use collections::vec::Vec;
 use point::PointEntry;
 use glstore::{DrawObjectIndex, DrawObjectList};
 use glstore::{ShaderInitValues, BrushInitValues, LuaInitValues};
@@ -20,41 +18,80 @@ use gltexture::{BrushTexture, PixelFormat};
 use pointshader::PointShader;
 use copyshader::CopyShader;
 use luascript::LuaScript;
-use paintlayer::PaintLayer;
+use paintlayer::{PaintLayer, LayerStyle};
+use glinit::{EraserMode, Reframe};
+use matrix::{Affine, Rotation};
+use smoothing::StrokeFilter;
 use glcommon::{GLResult, MString, UsingDefaults};
 use drawevent::event_stream::EventState;
+use sessionfile::SessionData;
+use core::borrow::IntoCow;
+use core::cell::Cell;
+use alloc::rc::Rc;
 //use collections::slice::CloneSliceExt;
 
 // can't use Copy, wtf
 #[derive(Clone)]
-enum DrawEvent {
+pub enum DrawEvent {
     UseAnimShader(DrawObjectIndex<CopyShader>),
     UseCopyShader(DrawObjectIndex<CopyShader>),
     UsePointShader(DrawObjectIndex<PointShader>),
     UseBrush(DrawObjectIndex<BrushTexture>),
     UseInterpolator(DrawObjectIndex<LuaScript>),
+    BrushColor(i32),
+    BrushSize(f32),
+    EraserMode(EraserMode),
+    StrokeFilter(StrokeFilter),
     Point(PointEntry),
     AddLayer(Option<DrawObjectIndex<CopyShader>>, Option<DrawObjectIndex<PointShader>>, i32),
     ClearLayers,
+    LayerStyle(i32, LayerStyle),
+    RemoveLayer(i32),
+    MoveLayer(i32, i32),
+    MergeLayer(i32),
+    DuplicateLayer(i32),
+    /// layer, index into the log's images, and where the image was placed
+    LayerImage(i32, i32, Affine),
+    /// index into the log's images, fitted onto the canvas at a rotation
+    Image(i32, Rotation),
+    PushUndo,
+    LoadUndo(i32),
+    ClearUndo,
+    Clear,
     Frame,
+    Reframe(Reframe),
 }
 
+/// An imported image's width and height, and its RGBA pixels.
+pub type LayerImageValues = ((i32, i32), Vec<u8>);
+
+/// Append-only, so indices into the list stay valid for as long as the session does.  Events are
+/// only recorded once they're known to apply, so replaying one can't fail where the original
+/// didn't.
 pub struct Events<'a> {
-    //eventlist: Vec<DrawEvent>,
+    eventlist: Vec<DrawEvent>,
+    /// shared with any replay, so it can turn recording back on however it ends
+    recording: Rc<Cell<bool>>,
+    /// the canvas size the log starts from, once it's known
+    canvas: Option<(i32, i32)>,
     pointshaders: DrawObjectList<'a, PointShader, ShaderInitValues>,
     copyshaders: DrawObjectList<'a, CopyShader, ShaderInitValues>,
     textures: DrawObjectList<'a, BrushTexture, BrushInitValues>,
     luascripts: DrawObjectList<'a, LuaScript, LuaInitValues>,
+    images: Vec<LayerImageValues>,
 }
 
 impl<'a> Events<'a> {
     pub fn new() -> Events<'a> {
         Events {
-            //eventlist: Vec::new(),
+            eventlist: Vec::new(),
+            recording: Rc::new(Cell::new(true)),
+            canvas: None,
             pointshaders: DrawObjectList::new(),
             copyshaders: DrawObjectList::new(),
             textures: DrawObjectList::new(),
             luascripts: DrawObjectList::new(),
+            images: Vec::new(),
         }
     }
 
@@ -65,13 +102,15 @@ impl<'a> Events<'a> {
     }
 
     pub fn use_copyshader(&mut self, idx: DrawObjectIndex<CopyShader>) -> GLResult<&'a CopyShader> {
-        //self.eventlist.push(DrawEvent::UseCopyShader(idx.clone()));
-        self.copyshaders.maybe_get_object(idx)
+        let object = try!(self.copyshaders.maybe_get_object(idx.clone()));
+        self.push_event(DrawEvent::UseCopyShader(idx));
+        Ok(object)
     }
 
     pub fn use_animshader(&mut self, idx: DrawObjectIndex<CopyShader>) -> GLResult<&'a CopyShader> {
-        //self.eventlist.push(DrawEvent::UseAnimShader(idx.clone()));
-        self.copyshaders.maybe_get_object(idx)
+        let object = try!(self.copyshaders.maybe_get_object(idx.clone()));
+        self.push_event(DrawEvent::UseAnimShader(idx));
+        Ok(object)
     }
 
     pub fn load_pointshader(&mut self, vert: Option<MString>, frag: Option<MString>) -> GLResult<DrawObjectIndex<PointShader>> {
@@ -79,8 +118,9 @@ impl<'a> Events<'a> {
         self.pointshaders.push_object(initargs)
     }
     pub fn use_pointshader(&mut self, idx: DrawObjectIndex<PointShader>) -> GLResult<&'a PointShader> {
-        //self.eventlist.push(DrawEvent::UsePointShader(idx.clone()));
-        self.pointshaders.maybe_get_object(idx)
+        let object = try!(self.pointshaders.maybe_get_object(idx.clone()));
+        self.push_event(DrawEvent::UsePointShader(idx));
+        Ok(object)
     }
     pub fn load_brush(&mut self, w: i32, h: i32, pixels: &[u8], format: PixelFormat) -> DrawObjectIndex<BrushTexture> {
         let ownedpixels = pixels.to_owned();
@@ -88,8 +128,9 @@ impl<'a> Events<'a> {
         self.textures.safe_push_object(init)
     }
     pub fn use_brush(&mut self, idx: DrawObjectIndex<BrushTexture>) -> GLResult<&'a BrushTexture> {
-        //self.eventlist.push(DrawEvent::UseBrush(idx.clone()));
-        self.textures.maybe_get_object(idx)
+        let object = try!(self.textures.maybe_get_object(idx.clone()));
+        self.push_event(DrawEvent::UseBrush(idx));
+        Ok(object)
     }
     pub fn load_interpolator(&mut self, script: Option<MString>) -> GLResult<DrawObjectIndex<LuaScript>> {
         let initopt: LuaUnfilledValues = script;
@@ -97,21 +138,100 @@ impl<'a> Events<'a> {
     }
 
     pub fn use_interpolator(&mut self, idx: DrawObjectIndex<LuaScript>) -> GLResult<&'a LuaScript> {
-        //self.eventlist.push(DrawEvent::UseInterpolator(idx.clone()));
-        self.luascripts.maybe_get_object(idx)
+        let object = try!(self.luascripts.maybe_get_object(idx.clone()));
+        self.push_event(DrawEvent::UseInterpolator(idx));
+        Ok(object)
     }
 
     pub fn add_layer(&mut self, dimensions: (i32, i32)
                      , copyshader: Option<DrawObjectIndex<CopyShader>>, pointshader: Option<DrawObjectIndex<PointShader>>
-                     , pointidx: i32) -> PaintLayer<'a> {
-        //self.eventlist.push(DrawEvent::AddLayer(copyshader.clone(), pointshader.clone(), pointidx));
-        let copyshader = match copyshader { Some(x) => Some(self.copyshaders.get_object(x)), None => None };
-        let pointshader = match pointshader { Some(x) => Some(self.pointshaders.get_object(x)), None => None };
-        PaintLayer::new(dimensions, copyshader, pointshader, pointidx)
+                     , pointidx: i32) -> GLResult<PaintLayer<'a>> {
+        let copyshader_obj = match copyshader { Some(ref x) => Some(try!(self.copyshaders.maybe_get_object(x.clone()))), None => None };
+        let pointshader_obj = match pointshader { Some(ref x) => Some(try!(self.pointshaders.maybe_get_object(x.clone()))), None => None };
+        self.push_event(DrawEvent::AddLayer(copyshader, pointshader, pointidx));
+        Ok(PaintLayer::new(dimensions, copyshader_obj, pointshader_obj, pointidx))
     }
 
     pub fn clear_layers(&mut self) {
-        //self.eventlist.push(DrawEvent::ClearLayers);
+        self.push_event(DrawEvent::ClearLayers);
+    }
+
+    pub fn use_layer_style(&mut self, layer: i32, style: LayerStyle) {
+        self.push_event(DrawEvent::LayerStyle(layer, style));
+    }
+
+    /// Only for layer changes requested from outside, and only once they've succeeded; lua's are
+    /// reproduced by running the interpolator again.
+    pub fn remove_layer(&mut self, layer: i32) {
+        self.push_event(DrawEvent::RemoveLayer(layer));
+    }
+
+    pub fn move_layer(&mut self, from: i32, to: i32) {
+        self.push_event(DrawEvent::MoveLayer(from, to));
+    }
+
+    pub fn merge_layer(&mut self, layer: i32) {
+        self.push_event(DrawEvent::MergeLayer(layer));
+    }
+
+    pub fn duplicate_layer(&mut self, layer: i32) {
+        self.push_event(DrawEvent::DuplicateLayer(layer));
+    }
+
+    /// Keeps a copy of the pixels, which usually belong to a bitmap that's only locked for the
+    /// duration of the call.  Nothing is copied while replaying.
+    pub fn draw_layer_image(&mut self, layer: i32, dimensions: (i32, i32), pixels: &[u8], placement: Affine) {
+        if self.recording.get() {
+            let image = self.push_image(dimensions, pixels);
+            self.push_event(DrawEvent::LayerImage(layer, image, placement));
+        }
+    }
+
+    /// Like draw_layer_image, for an image fitted onto the canvas.
+    pub fn draw_image(&mut self, dimensions: (i32, i32), pixels: &[u8], rotation: Rotation) {
+        if self.recording.get() {
+            let image = self.push_image(dimensions, pixels);
+            self.push_event(DrawEvent::Image(image, rotation));
+        }
+    }
+
+    fn push_image(&mut self, dimensions: (i32, i32), pixels: &[u8]) -> i32 {
+        self.images.push((dimensions, pixels.to_owned()));
+        self.images.len() as i32 - 1
+    }
+
+    pub fn use_brush_color(&mut self, color: i32) {
+        self.push_event(DrawEvent::BrushColor(color));
+    }
+
+    pub fn use_brush_size(&mut self, size: f32) {
+        self.push_event(DrawEvent::BrushSize(size));
+    }
+
+    pub fn use_eraser_mode(&mut self, mode: EraserMode) {
+        self.push_event(DrawEvent::EraserMode(mode));
+    }
+
+    pub fn use_stroke_filter(&mut self, filter: StrokeFilter) {
+        self.push_event(DrawEvent::StrokeFilter(filter));
+    }
+
+    pub fn use_reframe(&mut self, reframe: Reframe) {
+        self.push_event(DrawEvent::Reframe(reframe));
+    }
+
+    /// Only for undo operations requested from outside; lua's saveundo() is reproduced by
+    /// running the interpolator again.
+    pub fn push_undo(&mut self) {
+        self.push_event(DrawEvent::PushUndo);
+    }
+
+    pub fn load_undo(&mut self, idx: i32) {
+        self.push_event(DrawEvent::LoadUndo(idx));
+    }
+
+    pub fn clear_undo(&mut self) {
+        self.push_event(DrawEvent::ClearUndo);
     }
 
     pub fn get_pointshader_source(&mut self, pointshader: DrawObjectIndex<PointShader>) -> &(MString, MString) {
@@ -126,87 +246,626 @@ impl<'a> Events<'a> {
         self.luascripts.get_object(luascript).get_source()
     }
 
-    #[allow(unused_variables)]
     pub fn pushpoint(&mut self, event: PointEntry) {
-        //self.eventlist.push(DrawEvent::Point(event));
+        self.push_event(DrawEvent::Point(event));
     }
     pub fn pushframe(&mut self) {
-        //self.eventlist.push(DrawEvent::Frame);
+        self.push_event(DrawEvent::Frame);
     }
+    /// Clearing the canvas is recorded like everything else; the log itself is never truncated.
     pub fn clear(&mut self) {
-        //self.eventlist.clear();
+        self.push_event(DrawEvent::Clear);
+    }
+    /// The size the canvas was set up at, before any reframes.
+    pub fn set_canvas(&mut self, canvas: (i32, i32)) {
+        self.canvas = Some(canvas);
+    }
+    pub fn canvas(&self) -> Option<(i32, i32)> {
+        self.canvas
+    }
+    /// Replay goes through the same paths as live drawing, and mustn't append to the log it's reading.
+    pub fn set_recording(&mut self, recording: bool) {
+        self.recording.set(recording);
+    }
+    #[inline]
+    fn push_event(&mut self, event: DrawEvent) {
+        if self.recording.get() {
+            self.eventlist.push(event);
+        }
+    }
+    /// Copies out the log and the sources of everything it refers to, for saving.
+    pub fn to_session(&self) -> SessionData {
+        SessionData {
+            canvas: self.canvas,
+            pointshaders: self.pointshaders.objects().iter().map(|x| x.get_source().clone()).collect(),
+            copyshaders: self.copyshaders.objects().iter().map(|x| x.get_source().clone()).collect(),
+            textures: self.textures.objects().iter().map(|x| {
+                let (format, dimensions, ref pixels) = *x.get_source();
+                (format, dimensions, pixels.clone())
+            }).collect(),
+            luascripts: self.luascripts.objects().iter().map(|x| x.get_source().clone()).collect(),
+            images: self.images.clone(),
+            events: self.eventlist.clone(),
+        }
+    }
+
+    /// Replaces the log with a saved session's.  The session's objects are added after the ones
+    /// already loaded, and the loaded log's indices shifted to match, so indices handed out
+    /// before the load keep referring to the same things.
+    pub fn load_session(&mut self, session: SessionData) -> GLResult<()> {
+        let SessionData { canvas, pointshaders, copyshaders, textures, luascripts, images, events } = session;
+        for event in events.iter() {
+            try!(event.check_indices(pointshaders.len(), copyshaders.len(), textures.len(), luascripts.len(), images.len()));
+        }
+        let mut events = events;
+        for event in events.iter_mut() {
+            event.offset_indices(self.pointshaders.len() as i32, self.copyshaders.len() as i32,
+                                 self.textures.len() as i32, self.luascripts.len() as i32, self.images.len() as i32);
+        }
+        for (vert, frag) in pointshaders.into_iter() {
+            try!(self.pointshaders.push_object_uncached((Some(vert), Some(frag))));
+        }
+        for (vert, frag) in copyshaders.into_iter() {
+            try!(self.copyshaders.push_object_uncached((Some(vert), Some(frag))));
+        }
+        for brush in textures.into_iter() {
+            try!(self.textures.push_object_uncached(brush));
+        }
+        for script in luascripts.into_iter() {
+            try!(self.luascripts.push_object_uncached(Some(script)));
+        }
+        self.images.extend(images.into_iter());
+        self.eventlist = events;
+        // older sessions don't say, and are assumed to start at the current size
+        if let Some(canvas) = canvas {
+            self.canvas = Some(canvas);
+        }
+        Ok(())
+    }
+
+    pub fn get_event(&self, idx: usize) -> Option<&DrawEvent> {
+        self.eventlist.as_slice().get(idx)
+    }
+    pub fn len(&self) -> usize {
+        self.eventlist.len()
     }
-    //fn get_event(&self, idx: usize) -> Option<&DrawEvent> {
-        //self.eventlist.as_slice().get(idx)
-    //}
 }
 
-#[inline]
-#[allow(unused)]
-pub fn handle_event<'a>(gl: &mut ::glinit::GLInit<'a>, events: &mut Events<'a>, queue: &mut ::point::PointProducer, eventidx: i32) -> event_stream::EventState {
+fn check_index<T>(idx: &DrawObjectIndex<T>, len: usize, kind: &str) -> GLResult<()> {
+    let idx = idx.to_i32();
+    if idx < 0 || idx as usize >= len {
+        Err(format!("session refers to {} {} of {}", kind, idx, len).into_cow())
+    } else {
+        Ok(())
+    }
+}
 
-    // FIXME do this without exposing Events or GLInit internal details
-    /*
-    match events.get_event(eventidx as usize) {
-        Some(event) => match event.clone() {
-            DrawEvent::UseAnimShader(idx) => gl.set_anim_shader(events.copyshaders.get_object(idx)),
-            DrawEvent::UseCopyShader(idx) => gl.set_copy_shader(events.copyshaders.get_object(idx)),
-            DrawEvent::UsePointShader(idx) => gl.set_point_shader(events.pointshaders.get_object(idx)),
-            DrawEvent::UseBrush(idx) => gl.set_brush_texture(&events.textures.get_object(idx).texture),
-            DrawEvent::UseInterpolator(idx) => gl.set_interpolator(events.luascripts.get_object(idx)),
-            DrawEvent::Point(p) => queue.send(p),
-            DrawEvent::AddLayer(copyshader, pointshader, pointidx) => {
-                let copyshader = match copyshader { Some(x) => Some(events.copyshaders.get_object(x)), None => None };
-                let pointshader = match pointshader { Some(x) => Some(events.pointshaders.get_object(x)), None => None };
-                let layer = PaintLayer::new(gl.dimensions, copyshader, pointshader, pointidx);
-                gl.add_layer(layer);
+fn offset_index<T>(idx: &mut DrawObjectIndex<T>, by: i32) {
+    *idx = DrawObjectIndex::from_i32(idx.to_i32() + by);
+}
+
+impl DrawEvent {
+    /// Shifts the indices in this event by the number of each kind of object loaded before its log.
+    fn offset_indices(&mut self, pointshaders: i32, copyshaders: i32, textures: i32, luascripts: i32, images: i32) {
+        match *self {
+            DrawEvent::UseAnimShader(ref mut idx) => offset_index(idx, copyshaders),
+            DrawEvent::UseCopyShader(ref mut idx) => offset_index(idx, copyshaders),
+            DrawEvent::UsePointShader(ref mut idx) => offset_index(idx, pointshaders),
+            DrawEvent::UseBrush(ref mut idx) => offset_index(idx, textures),
+            DrawEvent::UseInterpolator(ref mut idx) => offset_index(idx, luascripts),
+            DrawEvent::AddLayer(ref mut copyshader, ref mut pointshader, _) => {
+                if let Some(ref mut idx) = *copyshader {
+                    offset_index(idx, copyshaders);
+                }
+                if let Some(ref mut idx) = *pointshader {
+                    offset_index(idx, pointshaders);
+                }
             },
-            DrawEvent::ClearLayers => gl.clear_layers(),
-            DrawEvent::Frame => return EventState::Frame,
-        },
+            DrawEvent::LayerImage(_, ref mut image, _) | DrawEvent::Image(ref mut image, _) => *image += images,
+            _ => {},
+        }
+    }
+
+    fn check_indices(&self, pointshaders: usize, copyshaders: usize, textures: usize, luascripts: usize, images: usize) -> GLResult<()> {
+        match *self {
+            DrawEvent::UseAnimShader(ref idx) => check_index(idx, copyshaders, "copy shader"),
+            DrawEvent::UseCopyShader(ref idx) => check_index(idx, copyshaders, "copy shader"),
+            DrawEvent::UsePointShader(ref idx) => check_index(idx, pointshaders, "point shader"),
+            DrawEvent::UseBrush(ref idx) => check_index(idx, textures, "brush"),
+            DrawEvent::UseInterpolator(ref idx) => check_index(idx, luascripts, "interpolator"),
+            DrawEvent::AddLayer(ref copyshader, ref pointshader, _) => {
+                if let Some(ref idx) = *copyshader {
+                    try!(check_index(idx, copyshaders, "copy shader"));
+                }
+                if let Some(ref idx) = *pointshader {
+                    try!(check_index(idx, pointshaders, "point shader"));
+                }
+                Ok(())
+            },
+            DrawEvent::LayerImage(_, image, _) | DrawEvent::Image(image, _) => {
+                if image < 0 || image as usize >= images {
+                    Err(format!("session refers to image {} of {}", image, images).into_cow())
+                } else {
+                    Ok(())
+                }
+            },
+            _ => Ok(()),
+        }
+    }
+
+    /// Whether this event only changes what's selected or how the layers are set up, rather than
+    /// just touching the canvas.  These are what has to be replayed to restore a keyframe's paint
+    /// state; the pixels come from the keyframe afterwards.
+    pub fn is_state_event(&self) -> bool {
+        match *self {
+            DrawEvent::UseAnimShader(_) | DrawEvent::UseCopyShader(_) | DrawEvent::UsePointShader(_)
+                | DrawEvent::UseBrush(_) | DrawEvent::UseInterpolator(_)
+                | DrawEvent::BrushColor(_) | DrawEvent::BrushSize(_) | DrawEvent::EraserMode(_)
+                | DrawEvent::StrokeFilter(_)
+                | DrawEvent::AddLayer(_, _, _) | DrawEvent::ClearLayers | DrawEvent::LayerStyle(_, _)
+                | DrawEvent::RemoveLayer(_) | DrawEvent::MoveLayer(_, _)
+                | DrawEvent::MergeLayer(_) | DrawEvent::DuplicateLayer(_)
+                | DrawEvent::Reframe(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Whether points queued before this event have to be drawn before it can be applied.
+    /// Points are only recorded as they're drawn, so everything else happened between draws.
+    pub fn needs_draw(&self) -> bool {
+        match *self {
+            DrawEvent::Point(_) | DrawEvent::Frame => false,
+            _ => true,
+        }
+    }
+}
+
+/// Only succeeded events are recorded, so one failing on replay means it's gone out of step.
+fn log_replay_error(result: GLResult<()>) {
+    if let Err(msg) = result {
+        logi!("replayed event failed: {}", msg.as_slice());
+    }
+}
+
+#[inline]
+pub fn handle_event<'a>(gl: &mut ::glinit::GLInit<'a>, events: &mut Events<'a>, queue: &mut ::point::PointProducer, eventidx: i32) -> event_stream::EventState {
+    let event = match events.get_event(eventidx as usize) {
+        Some(event) => event.clone(),
         None => return EventState::Done,
+    };
+    match event {
+        DrawEvent::UseAnimShader(idx) => gl.set_anim_shader(events.copyshaders.get_object(idx)),
+        DrawEvent::UseCopyShader(idx) => gl.set_copy_shader(events.copyshaders.get_object(idx)),
+        DrawEvent::UsePointShader(idx) => gl.set_point_shader(events.pointshaders.get_object(idx)),
+        DrawEvent::UseBrush(idx) => gl.set_brush_texture(&events.textures.get_object(idx).texture),
+        DrawEvent::UseInterpolator(idx) => gl.set_interpolator(events.luascripts.get_object(idx)),
+        DrawEvent::BrushColor(color) => gl.set_brush_color(color),
+        DrawEvent::BrushSize(size) => gl.set_brush_size(size),
+        DrawEvent::EraserMode(mode) => gl.set_eraser_mode(mode),
+        DrawEvent::StrokeFilter(filter) => gl.set_stroke_filter(filter),
+        DrawEvent::Point(p) => { let _ = queue.send(p); },
+        DrawEvent::AddLayer(copyshader, pointshader, pointidx) => {
+            let copyshader = match copyshader { Some(x) => Some(events.copyshaders.get_object(x)), None => None };
+            let pointshader = match pointshader { Some(x) => Some(events.pointshaders.get_object(x)), None => None };
+            let layer = PaintLayer::new(gl.dimensions, copyshader, pointshader, pointidx);
+            gl.add_layer(layer);
+        },
+        DrawEvent::ClearLayers => gl.clear_layers(),
+        DrawEvent::LayerStyle(layer, style) => log_replay_error(gl.set_layer_style(layer, style)),
+        DrawEvent::RemoveLayer(layer) => log_replay_error(gl.remove_layer(layer)),
+        DrawEvent::MoveLayer(from, to) => log_replay_error(gl.move_layer(from, to)),
+        DrawEvent::MergeLayer(layer) => log_replay_error(gl.merge_layer_down(layer)),
+        DrawEvent::DuplicateLayer(layer) => log_replay_error(gl.duplicate_layer(layer)),
+        DrawEvent::LayerImage(layer, image, placement) => {
+            let ((w, h), ref pixels) = events.images[image as usize];
+            log_replay_error(gl.draw_layer_image(layer, w, h, pixels.as_slice(), placement));
+        },
+        DrawEvent::Image(image, rotation) => {
+            let ((w, h), ref pixels) = events.images[image as usize];
+            gl.draw_image(w, h, pixels.as_slice(), rotation);
+        },
+        DrawEvent::PushUndo => { gl.push_undo_frame(); },
+        DrawEvent::LoadUndo(idx) => gl.load_undo_frame(idx),
+        DrawEvent::ClearUndo => gl.clear_undo_frames(),
+        DrawEvent::Clear => gl.clear_buffer(),
+        DrawEvent::Frame => return EventState::Frame,
+        DrawEvent::Reframe(reframe) => gl.reframe_canvas(reframe),
     }
-    */
-    return EventState::NoFrame;
+    EventState::NoFrame
 }
 
 pub mod event_stream {
     use core::prelude::*;
-    use drawevent::{Events, handle_event};
+    use collections::vec::Vec;
+    use drawevent::{Events, DrawEvent, handle_event};
+    use core::cell::Cell;
+    use alloc::rc::Rc;
+    use point::{PointEntry, PointInfo};
+    use paintlayer::TextureTarget;
+    use glcommon::GLResult;
+
+    /// events between keyframes, to begin with
+    const KEYFRAME_INTERVAL: i32 = 500;
+    /// once there are this many keyframes, every other one is dropped and the interval doubles
+    const MAX_KEYFRAMES: usize = 8;
 
     #[derive(Copy)]
     pub enum EventState {
         Done,
         Frame,
         NoFrame,
+        /// queued points must be drawn before replay can continue
+        Draw,
+    }
+
+    /// How far each call to advance_frame gets through the log.
+    #[derive(Copy, Debug, PartialEq)]
+    pub enum ReplaySpeed {
+        /// Stop at each recorded Frame event.  Reproduces the original exactly, including
+        /// anim shaders, but at whatever rate frames are requested.
+        Frames,
+        /// Follow point timestamps, at the given multiple of real time.  Timed(1.0) is real time.
+        /// Point times are compressed or stretched by the same factor, so time-based effects run
+        /// at the speed they're seen at.  Frame events are ignored.
+        Timed(f32),
+        /// Run to the end in one call, keeping the recorded point times.
+        Instant,
+    }
+
+    /// A copy of the canvas and its layers, taken while no pointers were down.
+    /// Undo buffers and interpolator state aren't saved, so a keyframe is only used when nothing
+    /// after it loads an undo frame from before it; see undo_independent.
+    struct Keyframe {
+        position: i32,
+        targets: Vec<TextureTarget>,
     }
 
+    /// Whether replaying from position on can do without the undo history built up before it.  A
+    /// LoadUndo before the next ClearUndo could reach back into frames a keyframe doesn't keep.
+    pub fn undo_independent(events: &Events, position: i32, end: i32) -> bool {
+        for idx in range(position, end) {
+            match events.get_event(idx as usize) {
+                Some(&DrawEvent::LoadUndo(_)) => return false,
+                Some(&DrawEvent::ClearUndo) => return true,
+                _ => {},
+            }
+        }
+        true
+    }
 
     pub struct EventStream {
         position: i32,
+        keyframes: Vec<Keyframe>,
+        keyframe_interval: i32,
+        seek_target: Option<i32>,
+        end: i32,
+        pending_points: bool,
+        speed: ReplaySpeed,
+        /// wall-clock milliseconds at the previous call to advance_frame
+        last_wall: Option<i64>,
+        /// how far through the recorded point timeline replay has got
+        clock: Option<f32>,
+        /// recorded and rescaled time at the last speed change, for mapping point times
+        anchor: (f32, f32),
         pub consumer: ::glpoint::MotionEventConsumer,
         producer: ::glpoint::MotionEventProducer,
+        /// the log's recording flag, turned back on if the replay is dropped before it finishes
+        recording: Rc<Cell<bool>>,
     }
 
     impl EventStream {
 
-        pub fn new() -> EventStream {
-            let (consumer, producer) = ::glpoint::create_motion_event_handler(0);
+        /// Replays the events recorded so far.  Recording is paused until the replay finishes or
+        /// is dropped.
+        pub fn new(events: &mut Events) -> EventStream {
+            let (consumer, producer) = ::glpoint::create_replay_handler();
+            events.set_recording(false);
             EventStream {
                 position: 0,
+                keyframes: Vec::new(),
+                keyframe_interval: KEYFRAME_INTERVAL,
+                seek_target: None,
+                end: events.len() as i32,
+                pending_points: false,
+                speed: ReplaySpeed::Frames,
+                last_wall: None,
+                clock: None,
+                anchor: (0f32, 0f32),
                 producer: producer,
-                consumer: consumer
+                consumer: consumer,
+                recording: events.recording.clone(),
+            }
+        }
+
+        pub fn set_speed(&mut self, speed: ReplaySpeed) {
+            if let Some(clock) = self.clock {
+                self.anchor = (clock, self.rescale(clock));
+            }
+            self.speed = speed;
+        }
+
+        /// Maps a recorded point time onto the replay's timeline.
+        fn rescale(&self, time: f32) -> f32 {
+            let (recorded, rescaled) = self.anchor;
+            match self.speed {
+                ReplaySpeed::Timed(factor) if factor > 0f32 => rescaled + (time - recorded) / factor,
+                _ => rescaled + (time - recorded),
+            }
+        }
+
+        /// Moves the replay clock forward by however much wall-clock time has passed.
+        fn tick(&mut self, now: i64) {
+            let elapsed = match self.last_wall {
+                Some(last) => (now - last) as f32 / 1000f32,
+                None => 0f32,
+            };
+            self.last_wall = Some(now);
+            if let (ReplaySpeed::Timed(factor), Some(clock)) = (self.speed, self.clock) {
+                self.clock = Some(clock + elapsed * factor);
+            }
+        }
+
+        /// Whether a point is due yet, starting the replay clock if this is the first point.
+        fn point_due(&mut self, point: &PointEntry) -> bool {
+            let time = match point.entry {
+                PointInfo::Point(p) => p.time,
+                _ => return true,
+            };
+            match self.clock {
+                None => {
+                    self.clock = Some(time);
+                    self.anchor = (time, time);
+                    true
+                },
+                Some(clock) if self.seek_target.is_some() => {
+                    // keep the clock up with points skipped over while seeking
+                    if time > clock {
+                        self.clock = Some(time);
+                    }
+                    true
+                },
+                Some(clock) => match self.speed {
+                    ReplaySpeed::Timed(_) => time <= clock,
+                    _ => true,
+                },
+            }
+        }
+
+        fn send_point(&mut self, point: PointEntry) {
+            let mut point = point;
+            if let PointInfo::Point(ref mut p) = point.entry {
+                p.time = self.rescale(p.time);
+            }
+            let _ = self.producer.producer.send(point);
+            self.pending_points = true;
+        }
+
+        pub fn len(&self) -> i32 {
+            self.end
+        }
+
+        pub fn position(&self) -> i32 {
+            self.position
+        }
+
+        fn maybe_take_keyframe(&mut self, init: &::glinit::GLInit) {
+            let last = match self.keyframes.last() {
+                Some(keyframe) => keyframe.position,
+                None => 0,
+            };
+            if self.position < last + self.keyframe_interval || self.pending_points || self.consumer.active_pointers() != 0 {
+                return;
+            }
+            debug_logi!("taking replay keyframe at event {}", self.position);
+            let keyframe = Keyframe { position: self.position, targets: init.snapshot_targets() };
+            self.add_keyframe(keyframe);
+        }
+
+        fn add_keyframe(&mut self, keyframe: Keyframe) {
+            self.keyframes.push(keyframe);
+            if self.keyframes.len() >= MAX_KEYFRAMES {
+                let old = ::core::mem::replace(&mut self.keyframes, Vec::new());
+                self.keyframes = old.into_iter().enumerate().filter(|&(i, _)| i % 2 == 1).map(|(_, k)| k).collect();
+                self.keyframe_interval *= 2;
+            }
+        }
+
+        /// The keyframe seeking to target can start from, or None to replay from the start.
+        fn keyframe_before(&self, events: &Events, target: i32) -> Option<usize> {
+            self.keyframes.iter().rev().position(|k| k.position <= target)
+                .map(|i| self.keyframes.len() - 1 - i)
+                .and_then(|i| if undo_independent(events, self.keyframes[i].position, self.end) { Some(i) } else { None })
+        }
+
+        /// Restores the nearest keyframe at or before `target` and replays from there.  Actually
+        /// reaching `target` is left to advance_frame, which runs through without pausing until
+        /// it gets there.
+        pub fn seek<'a>(&mut self, init: &mut ::glinit::GLInit<'a>, events: &mut Events<'a>, target: i32) -> GLResult<()> {
+            let target = if target < 0 { 0 } else if target > self.end { self.end } else { target };
+            let keyframe = self.keyframe_before(events, target);
+            let start = match keyframe { Some(i) => self.keyframes[i].position, None => 0 };
+            if target >= self.position && start <= self.position {
+                // no keyframe would get us there faster than carrying on
+                self.seek_target = Some(target);
+                return Ok(());
+            }
+
+            events.set_recording(false);
+            init.reset_paintstate(events.canvas());
+            for idx in range(0, start) {
+                let is_state = events.get_event(idx as usize).map_or(false, |e| e.is_state_event());
+                if is_state {
+                    handle_event(init, events, &mut self.producer.producer, idx);
+                }
+            }
+            let mut start = start;
+            if let Some(i) = keyframe {
+                if let Err(msg) = init.restore_targets(self.keyframes[i].targets.as_slice()) {
+                    // layers changed by lua or by loading undo aren't in the state events, so
+                    // the keyframe can't be trusted; go back to the start instead
+                    logi!("replaying from the start: {}", msg.as_slice());
+                    self.keyframes.truncate(i);
+                    init.reset_paintstate(events.canvas());
+                    start = 0;
+                }
             }
+
+            let (consumer, producer) = ::glpoint::create_replay_handler();
+            self.consumer = consumer;
+            self.producer = producer;
+            self.position = start;
+            self.pending_points = false;
+            self.clock = None;
+            self.seek_target = Some(target);
+            Ok(())
         }
 
-        pub fn advance_frame<'a>(&mut self, init: &mut ::glinit::GLInit<'a>, events: &mut Events<'a>) -> bool {
+        fn finish(&mut self, events: &mut Events) -> EventState {
+            self.seek_target = None;
+            events.set_recording(true);
+            EventState::Done
+        }
+
+        /// Applies events up to the next stopping point for the current speed; `now` is the
+        /// wall-clock time in milliseconds.  The caller is expected to draw queued points after
+        /// each call, and to call again while this returns Draw.
+        pub fn advance_frame<'a>(&mut self, init: &mut ::glinit::GLInit<'a>, events: &mut Events<'a>, now: i64) -> EventState {
+            self.tick(now);
             loop {
-                match handle_event(init, events, &mut self.producer.producer, self.position) {
-                    EventState::Done => return true,
-                    EventState::Frame => return false,
-                    EventState::NoFrame => { },
+                if let Some(target) = self.seek_target {
+                    if self.position >= target {
+                        self.seek_target = None;
+                        self.pending_points = false;
+                        return EventState::Frame;
+                    }
+                }
+                if self.position >= self.end {
+                    return self.finish(events);
+                }
+                self.maybe_take_keyframe(init);
+                let event = match events.get_event(self.position as usize) {
+                    Some(event) => event.clone(),
+                    None => return self.finish(events),
+                };
+                if event.needs_draw() && self.pending_points {
+                    self.pending_points = false;
+                    return EventState::Draw;
+                }
+                match (event, self.speed) {
+                    (DrawEvent::Point(point), _) => {
+                        if !self.point_due(&point) {
+                            self.pending_points = false;
+                            return EventState::Frame;
+                        }
+                        self.send_point(point);
+                        self.position += 1;
+                    },
+                    (DrawEvent::Frame, ReplaySpeed::Frames) if self.seek_target.is_none() => {
+                        self.position += 1;
+                        self.pending_points = false;
+                        return EventState::Frame;
+                    },
+                    (DrawEvent::Frame, _) => {
+                        self.position += 1;
+                    },
+                    _ => {
+                        if let EventState::Done = handle_event(init, events, &mut self.producer.producer, self.position) {
+                            return self.finish(events);
+                        }
+                        self.position += 1;
+                    },
                 }
             }
         }
     }
+
+    impl Drop for EventStream {
+        fn drop(&mut self) {
+            // a replay stopped partway through mustn't leave the log deaf to everything after
+            self.recording.set(true);
+        }
+    }
+
+    #[test]
+    fn seeks_start_from_the_nearest_usable_keyframe() {
+        let mut events = Events::new();
+        for _ in range(0, 6) { events.pushframe(); }
+        events.push_undo();
+        events.load_undo(0);
+        events.clear_undo();
+        for _ in range(0, 11) { events.pushframe(); }
+        let mut stream = EventStream::new(&mut events);
+        for &position in [4, 10, 14].iter() {
+            stream.add_keyframe(Keyframe { position: position, targets: Vec::new() });
+        }
+        // anything a keyframe can't stand in for has to be replayed from the start
+        assert_eq!(stream.keyframe_before(&events, 3), None);
+        assert_eq!(stream.keyframe_before(&events, 5), None);
+        assert_eq!(stream.keyframe_before(&events, 10), Some(1));
+        assert_eq!(stream.keyframe_before(&events, 13), Some(1));
+        assert_eq!(stream.keyframe_before(&events, 20), Some(2));
+    }
+
+    #[test]
+    fn keyframes_thin_out_as_replay_goes_on() {
+        let mut events = Events::new();
+        let mut stream = EventStream::new(&mut events);
+        for i in range(1, MAX_KEYFRAMES as i32 + 1) {
+            stream.add_keyframe(Keyframe { position: i * KEYFRAME_INTERVAL, targets: Vec::new() });
+        }
+        assert_eq!(stream.keyframe_interval, KEYFRAME_INTERVAL * 2);
+        let positions = stream.keyframes.iter().map(|k| k.position).collect::<Vec<i32>>();
+        assert_eq!(positions, range(1, MAX_KEYFRAMES as i32 / 2 + 1).map(|i| i * KEYFRAME_INTERVAL * 2).collect::<Vec<i32>>());
+        assert_eq!(stream.keyframe_before(&events, KEYFRAME_INTERVAL * 5), Some(1));
+    }
+}
+
+#[test]
+fn dropped_replays_resume_recording() {
+    let mut events = Events::new();
+    events.pushframe();
+    events.pushframe();
+    {
+        let _replay = event_stream::EventStream::new(&mut events);
+        events.pushframe();
+        assert_eq!(events.len(), 2);
+    }
+    events.pushframe();
+    assert_eq!(events.len(), 3);
+}
+
+#[test]
+fn keyframes_before_undo_loads_are_skipped() {
+    let mut events = Events::new();
+    events.pushframe();
+    events.push_undo();
+    events.pushframe();
+    events.load_undo(0);
+    events.pushframe();
+    events.clear_undo();
+    events.load_undo(0);
+    let end = events.len() as i32;
+    assert!(!event_stream::undo_independent(&events, 0, end));
+    assert!(!event_stream::undo_independent(&events, 2, end));
+    assert!(event_stream::undo_independent(&events, 4, end));
+    assert!(event_stream::undo_independent(&events, end, end));
+}
+
+#[test]
+fn loaded_sessions_keep_earlier_indices() {
+    let mut events = Events::new();
+    events.draw_image((1, 1), &[0u8; 4], Rotation::Rotation0);
+    let mut images = Vec::new();
+    images.push(((1, 1), (&[255u8; 4]).to_vec()));
+    let mut log = Vec::new();
+    log.push(DrawEvent::Image(0, Rotation::Rotation90));
+    events.load_session(SessionData {
+        canvas: None, pointshaders: Vec::new(), copyshaders: Vec::new(), textures: Vec::new(),
+        luascripts: Vec::new(), images: images, events: log,
+    }).unwrap();
+    assert_eq!(events.len(), 1);
+    match events.get_event(0) {
+        Some(&DrawEvent::Image(1, Rotation::Rotation90)) => {},
+        _ => panic!("loaded image index wasn't shifted past the existing image"),
+    }
 }
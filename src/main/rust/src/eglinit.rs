@@ -14,6 +14,18 @@ static DEFAULT_EGL_CONFIG: [u32; 15] = [
     EGL_NONE
 ];
 
+static PBUFFER_EGL_CONFIG: [u32; 17] = [
+    EGL_SURFACE_TYPE, EGL_PBUFFER_BIT,
+    EGL_RENDERABLE_TYPE, EGL_OPENGL_ES2_BIT,
+    EGL_RED_SIZE, 8,
+    EGL_GREEN_SIZE, 8,
+    EGL_BLUE_SIZE, 8,
+    EGL_ALPHA_SIZE, 8,
+    EGL_DEPTH_SIZE, 0,
+    EGL_STENCIL_SIZE, 0,
+    EGL_NONE
+];
+
 static DEFAULT_CONTEXT_ATTRIBS: [u32; 3] = [
     EGL_CONTEXT_CLIENT_VERSION, 2,
     EGL_NONE
@@ -33,6 +45,10 @@ fn get_config() -> *const i32 {
     unsafe { mem::transmute(DEFAULT_EGL_CONFIG.as_slice().as_ptr()) }
 }
 
+fn get_pbuffer_config() -> *const i32 {
+    unsafe { mem::transmute(PBUFFER_EGL_CONFIG.as_slice().as_ptr()) }
+}
+
 fn get_context_attribs() -> *const i32 {
     unsafe { mem::transmute(DEFAULT_CONTEXT_ATTRIBS.as_slice().as_ptr()) }
 }
@@ -41,12 +57,11 @@ fn get_no_attribs() -> *const i32 {
     unsafe { mem::transmute(NO_ATTRIBS.as_slice().as_ptr()) }
 }
 
-fn choose_egl_config(display: EGLDisplay) -> Option<EGLConfig> {
+fn choose_egl_config(display: EGLDisplay, configattrs: *const i32) -> Option<EGLConfig> {
     let mut config_count = 0;
     let mut config: EGLConfig = 0 as EGLConfig;
-    let configattrs = get_config();
     debug_logi!("choosing config... attrs = {}", configattrs as usize);
-    if EGL_TRUE != ChooseConfig(display, get_config(), &mut config, 1, &mut config_count) {
+    if EGL_TRUE != ChooseConfig(display, configattrs, &mut config, 1, &mut config_count) {
         loge!("eglChooseConfig returned false :(");
         None
     } else if config_count == 0 {
@@ -73,7 +88,17 @@ pub fn egl_init(window: *mut c_void) -> () {
     unsafe { data = init_context(window) };
 }
 
-fn init_context(surface_texture: *mut c_void) -> Option<EGLStatus> {
+/// Sets up an offscreen context backed by a w*h pbuffer, for rendering without a window.
+/// Returns false if no suitable config or surface could be created.
+#[no_mangle]
+pub fn egl_init_pbuffer(w: i32, h: i32) -> bool {
+    unsafe {
+        data = init_pbuffer_context(w, h);
+        data.is_some()
+    }
+}
+
+fn init_display(configattrs: *const i32) -> Option<(EGLDisplay, EGLConfig)> {
     let displayopt = get_display(EGL_DEFAULT_DISPLAY as EGLNativeDisplayType);
     if displayopt.is_none() {
         loge!("failed to get display :(\n");
@@ -91,16 +116,31 @@ fn init_context(surface_texture: *mut c_void) -> Option<EGLStatus> {
     loge!("extensions: {}\n", QueryString(display, EGL_EXTENSIONS));
     loge!("vendor: {}\n", QueryString(display, EGL_VENDOR));
 
-    let configopt = choose_egl_config(display);
+    let configopt = choose_egl_config(display, configattrs);
     if configopt.is_none() {
         loge!("failed to get config :(");
         return None;
     }
-    let config = configopt.unwrap();
-    //logi!("got config: 0x{:x}", config);
+    Some((display, configopt.unwrap()))
+}
+
+fn make_current(display: EGLDisplay, config: EGLConfig, surface: EGLSurface) -> Option<EGLStatus> {
     debug_logi!("creating context...");
     let context = CreateContext(display, config, EGL_NO_CONTEXT as *mut c_void, get_context_attribs());
     debug_logi!("got context: 0x{:x}", context as usize);
+    if MakeCurrent(display, surface, surface, context) != EGL_TRUE {
+        loge!("eglMakeCurrent failed");
+        return None
+    }
+    debug_logi!("made egl surface current!");
+    Some(EGLStatus { display: display, context: context, surface: surface })
+}
+
+fn init_context(surface_texture: *mut c_void) -> Option<EGLStatus> {
+    let (display, config) = match init_display(get_config()) {
+        Some(x) => x,
+        None => return None,
+    };
     debug_logi!("creating window surface...");
     let surface = CreateWindowSurface(display, config, surface_texture, get_no_attribs());
     if surface == EGL_NO_SURFACE as *mut c_void {
@@ -117,13 +157,23 @@ fn init_context(surface_texture: *mut c_void) -> Option<EGLStatus> {
         return None;
     }
     debug_logi!("got surface: 0x{:x}", surface as usize);
+    make_current(display, config, surface)
+}
 
-    if MakeCurrent(display, surface, surface, context) != EGL_TRUE {
-        loge!("eglMakeCurrent failed");
-        return None
+fn init_pbuffer_context(w: i32, h: i32) -> Option<EGLStatus> {
+    let (display, config) = match init_display(get_pbuffer_config()) {
+        Some(x) => x,
+        None => return None,
+    };
+    let surface_attribs = [EGL_WIDTH as i32, w, EGL_HEIGHT as i32, h, EGL_NONE as i32];
+    debug_logi!("creating {}x{} pbuffer surface...", w, h);
+    let surface = CreatePbufferSurface(display, config, surface_attribs.as_ptr());
+    if surface == EGL_NO_SURFACE as *mut c_void {
+        loge!("createpbuffersurface failed: {}", GetError());
+        return None;
     }
-    debug_logi!("made egl surface current!");
-    Some(EGLStatus { display: display, context: context, surface: surface })
+    debug_logi!("got surface: 0x{:x}", surface as usize);
+    make_current(display, config, surface)
 }
 
 #[no_mangle]
@@ -0,0 +1,282 @@
+/// Multi-touch gesture recognition.
+///
+/// A touch is held back from drawing until it's clear whether it's a stroke or the start of a
+/// gesture: a second finger landing soon after the first, before the first has moved far, makes
+/// it a gesture, and anything else lets the held points through to be drawn.  Once a gesture has
+/// started, nothing more is drawn until every finger is lifted.
+
+use core::prelude::*;
+use core::num::Float;
+use collections::vec_map::VecMap;
+
+use point::{Coordinate, ToolType};
+use matrix::{Affine, wrap_angle};
+use motionevent::{InputEvent, InputAction};
+
+/// A change to the view: points move to scale * rotate(p) + translate.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Transform {
+    pub scale: f32,
+    /// radians, counterclockwise in screen coordinates
+    pub rotation: f32,
+    pub translate: Coordinate,
+}
+
+impl Transform {
+    pub fn identity() -> Transform {
+        Transform { scale: 1f32, rotation: 0f32, translate: Coordinate { x: 0f32, y: 0f32 } }
+    }
+
+    /// Scales and rotates about focus, then moves by pan.
+    pub fn about(focus: Coordinate, pan: Coordinate, scale: f32, rotation: f32) -> Transform {
+        let scaled = Transform { scale: scale, rotation: rotation, translate: Coordinate { x: 0f32, y: 0f32 } };
+        Transform { translate: focus + pan - scaled.apply(focus), .. scaled }
+    }
+
+    pub fn apply(&self, p: Coordinate) -> Coordinate {
+        let (sin, cos) = self.rotation.sin_cos();
+        Coordinate {
+            x: self.scale * (cos * p.x - sin * p.y) + self.translate.x,
+            y: self.scale * (sin * p.x + cos * p.y) + self.translate.y,
+        }
+    }
+
+    pub fn to_affine(&self) -> Affine {
+        Affine::scale(self.scale, self.scale)
+            .then(&Affine::rotate(self.rotation))
+            .then(&Affine::translate(self.translate.x, self.translate.y))
+    }
+
+    /// This transform followed by next.
+    pub fn then(&self, next: &Transform) -> Transform {
+        let moved = Transform { translate: Coordinate { x: 0f32, y: 0f32 }, .. *next }.apply(self.translate);
+        Transform {
+            scale: self.scale * next.scale,
+            rotation: wrap_angle(self.rotation + next.rotation),
+            translate: moved + next.translate,
+        }
+    }
+}
+
+/// Something a gesture asked for instead of painting.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum GestureEvent {
+    /// Two or more fingers moved the view, by this much since the last Transform.
+    Transform(Transform),
+    /// Fingers went down and came back up without moving.  Carries the most fingers that were down
+    /// at once.
+    Tap(usize),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct GestureSettings {
+    /// seconds after the first finger within which the second must land to start a gesture
+    pub touch_window: f32,
+    /// seconds within which every finger must be lifted for a tap
+    pub tap_time: f32,
+    /// pixels a finger can wander and still count as not having moved
+    pub tap_slop: f32,
+    /// pixels a finger must move before the view starts following
+    pub transform_slop: f32,
+}
+
+impl GestureSettings {
+    pub fn default() -> GestureSettings {
+        GestureSettings {
+            touch_window: 0.12f32,
+            tap_time: 0.3f32,
+            tap_slop: 12f32,
+            transform_slop: 24f32,
+        }
+    }
+}
+
+/// What to do with an input event's points.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Decision {
+    /// Draw them as usual.
+    Draw,
+    /// Keep them back until it's clear what the touch is.
+    Hold,
+    /// Release anything held, then draw these.
+    Flush,
+    /// Throw away anything held, along with these; a gesture has started.
+    Discard,
+    /// These belong to a gesture already in progress.
+    Gesture(Option<GestureEvent>),
+}
+
+/// Where the fingers of a gesture are, taken together.
+#[derive(Copy, Clone, Debug)]
+struct Pose {
+    centroid: Coordinate,
+    // mean distance of the fingers from the centroid
+    span: f32,
+    // direction from the first finger to the second
+    angle: f32,
+}
+
+enum Phase {
+    Idle,
+    Undecided { start: f32, origin: Coordinate },
+    Drawing,
+    Gesture { start: f32, fingers: usize, origins: VecMap<Coordinate>, transforming: bool, last: Option<Pose> },
+}
+
+pub struct GestureRecognizer {
+    settings: GestureSettings,
+    phase: Phase,
+}
+
+fn distance(a: Coordinate, b: Coordinate) -> f32 {
+    let d = a - b;
+    (d.x * d.x + d.y * d.y).sqrt()
+}
+
+fn event_time<E: InputEvent>(evt: &E) -> f32 {
+    if evt.pointer_count() > 0 { evt.current_point(0).time } else { 0f32 }
+}
+
+/// The pointers that are still down after this event.
+fn down_pointers<E: InputEvent>(evt: &E) -> VecMap<Coordinate> {
+    let lifted = match evt.action() {
+        InputAction::PointerUp(id) => Some(id),
+        _ => None,
+    };
+    let mut pointers = VecMap::new();
+    for ptr in range(0, evt.pointer_count()) {
+        let id = evt.pointer_id(ptr);
+        if Some(id) != lifted {
+            pointers.insert(id as usize, evt.current_point(ptr).pos);
+        }
+    }
+    pointers
+}
+
+fn pose(pointers: &VecMap<Coordinate>) -> Option<Pose> {
+    if pointers.len() < 2 {
+        return None;
+    }
+    let count = pointers.len() as f32;
+    let sum = pointers.values().fold(Coordinate { x: 0f32, y: 0f32 }, |sum, &p| sum + p);
+    let centroid = sum * (1f32 / count);
+    let span = pointers.values().fold(0f32, |total, &p| total + distance(p, centroid)) / count;
+    let mut firsttwo = pointers.values();
+    let (a, b) = (*firsttwo.next().unwrap(), *firsttwo.next().unwrap());
+    Some(Pose { centroid: centroid, span: span, angle: (b.y - a.y).atan2(b.x - a.x) })
+}
+
+impl GestureRecognizer {
+    pub fn new(settings: GestureSettings) -> GestureRecognizer {
+        GestureRecognizer { settings: settings, phase: Phase::Idle }
+    }
+
+    /// Decides what an event's points are for.  Call it once for every event, in order.
+    pub fn feed<E: InputEvent>(&mut self, evt: &E) -> Decision {
+        let action = evt.action();
+        let time = event_time(evt);
+        let settings = self.settings;
+        let (decision, next) = match (&mut self.phase, action) {
+            (_, InputAction::Key) => return Decision::Draw,
+            (_, InputAction::Down) => {
+                if evt.pointer_count() == 0 {
+                    return Decision::Draw;
+                }
+                if evt.tool_type(0) != ToolType::Finger {
+                    // styluses and mice don't make gestures, so there's no reason to wait
+                    (Decision::Draw, Some(Phase::Drawing))
+                } else {
+                    (Decision::Hold, Some(Phase::Undecided { start: time, origin: evt.current_point(0).pos }))
+                }
+            },
+            (&mut Phase::Idle, _) => (Decision::Draw, None),
+            (&mut Phase::Drawing, InputAction::Up) | (&mut Phase::Drawing, InputAction::Cancel) =>
+                (Decision::Draw, Some(Phase::Idle)),
+            (&mut Phase::Drawing, _) => (Decision::Draw, None),
+            (&mut Phase::Undecided { .. }, InputAction::Up) | (&mut Phase::Undecided { .. }, InputAction::Cancel) =>
+                (Decision::Flush, Some(Phase::Idle)),
+            (&mut Phase::Undecided { start, origin }, InputAction::PointerDown(_)) => {
+                let first = evt.current_point(0).pos;
+                if time - start <= settings.touch_window && distance(first, origin) <= settings.tap_slop {
+                    let origins = down_pointers(evt);
+                    let gesture = Phase::Gesture {
+                        start: start,
+                        fingers: origins.len(),
+                        last: pose(&origins),
+                        origins: origins,
+                        transforming: false,
+                    };
+                    (Decision::Discard, Some(gesture))
+                } else {
+                    (Decision::Flush, Some(Phase::Drawing))
+                }
+            },
+            (&mut Phase::Undecided { start, origin }, _) => {
+                let moved = evt.pointer_count() > 0 && distance(evt.current_point(0).pos, origin) > settings.tap_slop;
+                if moved || time - start > settings.touch_window {
+                    (Decision::Flush, Some(Phase::Drawing))
+                } else {
+                    (Decision::Hold, None)
+                }
+            },
+            (&mut Phase::Gesture { start, fingers, transforming, .. }, InputAction::Up) => {
+                let tapped = !transforming && time - start <= settings.tap_time && fingers >= 2;
+                let event = if tapped { Some(GestureEvent::Tap(fingers)) } else { None };
+                (Decision::Gesture(event), Some(Phase::Idle))
+            },
+            (&mut Phase::Gesture { .. }, InputAction::Cancel) => (Decision::Gesture(None), Some(Phase::Idle)),
+            (&mut Phase::Gesture { ref mut fingers, ref mut origins, ref mut transforming, ref mut last, .. }, _) => {
+                let pointers = down_pointers(evt);
+                if let InputAction::PointerDown(id) = action {
+                    if let Some(&pos) = pointers.get(&(id as usize)) {
+                        origins.insert(id as usize, pos);
+                    }
+                }
+                if pointers.len() > *fingers {
+                    *fingers = pointers.len();
+                }
+                if !*transforming {
+                    *transforming = pointers.iter().any(|(id, &pos)| {
+                        origins.get(&id).map_or(false, |&origin| distance(pos, origin) > settings.transform_slop)
+                    });
+                }
+                let current = pose(&pointers);
+                let event = match (action, *last, current) {
+                    // the same fingers moved, so the view can follow them
+                    (InputAction::Move, Some(before), Some(after)) if *transforming && before.span > 0f32 => {
+                        let change = Transform::about(before.centroid, after.centroid - before.centroid,
+                                                      after.span / before.span, wrap_angle(after.angle - before.angle));
+                        Some(GestureEvent::Transform(change))
+                    },
+                    _ => None,
+                };
+                // fingers coming and going shift the centroid, so start again from wherever
+                // they are now rather than jumping the view
+                *last = current;
+                (Decision::Gesture(event), None)
+            },
+        };
+        if let Some(next) = next {
+            self.phase = next;
+        }
+        decision
+    }
+}
+
+#[test]
+fn transform_about_focus() {
+    let focus = Coordinate { x: 10f32, y: 10f32 };
+    let t = Transform::about(focus, Coordinate { x: 5f32, y: 0f32 }, 2f32, ::core::f32::consts::FRAC_PI_2);
+    let moved = t.apply(focus);
+    assert!(distance(moved, Coordinate { x: 15f32, y: 10f32 }) < 0.001f32);
+    let corner = t.apply(Coordinate { x: 11f32, y: 10f32 });
+    assert!(distance(corner, Coordinate { x: 15f32, y: 12f32 }) < 0.001f32);
+}
+
+#[test]
+fn transform_composes() {
+    let a = Transform::about(Coordinate { x: 3f32, y: 1f32 }, Coordinate { x: 1f32, y: 2f32 }, 1.5f32, 0.3f32);
+    let b = Transform::about(Coordinate { x: -2f32, y: 4f32 }, Coordinate { x: 0f32, y: -1f32 }, 0.5f32, -1f32);
+    let p = Coordinate { x: 7f32, y: -3f32 };
+    assert!(distance(a.then(&b).apply(p), b.apply(a.apply(p))) < 0.001f32);
+}
@@ -2,6 +2,8 @@ extern crate opengles;
 use core::prelude::*;
 use core::mem;
 use core::iter;
+use core::cmp;
+use core::num::Float;
 use collections::vec::Vec;
 use core::borrow::IntoCow;
 
@@ -9,20 +11,24 @@ use opengles::gl2;
 use opengles::gl2::{GLuint, GLenum, GLubyte};
 
 use glcommon::{check_gl_error, GLResult};
+use glpoint;
 use glpoint::{MotionEventConsumer};
-use point::ShaderPaintPoint;
+use point::{ShaderPaintPoint, ToolType, Coordinate};
 use pointshader::PointShader;
-use paintlayer::{TextureTarget, CompletedLayer};
+use paintlayer::{TextureTarget, CompletedLayer, LayerStyle};
 use copyshader::*;
 use gltexture::{Texture, PixelFormat};
 use matrix;
+use matrix::Affine;
+use flate;
 use eglinit;
 use luascript::LuaScript;
 use paintlayer::PaintLayer;
 use lua_callbacks::{LuaCallbackType};
+use lua_geom;
 use lua_geom::{do_interpolate_lua, finish_lua_script};
 use drawevent::Events;
-use rustjni::JNICallbackClosure;
+use smoothing::StrokeFilter;
 
 
 static DRAW_INDEXES: [GLubyte; 6] = [
@@ -30,19 +36,62 @@ static DRAW_INDEXES: [GLubyte; 6] = [
     0, 2, 3
 ];
 
-const UNDO_BUFFERS: i32 = 5;
-
 //#[deriving(FromPrimitive)]
 
 /// struct for storage of data that stays on rust side
 /// should probably be given a meaningful name like PaintContext, but w/e
 pub struct GLInit<'a> {
-    #[allow(dead_code)]
+    /// The canvas size, which every target, layer and undo buffer shares.
     pub dimensions: (i32, i32),
+    /// The size of the surface the canvas is shown on.
+    surface: (i32, i32),
     pub paintstate: PaintState<'a>,
     targetdata: TargetData,
     pub points: Vec<Vec<ShaderPaintPoint>>,
     undo_shader: &'a CopyShader,
+    /// For draw_layer_image, or None if it didn't compile.
+    placed_shader: Option<CopyShader>,
+    /// For exporting with each ExportAlpha, or None if they didn't compile.
+    opaque_export_shader: Option<CopyShader>,
+    straight_export_shader: Option<CopyShader>,
+    /// How far ahead to predict strokes, in seconds, or 0 for no prediction.
+    prediction_horizon: f32,
+    /// Holds the predicted ends of strokes, redrawn every frame and only ever shown on screen.
+    prediction_target: Option<TextureTarget>,
+    prediction_points: Vec<ShaderPaintPoint>,
+    /// Where the canvas appears on screen, taking canvas pixels to screen pixels.
+    view: Affine,
+    /// What's been drawn on the layers since they were last copied down.
+    layers_dirty: Option<PixelRect>,
+    /// Where layers at partial opacity are scaled before they're composited.
+    opacity_target: Option<TextureTarget>,
+}
+
+/// How to fit an existing canvas into a new size: the new canvas is width by height, and the
+/// old one is drawn into the area at (x, y) of size scaled_width by scaled_height, in new canvas
+/// pixels.  Anything outside that area starts out transparent.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Reframe {
+    pub width: i32,
+    pub height: i32,
+    pub x: i32,
+    pub y: i32,
+    pub scaled_width: i32,
+    pub scaled_height: i32,
+}
+
+impl Reframe {
+    /// Stretches the whole canvas to the new size.
+    pub fn scale(width: i32, height: i32) -> Reframe {
+        Reframe { width: width, height: height, x: 0, y: 0, scaled_width: width, scaled_height: height }
+    }
+
+    /// Keeps the width by height area at (x, y) of a canvas that's currently old_size, at its
+    /// current scale.  The area can extend past the canvas to make it bigger.
+    pub fn crop(old_size: (i32, i32), x: i32, y: i32, width: i32, height: i32) -> Reframe {
+        let (oldw, oldh) = old_size;
+        Reframe { width: width, height: height, x: -x, y: -y, scaled_width: oldw, scaled_height: oldh }
+    }
 }
 
 pub struct TargetData {
@@ -50,89 +99,535 @@ pub struct TargetData {
     current_target: u8,
 }
 
-pub struct UndoTargets {
-    targets: [TextureTarget; UNDO_BUFFERS as usize],
-    start: i32,
-    len: i32,
-    max: i32,
-    pos: i32,
+/// How much memory undo history can use.  The newest changes stay on the gpu, as many as fit in
+/// gpu_bytes; older ones are compressed into cpu memory, and the oldest are forgotten once those
+/// go over cpu_bytes.  gpu_bytes also covers the full-size copy of every plane as it was at the
+/// current snapshot; those can't be moved, so once they fill it, every change goes to the cpu.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct UndoBudget {
+    pub gpu_bytes: usize,
+    pub cpu_bytes: usize,
+}
+
+impl UndoBudget {
+    pub fn default() -> UndoBudget {
+        UndoBudget { gpu_bytes: 48 << 20, cpu_bytes: 64 << 20 }
+    }
+}
+
+/// Undo history only keeps the tiles a snapshot changed.
+const TILE_SIZE: i32 = 64;
+
+/// The least room left around a point from the default point shader, which draws 40 pixels per
+/// unit of brush size.
+const MIN_POINT_EXTENT: f32 = 64f32;
+
+/// A rectangle of canvas pixels, counting up from the bottom like gl does.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PixelRect {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+impl PixelRect {
+    pub fn whole(dimensions: (i32, i32)) -> PixelRect {
+        let (w, h) = dimensions;
+        PixelRect { x: 0, y: 0, width: w, height: h }
+    }
+
+    /// The pixels a point extent pixels wide could touch.  pos is in canvas coordinates, which
+    /// count down from the top.
+    pub fn around_point(pos: Coordinate, extent: f32, canvas_height: i32) -> PixelRect {
+        let radius = extent / 2f32;
+        let y = canvas_height as f32 - pos.y;
+        let (left, right) = ((pos.x - radius).floor() as i32, (pos.x + radius).ceil() as i32);
+        let (bottom, top) = ((y - radius).floor() as i32, (y + radius).ceil() as i32);
+        PixelRect { x: left, y: bottom, width: right - left, height: top - bottom }
+    }
+
+    pub fn union(&self, other: &PixelRect) -> PixelRect {
+        let (left, bottom) = (cmp::min(self.x, other.x), cmp::min(self.y, other.y));
+        let right = cmp::max(self.x + self.width, other.x + other.width);
+        let top = cmp::max(self.y + self.height, other.y + other.height);
+        PixelRect { x: left, y: bottom, width: right - left, height: top - bottom }
+    }
+
+    /// The part of the rect inside the canvas, if any.
+    pub fn clip(&self, dimensions: (i32, i32)) -> Option<PixelRect> {
+        let (w, h) = dimensions;
+        let (left, bottom) = (cmp::max(self.x, 0), cmp::max(self.y, 0));
+        let (right, top) = (cmp::min(self.x + self.width, w), cmp::min(self.y + self.height, h));
+        if right > left && top > bottom {
+            Some(PixelRect { x: left, y: bottom, width: right - left, height: top - bottom })
+        } else {
+            None
+        }
+    }
+
+    /// Grows the rect out to whole tiles, then clips it to the canvas.
+    pub fn to_tiles(&self, dimensions: (i32, i32)) -> Option<PixelRect> {
+        let tile_floor = |x: i32| if x < 0 { -((-x + TILE_SIZE - 1) / TILE_SIZE) * TILE_SIZE } else { x / TILE_SIZE * TILE_SIZE };
+        let (left, bottom) = (tile_floor(self.x), tile_floor(self.y));
+        let (right, top) = (-tile_floor(-(self.x + self.width)), -tile_floor(-(self.y + self.height)));
+        PixelRect { x: left, y: bottom, width: right - left, height: top - bottom }.clip(dimensions)
+    }
+
+    /// Where the rect ends up once a canvas that's old_size is reframed: the pixels it covers
+    /// afterwards, if any are left, and the viewport that draws its old contents into them.
+    pub fn reframe(&self, old_size: (i32, i32), reframe: &Reframe) -> Option<(PixelRect, PixelRect)> {
+        let (oldw, oldh) = old_size;
+        let (sx, sy) = (reframe.scaled_width as f32 / oldw as f32, reframe.scaled_height as f32 / oldh as f32);
+        // the viewport counts up from the bottom, and reframe.y down from the top
+        let bottom = (reframe.height - reframe.y - reframe.scaled_height) as f32;
+        let (left, bottom) = (reframe.x as f32 + self.x as f32 * sx, bottom + self.y as f32 * sy);
+        let (right, top) = (left + self.width as f32 * sx, bottom + self.height as f32 * sy);
+        let covered = PixelRect {
+            x: left.floor() as i32,
+            y: bottom.floor() as i32,
+            width: right.ceil() as i32 - left.floor() as i32,
+            height: top.ceil() as i32 - bottom.floor() as i32,
+        };
+        covered.clip((reframe.width, reframe.height)).map(|clipped| {
+            let viewport = PixelRect {
+                x: (left - clipped.x as f32).round() as i32,
+                y: (bottom - clipped.y as f32).round() as i32,
+                width: (right - left).round() as i32,
+                height: (top - bottom).round() as i32,
+            };
+            (clipped, viewport)
+        })
+    }
+
+    fn bytes(&self) -> usize {
+        self.width as usize * self.height as usize * 4
+    }
+
+    fn set_viewport(&self) {
+        gl2::viewport(self.x, self.y, self.width, self.height);
+    }
+}
+
+/// Adds rect to a dirty region that might still be empty.
+fn add_dirty(dirty: &mut Option<PixelRect>, rect: PixelRect) {
+    *dirty = Some(match *dirty {
+        Some(ref current) => current.union(&rect),
+        None => rect,
+    });
+}
+
+enum UndoSnapshot {
+    Gpu(TextureTarget),
+    /// deflated RGBA pixels, in gl's bottom-up row order
+    Compressed((i32, i32), Vec<u8>),
 }
 
-impl UndoTargets {
-    pub fn new() -> UndoTargets {
-        UndoTargets {
-            targets: unsafe { mem::uninitialized() }, // backing array for ringbuffer
-            start: 0, // offset of first index in ringbuffer
-            max: 0, // highest allocated index in target (anything above this is uninitialized)
-            len: 0, // length of ringbuffer
-            pos: 0, // index after most recent position in ringbuffer (first index that will be cleared by a push)
+impl UndoSnapshot {
+    fn gpu_size(&self) -> usize {
+        match *self {
+            UndoSnapshot::Gpu(ref target) => { let (w, h) = target.texture.dimensions; w as usize * h as usize * 4 },
+            UndoSnapshot::Compressed(..) => 0,
         }
     }
-    #[inline(always)] #[allow(dead_code)]
-    pub fn len(&self) -> i32 { self.len }
+
+    fn compressed_size(&self) -> usize {
+        match *self {
+            UndoSnapshot::Gpu(_) => 0,
+            UndoSnapshot::Compressed(_, ref data) => data.len(),
+        }
+    }
+
+    /// Moves the snapshot into cpu memory.  False if that didn't work, leaving it where it was.
+    fn compress(&mut self) -> bool {
+        let compressed = match *self {
+            UndoSnapshot::Gpu(ref target) => match compress_target(target) {
+                Some(compressed) => compressed,
+                None => return false,
+            },
+            UndoSnapshot::Compressed(..) => return true,
+        };
+        *self = compressed;
+        true
+    }
+
+    /// Runs f with the snapshot on the gpu, uncompressing a temporary copy if it has to.
+    fn with_target<F: FnOnce(&TextureTarget)>(&self, copyshader: &CopyShader, f: F) {
+        match *self {
+            UndoSnapshot::Gpu(ref target) => f(target),
+            ref compressed => match uncompress_snapshot(compressed, copyshader) {
+                Some(target) => f(&target),
+                None => { },
+            },
+        }
+    }
+}
+
+/// Reads a target back from the gpu and deflates it.
+fn compress_target(target: &TextureTarget) -> Option<UndoSnapshot> {
+    let (w, h) = target.texture.dimensions;
+    let mut pixels: Vec<u8> = iter::repeat(0u8).take(w as usize * h as usize * 4).collect();
+    gl2::bind_framebuffer(gl2::FRAMEBUFFER, target.framebuffer);
+    gl2::read_pixels_into(0, 0, w, h, gl2::RGBA, gl2::UNSIGNED_BYTE, pixels.as_mut_slice());
+    check_gl_error("read_pixels");
+    flate::deflate_bytes(pixels.as_slice()).map(|bytes| UndoSnapshot::Compressed((w, h), bytes.as_slice().to_vec()))
+}
+
+/// Puts a snapshot back on the gpu, if it isn't there already.
+fn uncompress_snapshot(snapshot: &UndoSnapshot, copyshader: &CopyShader) -> Option<TextureTarget> {
+    match *snapshot {
+        UndoSnapshot::Gpu(_) => None,
+        UndoSnapshot::Compressed((w, h), ref data) => {
+            let pixels = match flate::inflate_bytes(data.as_slice()) {
+                Some(pixels) => pixels,
+                None => {
+                    loge!("couldn't inflate undo snapshot!");
+                    return None;
+                },
+            };
+            let texture = Texture::with_image(w, h, Some(pixels.as_slice()), PixelFormat::RGBA);
+            let target = TextureTarget::new(w, h, PixelFormat::RGBA);
+            gl2::viewport(0, 0, w, h);
+            copy_texture(&target, &texture, copyshader);
+            Some(target)
+        },
+    }
+}
+
+/// Copies the pixels under rect out of src.  Leaves the viewport set for the copy.
+fn take_tile(src: &TextureTarget, rect: PixelRect, copyshader: &CopyShader) -> TextureTarget {
+    let tile = TextureTarget::new(rect.width, rect.height, PixelFormat::RGBA);
+    let (w, h) = src.texture.dimensions;
+    gl2::viewport(-rect.x, -rect.y, w, h);
+    copy_target(&tile, src, copyshader);
+    tile
+}
+
+/// Copies a tile taken by take_tile back into dest.  Leaves the viewport set for the copy.
+fn put_tile(dest: &TextureTarget, rect: PixelRect, tile: &TextureTarget, copyshader: &CopyShader) {
+    rect.set_viewport();
+    copy_target(dest, tile, copyshader);
+}
+
+/// What one snapshot changed: the tiles under rect, before and after it was taken.  The oldest
+/// snapshot in the history doesn't need either.
+struct UndoDelta {
+    rect: PixelRect,
+    before: UndoSnapshot,
+    after: UndoSnapshot,
+}
+
+impl UndoDelta {
+    fn snapshots_mut(&mut self) -> [&mut UndoSnapshot; 2] {
+        [&mut self.before, &mut self.after]
+    }
+
+    fn gpu_size(&self) -> usize {
+        self.before.gpu_size() + self.after.gpu_size()
+    }
+
+    fn compressed_size(&self) -> usize {
+        self.before.compressed_size() + self.after.compressed_size()
+    }
+
+    /// The delta for the same pixels of a reframed canvas, unless they were cropped away.
+    fn reframe(&self, old_size: (i32, i32), reframe: &Reframe, copyshader: &CopyShader) -> Option<UndoDelta> {
+        let (rect, viewport) = match self.rect.reframe(old_size, reframe) {
+            Some(placed) => placed,
+            None => return None,
+        };
+        let resample = |snapshot: &UndoSnapshot| {
+            let mut resampled = None;
+            snapshot.with_target(copyshader, |tile| {
+                let target = TextureTarget::new(rect.width, rect.height, PixelFormat::RGBA);
+                viewport.set_viewport();
+                copy_target(&target, tile, copyshader);
+                resampled = Some(UndoSnapshot::Gpu(target));
+            });
+            resampled
+        };
+        match (resample(&self.before), resample(&self.after)) {
+            (Some(before), Some(after)) => Some(UndoDelta { rect: rect, before: before, after: after }),
+            _ => None,
+        }
+    }
+}
+
+/// A layer's settings, without its pixels.
+#[derive(Copy, Clone)]
+pub struct LayerSettings<'a> {
+    pub copyshader: Option<&'a CopyShader>,
+    pub pointshader: Option<&'a PointShader>,
+    pub pointidx: i32,
+    pub style: LayerStyle,
+}
+
+impl<'a> LayerSettings<'a> {
+    pub fn of(layer: &PaintLayer<'a>) -> LayerSettings<'a> {
+        LayerSettings { copyshader: layer.copyshader, pointshader: layer.pointshader, pointidx: layer.pointidx, style: layer.style }
+    }
+}
+
+/// One snapshot: the layers that were set up, and what it changed on each plane.
+struct UndoEntry<'a> {
+    layers: Vec<LayerSettings<'a>>,
+    /// indexed by plane; None where nothing changed, and empty for the oldest snapshot
+    deltas: Vec<Option<UndoDelta>>,
+}
+
+/// Undo history, oldest first.  It covers every plane of the canvas: the base target is plane
+/// 0, and each layer is the plane after it.  A plane a snapshot didn't have counts as empty.
+///
+/// Rather than a copy of every plane for every snapshot, it keeps one copy of each plane as it was
+/// at the current snapshot, and the tiles each snapshot changed, so it can step from one snapshot
+/// to the next.  Loading a snapshot keeps the ones after it around for redo until the next push.
+pub struct UndoHistory<'a> {
+    entries: Vec<UndoEntry<'a>>,
+    pos: usize, // index after the current snapshot
+    /// every plane as of the current snapshot
+    current: Vec<TextureTarget>,
+    /// what's been drawn on each plane since the current snapshot
+    dirty: Vec<Option<PixelRect>>,
+    budget: UndoBudget,
+}
+
+impl<'a> UndoHistory<'a> {
+    pub fn new() -> UndoHistory<'a> {
+        UndoHistory { entries: Vec::new(), pos: 0, current: Vec::new(), dirty: Vec::new(), budget: UndoBudget::default() }
+    }
 
     #[inline(always)]
-    fn get_pos(&self, pos: i32) -> i32 { (self.start + pos) % UNDO_BUFFERS }
-
-    pub fn push_new_buffer(&mut self, buf: &TextureTarget, copyshader: &CopyShader) {
-        let end = self.get_pos(self.pos);
-        let target = &mut self.targets[end as usize];
-        if end >= self.max {
-            let (x, y) = buf.texture.dimensions;
-            *target = TextureTarget::new(x, y, PixelFormat::RGBA);
-            self.max += 1;
-        }
-        gl2::bind_framebuffer(gl2::FRAMEBUFFER, target.framebuffer);
-        gl2::blend_func(gl2::ONE, gl2::ZERO);
-        perform_copy(target.framebuffer, &buf.texture, copyshader, matrix::IDENTITY.as_slice());
-        if self.pos < UNDO_BUFFERS {
-            self.pos += 1;
+    pub fn len(&self) -> i32 { self.entries.len() as i32 }
+
+    /// The index of the snapshot last pushed or loaded, or -1 if there isn't one.
+    #[inline(always)]
+    pub fn position(&self) -> i32 { self.pos as i32 - 1 }
+
+    pub fn set_budget(&mut self, budget: UndoBudget) {
+        self.budget = budget;
+        self.enforce_budget();
+    }
+
+    /// Notes that the pixels under rect are about to change on a plane.
+    pub fn mark_dirty(&mut self, plane: usize, rect: PixelRect) {
+        while self.dirty.len() <= plane {
+            self.dirty.push(None);
+        }
+        add_dirty(&mut self.dirty[plane], rect);
+    }
+
+    /// A plane as of the current snapshot.
+    pub fn plane(&self, plane: usize) -> Option<&TextureTarget> {
+        self.current.get(plane)
+    }
+
+    /// Makes sure there's a saved copy of every plane up to count, starting any new ones empty.
+    fn extend_planes(&mut self, count: usize, dimensions: (i32, i32)) {
+        let (w, h) = dimensions;
+        while self.current.len() < count {
+            self.current.push(TextureTarget::new(w, h, PixelFormat::RGBA));
+        }
+    }
+
+    /// Takes a snapshot of the given planes, the base target first.
+    pub fn push_new_buffer(&mut self, planes: &[&TextureTarget], layers: Vec<LayerSettings<'a>>, copyshader: &CopyShader) {
+        self.entries.truncate(self.pos);
+        let dimensions = planes[0].texture.dimensions;
+        let (w, h) = dimensions;
+        let fresh = self.current.first().map_or(true, |current| current.texture.dimensions != dimensions);
+        let mut deltas = Vec::new();
+        if fresh {
+            self.current.clear();
+            gl2::viewport(0, 0, w, h);
+            for plane in planes.iter() {
+                let current = TextureTarget::new(w, h, PixelFormat::RGBA);
+                copy_target(&current, *plane, copyshader);
+                self.current.push(current);
+            }
         } else {
-            let next = self.start + 1;
-            self.start = if next == UNDO_BUFFERS { 0 } else { next };
+            let count = cmp::max(self.current.len(), planes.len());
+            self.extend_planes(count, dimensions);
+            for p in range(0, count) {
+                let changed = self.dirty.get(p).and_then(|dirty| dirty.and_then(|dirty| dirty.to_tiles(dimensions)));
+                let current = &self.current[p];
+                deltas.push(changed.map(|rect| {
+                    let before = take_tile(current, rect, copyshader);
+                    // a plane that's gone now is empty
+                    let after = match planes.get(p) {
+                        Some(plane) => take_tile(*plane, rect, copyshader),
+                        None => TextureTarget::new(rect.width, rect.height, PixelFormat::RGBA),
+                    };
+                    put_tile(current, rect, &after, copyshader);
+                    UndoDelta { rect: rect, before: UndoSnapshot::Gpu(before), after: UndoSnapshot::Gpu(after) }
+                }));
+            }
         }
-        self.len = self.pos;
+        gl2::viewport(0, 0, w, h);
+        debug_logi!("pushing undo snapshot {} with {} layers, changing {:?}", self.entries.len(), layers.len(),
+                    deltas.iter().map(|d| d.as_ref().map(|d| d.rect)).collect::<Vec<Option<PixelRect>>>());
+        self.entries.push(UndoEntry { layers: layers, deltas: deltas });
+        self.pos = self.entries.len();
+        self.dirty.clear();
+        self.enforce_budget();
     }
 
-    pub fn load_buffer_at(&mut self, idx: i32, buf: &TextureTarget, copyshader: &CopyShader) {
-        if idx >= self.len {
-            loge!("undo index {} exceeds current buffer size {}!", idx, self.len);
-            return;
+    /// Loads a snapshot into the given planes, the base target first, and returns the layers it
+    /// had.  Layers that don't exist yet should be filled in from plane() afterwards.
+    pub fn load_buffer_at(&mut self, idx: i32, planes: &[&TextureTarget], copyshader: &CopyShader) -> Option<Vec<LayerSettings<'a>>> {
+        if idx < 0 || idx >= self.len() {
+            loge!("undo index {} exceeds current buffer size {}!", idx, self.len());
+            return None;
+        }
+        debug_logi!("loading undo buffer {}/{}", idx, self.len());
+        let dimensions = planes[0].texture.dimensions;
+        let count = planes.len();
+        self.extend_planes(count, dimensions);
+        // put back whatever was drawn since the current snapshot, then step through the
+        // snapshots in between, fixing up both copies of each plane as we go
+        for (p, plane) in planes.iter().enumerate() {
+            if let Some(rect) = self.dirty.get(p).and_then(|dirty| dirty.and_then(|dirty| dirty.to_tiles(dimensions))) {
+                let tile = take_tile(&self.current[p], rect, copyshader);
+                put_tile(*plane, rect, &tile, copyshader);
+            }
         }
-        debug_logi!("loading undo buffer {}/{}", idx, self.len);
-        self.pos = idx + 1;
-        let src = &mut self.targets[self.get_pos(idx) as usize];
-        gl2::bind_framebuffer(gl2::FRAMEBUFFER, buf.framebuffer);
-        gl2::blend_func(gl2::ONE, gl2::ZERO);
-        perform_copy(buf.framebuffer, &src.texture, copyshader, matrix::IDENTITY.as_slice());
+        let target = idx as usize + 1;
+        while self.pos != target {
+            let (entry, forward) = if self.pos > target {
+                (&self.entries[self.pos - 1], false)
+            } else {
+                (&self.entries[self.pos], true)
+            };
+            for (p, delta) in entry.deltas.iter().enumerate() {
+                if let Some(ref delta) = *delta {
+                    let snapshot = if forward { &delta.after } else { &delta.before };
+                    let current = &self.current[p];
+                    snapshot.with_target(copyshader, |tile| {
+                        put_tile(current, delta.rect, tile, copyshader);
+                        if let Some(plane) = planes.get(p) {
+                            put_tile(*plane, delta.rect, tile, copyshader);
+                        }
+                    });
+                }
+            }
+            self.pos = if forward { self.pos + 1 } else { self.pos - 1 };
+        }
+        let (w, h) = dimensions;
+        gl2::viewport(0, 0, w, h);
+        self.dirty.clear();
+        Some(self.entries[target - 1].layers.clone())
     }
 
     pub fn clear_buffers(&mut self) {
-        self.start = self.pos;
-        self.len = 0;
+        self.entries.clear();
         self.pos = 0;
+        self.current.clear();
+        self.dirty.clear();
+    }
+
+    /// Including the saved copy of each plane.
+    fn gpu_total(&self) -> usize {
+        let planes = self.current.iter().fold(0, |total, current| total + PixelRect::whole(current.texture.dimensions).bytes());
+        self.entries.iter().flat_map(|e| e.deltas.iter()).fold(planes, |total, d| total + d.as_ref().map_or(0, |d| d.gpu_size()))
     }
-}
 
-#[unsafe_destructor]
-impl Drop for UndoTargets {
-    fn drop(&mut self) -> () {
-        for pos in range(0, self.max) {
-            mem::drop(&mut self.targets[self.get_pos(pos) as usize]);
+    fn compressed_total(&self) -> usize {
+        self.entries.iter().flat_map(|e| e.deltas.iter()).fold(0, |total, d| total + d.as_ref().map_or(0, |d| d.compressed_size()))
+    }
+
+    /// Moves the oldest changes still on the gpu into cpu memory, then forgets the oldest
+    /// snapshots, until everything fits.
+    fn enforce_budget(&mut self) {
+        let mut gpu_total = self.gpu_total();
+        let mut idx = 0;
+        while gpu_total > self.budget.gpu_bytes && idx < self.entries.len() {
+            for delta in self.entries[idx].deltas.iter_mut() {
+                if let Some(ref mut delta) = *delta {
+                    for snapshot in delta.snapshots_mut().iter_mut() {
+                        let size = snapshot.gpu_size();
+                        if size > 0 && snapshot.compress() {
+                            gpu_total -= size;
+                        }
+                    }
+                }
+            }
+            idx += 1;
         }
+        let mut cpu_total = self.compressed_total();
+        // the current snapshot is kept no matter what
+        while cpu_total > self.budget.cpu_bytes && self.pos > 1 {
+            self.remove_oldest(1);
+            cpu_total = self.compressed_total();
+        }
+        debug_logi!("undo history: {} snapshots, {} bytes on the gpu, {} compressed bytes", self.entries.len(), gpu_total, cpu_total);
+    }
+
+    fn remove_oldest(&mut self, count: usize) {
+        let count = if count > self.pos { self.pos } else { count };
+        for _ in range(0, count) {
+            self.entries.remove(0);
+        }
+        // nothing steps back past the oldest snapshot, so its changes aren't needed any more
+        if let Some(oldest) = self.entries.get_mut(0) {
+            oldest.deltas.clear();
+        }
+        self.pos -= count;
+    }
+
+    /// Reframes the saved planes and every change, so history survives a canvas resize.
+    pub fn reframe(&mut self, reframe: &Reframe, copyshader: &CopyShader) {
+        let old_size = match self.current.first() {
+            Some(current) => current.texture.dimensions,
+            None => return,
+        };
+        let current = self.current.iter().map(|current| reframe_target(current, reframe, copyshader)).collect();
+        self.current = current;
+        for entry in self.entries.iter_mut() {
+            let deltas = entry.deltas.iter().map(|delta| {
+                delta.as_ref().and_then(|delta| delta.reframe(old_size, reframe, copyshader))
+            }).collect();
+            entry.deltas = deltas;
+        }
+        let dirty = self.dirty.iter().map(|dirty| {
+            dirty.and_then(|dirty| dirty.reframe(old_size, reframe)).map(|(rect, _)| rect)
+        }).collect();
+        self.dirty = dirty;
+        gl2::viewport(0, 0, reframe.width, reframe.height);
+        self.enforce_budget();
     }
 }
 
+/// What happens to points drawn with the eraser end of a stylus.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum EraserMode {
+    /// Drawn like any other point; the interpolator can still check the tool itself.
+    Paint,
+    /// Cuts the brush shape out of whichever layer the points were pushed to.
+    Erase,
+}
+
+/// What exported pixels do with transparency.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ExportAlpha {
+    /// Flattened onto black and fully opaque, like the screen.
+    Opaque,
+    /// Straight (un-premultiplied) alpha, so transparent parts stay transparent.
+    Straight,
+}
+
 pub struct PaintState<'a> {
     pub pointshader: Option<&'a PointShader>,
     pub animshader: Option<&'a CopyShader>,
+    /// whether animshader does anything but copy, decided when it's set
+    pub animating: bool,
     pub copyshader: Option<&'a CopyShader>,
     pub brush: Option<&'a Texture>,
     pub interpolator: Option<&'a LuaScript>,
     pub layers: Vec<PaintLayer<'a>>,
-    pub undo_targets: UndoTargets,
+    pub undo_targets: UndoHistory<'a>,
     pub brush_color: [f32; 3],
     pub brush_size: f32,
+    pub eraser_mode: EraserMode,
+    pub stroke_filter: StrokeFilter,
 }
 
 impl<'a> PaintState<'a> {
@@ -140,13 +635,16 @@ impl<'a> PaintState<'a> {
         PaintState {
             pointshader: None,
             animshader: None,
+            animating: false,
             copyshader: None,
             brush: None,
             interpolator: None,
             layers: Vec::new(),
-            undo_targets: UndoTargets::new(),
+            undo_targets: UndoHistory::new(),
             brush_color: [1f32, 1f32, 0f32],
             brush_size: 1f32,
+            eraser_mode: EraserMode::Erase,
+            stroke_filter: StrokeFilter::default(),
         }
     }
 }
@@ -164,16 +662,173 @@ fn perform_copy(dest_framebuffer: GLuint, source_texture: &Texture, shader: &Cop
     check_gl_error("drew elements");
 }
 
+/// Overwrites dest with src, alpha included.
+fn copy_target(dest: &TextureTarget, src: &TextureTarget, copyshader: &CopyShader) {
+    copy_texture(dest, &src.texture, copyshader);
+}
+
+fn copy_texture(dest: &TextureTarget, src: &Texture, copyshader: &CopyShader) {
+    gl2::bind_framebuffer(gl2::FRAMEBUFFER, dest.framebuffer);
+    gl2::blend_func(gl2::ONE, gl2::ZERO);
+    perform_copy(dest.framebuffer, src, copyshader, matrix::IDENTITY.as_slice());
+}
+
+/// Makes a copy of src at the reframed size.  Leaves the viewport set for the copy.
+fn reframe_target(src: &TextureTarget, reframe: &Reframe, copyshader: &CopyShader) -> TextureTarget {
+    let target = TextureTarget::new(reframe.width, reframe.height, PixelFormat::RGBA);
+    // the viewport counts up from the bottom, and reframe.y down from the top
+    let bottom = reframe.height - reframe.y - reframe.scaled_height;
+    gl2::viewport(reframe.x, bottom, reframe.scaled_width, reframe.scaled_height);
+    copy_target(&target, src, copyshader);
+    target
+}
+
+/// Shows all of a canvas as large as it fits on a surface, centered.
+fn fit_view(canvas: (i32, i32), surface: (i32, i32)) -> Affine {
+    let ((cw, ch), (sw, sh)) = (canvas, surface);
+    let (cw, ch, sw, sh) = (cw as f32, ch as f32, sw as f32, sh as f32);
+    let scale = if sw / cw < sh / ch { sw / cw } else { sh / ch };
+    Affine::scale(scale, scale).then(&Affine::translate((sw - cw * scale) / 2f32, (sh - ch * scale) / 2f32))
+}
+
+#[inline]
+fn is_erasing(mode: EraserMode, point: &ShaderPaintPoint) -> bool {
+    mode == EraserMode::Erase && point.tool == ToolType::Eraser as u8 as f32
+}
+
+/// The base target, followed by each layer's.
+fn collect_planes<'b>(base: &'b TextureTarget, layers: &'b [PaintLayer]) -> Vec<&'b TextureTarget> {
+    let mut planes = Vec::with_capacity(layers.len() + 1);
+    planes.push(base);
+    planes.extend(layers.iter().map(|layer| &layer.target));
+    planes
+}
+
+/// The pixels a run of points could be drawn on, in a target of the given dimensions, going by
+/// where matrix puts them.  Only the default vertex shader's point size is known; any other
+/// shader can make points as big as it likes, so they're taken to cover the whole target.
+fn points_extent(points: &[ShaderPaintPoint], shader: &PointShader, brush_size: f32, matrix: &[f32], dimensions: (i32, i32)) -> Option<PixelRect> {
+    if points.is_empty() {
+        return None;
+    }
+    if !shader.has_default_size() {
+        return Some(PixelRect::whole(dimensions));
+    }
+    let (w, h) = dimensions;
+    let extent = if 40f32 * brush_size > MIN_POINT_EXTENT { 40f32 * brush_size } else { MIN_POINT_EXTENT };
+    let mut dirty = None;
+    for point in points.iter() {
+        let (x, y) = (point.pos.x, point.pos.y);
+        let clipw = matrix[3] * x + matrix[7] * y + matrix[15];
+        if !(clipw > 0f32) {
+            return Some(PixelRect::whole(dimensions));
+        }
+        let clipx = (matrix[0] * x + matrix[4] * y + matrix[12]) / clipw;
+        let clipy = (matrix[1] * x + matrix[5] * y + matrix[13]) / clipw;
+        // around_point wants y counting down from the top
+        let pixel = Coordinate { x: (clipx + 1f32) / 2f32 * w as f32, y: (1f32 - clipy) / 2f32 * h as f32 };
+        add_dirty(&mut dirty, PixelRect::around_point(pixel, extent, h));
+    }
+    dirty
+}
+
+/// Composites a layer onto dest through copyshader, the way its style says to.  A layer at
+/// partial opacity is scaled into scratch with plaincopy first, so any copy shader works with it.
+/// viewport is the size of dest.  Leaves blending on, with the normal blend function.
+fn composite_layer(dest: GLuint, layer: &PaintLayer, copyshader: &CopyShader, matrix: &[f32]
+                   , viewport: (i32, i32), scratch: &mut Option<TextureTarget>, plaincopy: &CopyShader) {
+    let style = layer.style;
+    if !style.is_shown() {
+        return;
+    }
+    gl2::enable(gl2::BLEND);
+    let source = if style.opacity < 1f32 {
+        let (w, h) = layer.target.texture.dimensions;
+        if scratch.as_ref().map_or(true, |scaled| scaled.texture.dimensions != (w, h)) {
+            *scratch = Some(TextureTarget::new(w, h, PixelFormat::RGBA));
+        }
+        let scaled = scratch.as_ref().unwrap();
+        gl2::viewport(0, 0, w, h);
+        gl2::blend_color(0f32, 0f32, 0f32, style.opacity);
+        gl2::blend_func(gl2::CONSTANT_ALPHA, gl2::ZERO);
+        perform_copy(scaled.framebuffer, &layer.target.texture, plaincopy, matrix::IDENTITY.as_slice());
+        let (vw, vh) = viewport;
+        gl2::viewport(0, 0, vw, vh);
+        &scaled.texture
+    } else {
+        &layer.target.texture
+    };
+    style.blend.apply();
+    perform_copy(dest, source, copyshader, matrix);
+    gl2::blend_func(gl2::ONE, gl2::ONE_MINUS_SRC_ALPHA);
+}
+
+/// Draws points in order, switching to an erasing blend for runs of eraser points.
 fn draw_layer(layer: CompletedLayer, matrix: &[f32], color: [f32; 3], size: f32
-              , brush: &Texture, back_buffer: &Texture, points: &[ShaderPaintPoint]) {
+              , brush: &Texture, back_buffer: &Texture, points: &[ShaderPaintPoint], eraser: EraserMode) {
     if points.len() > 0 {
         gl2::bind_framebuffer(gl2::FRAMEBUFFER, layer.target.framebuffer);
-        layer.pointshader.prep(matrix.as_slice(), points, color, size, brush, back_buffer);
-        gl2::draw_arrays(gl2::POINTS, 0, points.len() as i32);
-        check_gl_error("draw_arrays");
+        let mut start = 0;
+        while start < points.len() {
+            let erasing = is_erasing(eraser, &points[start]);
+            let end = points[start..].iter().position(|p| is_erasing(eraser, p) != erasing).map_or(points.len(), |x| start + x);
+            if erasing {
+                gl2::blend_func(gl2::ZERO, gl2::ONE_MINUS_SRC_ALPHA);
+            }
+            layer.pointshader.prep(matrix.as_slice(), &points[start..end], color, size, brush, back_buffer);
+            gl2::draw_arrays(gl2::POINTS, 0, (end - start) as i32);
+            check_gl_error("draw_arrays");
+            if erasing {
+                gl2::blend_func(gl2::ONE, gl2::ONE_MINUS_SRC_ALPHA);
+            }
+            start = end;
+        }
+    }
+}
+
+/// Where a target's texture coordinates land on an image of the given size, placed on a canvas
+/// by placement.  This is the copy matrix for drawing the image; bitmap rows are uploaded top
+/// first, so unlike the canvas, the image's texture coordinates count down from the top.
+fn image_texture_from_target(placement: Affine, image: (i32, i32), canvas: (i32, i32)) -> Option<Affine> {
+    let ((w, h), (cw, ch)) = (image, canvas);
+    let canvas_from_target = Affine { a: cw as f32, d: -ch as f32, ty: ch as f32, .. Affine::identity() };
+    let texture_from_image = Affine::scale(1f32 / w as f32, 1f32 / h as f32);
+    placement.invert().map(|image_from_canvas| {
+        canvas_from_target.then(&image_from_canvas).then(&texture_from_image)
+    })
+}
+
+/// Compiles one of the built-in copy shaders.  A failure is only logged here; whatever needs the
+/// shader reports it when it's used.
+fn builtin_copy_shader(fragment: &'static str) -> Option<CopyShader> {
+    match ::glstore::init_from_defaults((None, Some(fragment.into_cow()))) {
+        Ok(shader) => Some(shader),
+        Err(msg) => {
+            loge!("couldn't compile a built-in shader: {}", msg);
+            None
+        },
     }
 }
 
+/// Reads a texture back into pixels as RGBA, flipped from gl coords to bitmap coords, through
+/// one of the export shaders.
+fn read_texture_pixels(texture: &Texture, pixels: &mut [u8], saveshader: &CopyShader, format: PixelFormat) {
+    let (x,y) = texture.dimensions;
+    // The only purpose of the shader copy is to flip the image from gl coords to bitmap coords.
+    // it might be better to finagle the output copy matrix so the rest of the targets
+    // can stay in bitmap coords?  Or have a dedicated target for this.
+    let newtarget = TextureTarget::new(x, y, format);
+    let matrix = [1f32,  0f32,  0f32,  0f32,
+                  0f32, -1f32,  0f32,  0f32,
+                  0f32,  0f32,  1f32,  0f32,
+                  0f32,  1f32,  0f32,  1f32,];
+    gl2::viewport(0, 0, x, y);
+    perform_copy(newtarget.framebuffer, texture, saveshader, matrix.as_slice());
+    gl2::finish();
+    gl2::read_pixels_into(0, 0, x, y, gl2::RGBA, gl2::UNSIGNED_BYTE, pixels);
+    check_gl_error("read_pixels");
+}
+
 impl TargetData {
     fn get_current_texturetarget<'a>(&'a self) -> &'a TextureTarget {
         &self.targets[self.current_target as usize]
@@ -205,30 +860,107 @@ impl<'a> GLInit<'a> {
         let intexture = Texture::with_image(w, h, Some(pixels), PixelFormat::RGBA);
         check_gl_error("creating texture");
         perform_copy(target.framebuffer, &intexture, self.undo_shader, matrix.as_slice());
+        self.paintstate.undo_targets.mark_dirty(0, PixelRect::whole(self.dimensions));
     }
 
     pub fn get_buffer_dimensions(&self) -> (i32, i32) {
         self.targetdata.get_current_texturetarget().texture.dimensions
     }
 
-    pub fn get_pixels(&mut self, pixels: &mut [u8]) {
-        let oldtarget = self.targetdata.get_current_texturetarget();
-        let (x,y) = oldtarget.texture.dimensions;
-        gl2::bind_framebuffer(gl2::FRAMEBUFFER, oldtarget.framebuffer);
-        check_gl_error("read_pixels");
-        // The only purpose of the shader copy is to flip the image from gl coords to bitmap coords.
-        // it might be better to finagle the output copy matrix so the rest of the targets
-        // can stay in bitmap coords?  Or have a dedicated target for this.
-        let saveshader = ::glstore::init_from_defaults((None, Some(include_str!("../includes/shaders/noalpha_copy.fsh").into_cow()))).unwrap();
-        let newtarget = TextureTarget::new(x, y, PixelFormat::RGB);
-        let matrix = [1f32,  0f32,  0f32,  0f32,
-                      0f32, -1f32,  0f32,  0f32,
-                      0f32,  0f32,  1f32,  0f32,
-                      0f32,  1f32,  0f32,  1f32,];
-        perform_copy(newtarget.framebuffer, &oldtarget.texture, &saveshader, matrix.as_slice());
-        gl2::finish();
-        gl2::read_pixels_into(0, 0, x, y, gl2::RGBA, gl2::UNSIGNED_BYTE, pixels);
-        check_gl_error("read_pixels");
+    /// Composites the canvas and every layer into a new target, the same way render_frame shows
+    /// them, but at canvas size and without the view or any predicted strokes.
+    fn composite_targets(&mut self) -> TextureTarget {
+        let copy_shader = self.paintstate.copyshader.unwrap_or(self.undo_shader);
+        let (w, h) = self.dimensions;
+        let copymatrix = matrix::IDENTITY.as_slice();
+        let composited = TextureTarget::new(w, h, PixelFormat::RGBA);
+        gl2::viewport(0, 0, w, h);
+        gl2::disable(gl2::BLEND);
+        perform_copy(composited.framebuffer, &self.targetdata.get_current_texturetarget().texture, copy_shader, copymatrix);
+        gl2::enable(gl2::BLEND);
+        for layer in self.paintstate.layers.iter() {
+            composite_layer(composited.framebuffer, layer, layer.copyshader.unwrap_or(copy_shader), copymatrix,
+                            (w, h), &mut self.opacity_target, self.undo_shader);
+        }
+        composited
+    }
+
+    /// The shader and intermediate format for exporting with alpha.
+    fn export_shader(&self, alpha: ExportAlpha) -> GLResult<(&CopyShader, PixelFormat)> {
+        let (shader, format) = match alpha {
+            ExportAlpha::Opaque => (self.opaque_export_shader.as_ref(), PixelFormat::RGB),
+            ExportAlpha::Straight => (self.straight_export_shader.as_ref(), PixelFormat::RGBA),
+        };
+        match shader {
+            Some(shader) => Ok((shader, format)),
+            None => Err(format!("can't export with {:?} alpha, as the shader for it didn't compile", alpha).into_cow()),
+        }
+    }
+
+    /// Reads back what's on screen: the canvas with every layer composited over it, including
+    /// strokes that haven't been copied down yet.  Pixels are always RGBA.
+    pub fn get_pixels(&mut self, pixels: &mut [u8], alpha: ExportAlpha) -> GLResult<()> {
+        let composited = self.composite_targets();
+        let (shader, format) = try!(self.export_shader(alpha));
+        read_texture_pixels(&composited.texture, pixels, shader, format);
+        Ok(())
+    }
+
+    /// Reads back a single plane, numbered the way erase_layer numbers them, so the base canvas
+    /// is 0.  Layers come out as they are, without their copy shader or style applied.
+    pub fn get_layer_pixels(&mut self, layer: i32, pixels: &mut [u8], alpha: ExportAlpha) -> GLResult<()> {
+        let texture = match layer {
+            0 => &self.targetdata.get_current_texturetarget().texture,
+            _ => &self.paintstate.layers[try!(self.layer_index(layer, "export"))].target.texture,
+        };
+        let (shader, format) = try!(self.export_shader(alpha));
+        read_texture_pixels(texture, pixels, shader, format);
+        Ok(())
+    }
+
+    /// Draws an image of w by h RGBA pixels over a plane, numbered the way erase_layer numbers
+    /// them.  placement takes image pixels to canvas pixels, both counting down from the top left.
+    pub fn draw_layer_image(&mut self, layer: i32, w: i32, h: i32, pixels: &[u8], placement: Affine) -> GLResult<()> {
+        if w <= 0 || h <= 0 || pixels.len() < (w * h * 4) as usize {
+            return Err(format!("tried to draw a {}x{} image from {} bytes", w, h, pixels.len()).into_cow());
+        }
+        let texture_from_target = match image_texture_from_target(placement, (w, h), self.dimensions) {
+            Some(matrix) => matrix,
+            None => return Err("tried to draw an image with a placement that flattens it".into_cow()),
+        };
+        let placeshader = match self.placed_shader {
+            Some(ref shader) => shader,
+            None => return Err("can't draw placed images, as the shader for them didn't compile".into_cow()),
+        };
+        let (cw, ch) = self.dimensions;
+        let framebuffer = match layer {
+            0 => self.targetdata.get_current_texturetarget().framebuffer,
+            _ => self.paintstate.layers[try!(self.layer_index(layer, "draw on"))].target.framebuffer,
+        };
+        let corners = [(0, 0), (w, 0), (0, h), (w, h)];
+        let dirty = corners.iter().fold(None, |dirty, &(x, y)| {
+            let mut dirty = dirty;
+            let corner = placement.apply(Coordinate { x: x as f32, y: y as f32 });
+            add_dirty(&mut dirty, PixelRect::around_point(corner, 2f32, ch));
+            dirty
+        });
+        let dirty = match dirty.and_then(|dirty| dirty.clip(self.dimensions)) {
+            Some(dirty) => dirty,
+            None => return Ok(()),
+        };
+        self.paintstate.undo_targets.mark_dirty(layer as usize, dirty);
+        if layer > 0 {
+            add_dirty(&mut self.layers_dirty, dirty);
+        }
+
+        let matrix = texture_from_target.to_matrix();
+        let intexture = Texture::with_image(w, h, Some(pixels), PixelFormat::RGBA);
+        check_gl_error("creating texture");
+        gl2::viewport(0, 0, cw, ch);
+        gl2::enable(gl2::BLEND);
+        gl2::blend_func(gl2::ONE, gl2::ONE_MINUS_SRC_ALPHA);
+        perform_copy(framebuffer, &intexture, placeshader, matrix.as_slice());
+        Ok(())
     }
 
     // TODO: make an enum for these with a scala counterpart
@@ -239,9 +971,12 @@ impl<'a> GLInit<'a> {
 
     // these can also be null to unset the shader
     // TODO: document better from scala side
+    /// An anim shader that does more than copy can change any pixel on any frame, so while one is
+    /// selected, every undo snapshot of the canvas is of the whole thing.
     pub fn set_anim_shader(&mut self, shader: &'a CopyShader) -> () {
         debug_logi!("setting anim shader");
         self.paintstate.animshader = Some(shader);
+        self.paintstate.animating = shader.source != self.undo_shader.source;
     }
 
     pub fn set_point_shader(&mut self, shader: &'a PointShader) -> () {
@@ -262,6 +997,47 @@ impl<'a> GLInit<'a> {
         self.paintstate.brush_size = size;
     }
 
+    pub fn set_eraser_mode(&mut self, mode: EraserMode) {
+        self.paintstate.eraser_mode = mode;
+    }
+
+    pub fn set_stroke_filter(&mut self, filter: StrokeFilter) {
+        self.paintstate.stroke_filter = filter;
+    }
+
+    /// Not part of the paint state: predictions never reach the canvas, so sessions don't need
+    /// to know about them.
+    pub fn set_prediction(&mut self, horizon: f32) {
+        self.prediction_horizon = if horizon > 0f32 { horizon } else { 0f32 };
+        if self.prediction_horizon == 0f32 {
+            self.prediction_target = None;
+        }
+    }
+
+    /// Like predictions, the view is left out of the paint state; recorded points have already
+    /// been mapped onto the canvas.  Transforms that can't be undone are ignored.
+    pub fn set_view(&mut self, view: Affine) {
+        if view.invert().is_some() {
+            self.view = view;
+        }
+    }
+
+    pub fn get_view(&self) -> Affine {
+        self.view
+    }
+
+    /// The copy shader matrix that shows the canvas through the view, taking screen texture
+    /// coordinates to canvas ones.  Anything outside the canvas shows its edge pixels.
+    fn present_matrix(&self) -> matrix::Matrix {
+        let ((w, h), (sw, sh)) = (self.dimensions, self.surface);
+        let (w, h, sw, sh) = (w as f32, h as f32, sw as f32, sh as f32);
+        // texture coordinates start at the bottom left, and pixels at the top left
+        let screen_from_texture = Affine { a: sw, d: -sh, ty: sh, .. Affine::identity() };
+        let texture_from_canvas = Affine { a: 1f32 / w, d: -1f32 / h, ty: 1f32, .. Affine::identity() };
+        let view_inverse = self.view.invert().unwrap_or(Affine::identity());
+        screen_from_texture.then(&view_inverse).then(&texture_from_canvas).to_matrix()
+    }
+
     pub fn set_brush_color(&mut self, color: i32) {
         self.paintstate.brush_color[0] = (((color & 0x00ff0000) >> 16) as f32) / 255f32;
         self.paintstate.brush_color[1] = (((color & 0x0000ff00) >> 8) as f32) / 255f32;
@@ -270,26 +1046,61 @@ impl<'a> GLInit<'a> {
 
     pub fn add_layer(&mut self, layer: PaintLayer<'a>) -> () {
         debug_logi!("adding layer");
-        let extra: i32 = (layer.pointidx as i32 + 1) - self.points.len() as i32;
+        self.ensure_points(layer.pointidx);
+        self.paintstate.layers.push(layer);
+    }
+
+    fn ensure_points(&mut self, pointidx: i32) {
+        let extra: i32 = (pointidx as i32 + 1) - self.points.len() as i32;
         if extra > 0 {
             //self.points.extend(iter::repeat(Vec::new()).take(extra as usize));
             self.points.extend(iter::range(0, extra).map(|_| Vec::new()));
         }
-        self.paintstate.layers.push(layer);
+    }
+
+    /// Changes how a layer is composited.  Layers are numbered the way erase_layer numbers them,
+    /// so the first layer is 1.
+    pub fn set_layer_style(&mut self, layer: i32, style: LayerStyle) -> GLResult<()> {
+        let count = self.paintstate.layers.len();
+        match self.paintstate.layers.as_mut_slice().get_mut((layer - 1) as usize) {
+            Some(target) if layer > 0 => {
+                debug_logi!("setting layer {} style to {:?}", layer, style);
+                if target.style.is_shown() != style.is_shown() {
+                    // what it covers has to be copied down, or has been kept back
+                    add_dirty(&mut self.layers_dirty, PixelRect::whole(self.dimensions));
+                }
+                target.style = style;
+                Ok(())
+            },
+            _ => Err(format!("tried to style layer {} of {}", layer, count).into_cow()),
+        }
     }
 
     pub fn clear_layers(&mut self) {
         debug_logi!("setting layer count to 0");
+        // the layers' planes in undo history are empty from now on
+        let whole = PixelRect::whole(self.dimensions);
+        for plane in range(1, self.paintstate.layers.len() + 1) {
+            self.paintstate.undo_targets.mark_dirty(plane, whole);
+        }
         self.paintstate.layers.clear();
         self.points.truncate(1);
     }
 
     #[inline]
     pub fn erase_layer(&mut self, layer: i32) -> GLResult<()> {
+        let whole = PixelRect::whole(self.dimensions);
         let target = match layer {
-            0 => self.targetdata.get_current_texturetarget().framebuffer,
+            0 => {
+                self.paintstate.undo_targets.mark_dirty(0, whole);
+                self.targetdata.get_current_texturetarget().framebuffer
+            },
             _ => match self.paintstate.layers.as_slice().get((layer - 1) as usize) {
-                Some(layer) => layer.target.framebuffer,
+                Some(target) => {
+                    add_dirty(&mut self.layers_dirty, whole);
+                    self.paintstate.undo_targets.mark_dirty(layer as usize, whole);
+                    target.target.framebuffer
+                },
                 None => return Err(format!("tried to erase layer {} of {}", layer - 1, self.paintstate.layers.len()).into_cow()),
             },
         };
@@ -299,13 +1110,114 @@ impl<'a> GLInit<'a> {
         Ok(())
     }
 
+    /// The index into paintstate.layers of a layer numbered the way erase_layer numbers them.
+    fn layer_index(&self, layer: i32, action: &str) -> GLResult<usize> {
+        let count = self.paintstate.layers.len();
+        if layer > 0 && layer as usize <= count {
+            Ok((layer - 1) as usize)
+        } else {
+            Err(format!("tried to {} layer {} of {}", action, layer, count).into_cow())
+        }
+    }
+
+    /// Notes that every plane from the given one up has moved, and drops point queues that no
+    /// layer draws from any more.  count is the most planes there were before or after the move.
+    fn restack_layers(&mut self, from_plane: usize, count: usize) {
+        let whole = PixelRect::whole(self.dimensions);
+        for plane in range(from_plane, count) {
+            self.paintstate.undo_targets.mark_dirty(plane, whole);
+        }
+        let queues = self.paintstate.layers.iter().fold(1, |queues, layer| cmp::max(queues, layer.pointidx as usize + 1));
+        self.points.truncate(queues);
+    }
+
+    /// Throws away a layer and whatever's on it.  The layers above move down one.
+    pub fn remove_layer(&mut self, layer: i32) -> GLResult<()> {
+        let idx = try!(self.layer_index(layer, "remove"));
+        debug_logi!("removing layer {}", layer);
+        let count = self.paintstate.layers.len() + 1;
+        self.paintstate.layers.remove(idx);
+        self.restack_layers(idx + 1, count);
+        Ok(())
+    }
+
+    /// Moves a layer so it ends up numbered to, shifting the ones in between along by one.
+    pub fn move_layer(&mut self, from: i32, to: i32) -> GLResult<()> {
+        let from_idx = try!(self.layer_index(from, "move"));
+        let to_idx = try!(self.layer_index(to, "move to"));
+        debug_logi!("moving layer {} to {}", from, to);
+        let layer = self.paintstate.layers.remove(from_idx);
+        self.paintstate.layers.insert(to_idx, layer);
+        let count = self.paintstate.layers.len() + 1;
+        self.restack_layers(cmp::min(from_idx, to_idx) + 1, count);
+        Ok(())
+    }
+
+    /// Composites a layer into the one below it, or onto the canvas if it's the first, the same
+    /// way copy_layers_down would, and then removes it.  A hidden layer merges as nothing.
+    pub fn merge_layer_down(&mut self, layer: i32) -> GLResult<()> {
+        let idx = try!(self.layer_index(layer, "merge"));
+        let copy_shader = match self.paintstate.copyshader {
+            Some(copy_shader) => copy_shader,
+            None => return Err(format!("tried to merge layer {} without a copy shader", layer).into_cow()),
+        };
+        debug_logi!("merging layer {} down", layer);
+        let dimensions = self.dimensions;
+        {
+            let layers = self.paintstate.layers.as_slice();
+            let dest = match idx {
+                0 => self.targetdata.get_current_texturetarget(),
+                _ => &layers[idx - 1].target,
+            };
+            let source = &layers[idx];
+            let (w, h) = dimensions;
+            gl2::viewport(0, 0, w, h);
+            composite_layer(dest.framebuffer, source, source.copyshader.unwrap_or(copy_shader), matrix::IDENTITY.as_slice(),
+                            dimensions, &mut self.opacity_target, self.undo_shader);
+        }
+        if idx > 0 {
+            // what's on the layer below hasn't been copied down yet either
+            add_dirty(&mut self.layers_dirty, PixelRect::whole(dimensions));
+        }
+        let count = self.paintstate.layers.len() + 1;
+        self.paintstate.layers.remove(idx);
+        self.restack_layers(idx, count);
+        Ok(())
+    }
+
+    /// Adds a copy of a layer, pixels, shaders and style included, just above it.
+    pub fn duplicate_layer(&mut self, layer: i32) -> GLResult<()> {
+        let idx = try!(self.layer_index(layer, "duplicate"));
+        debug_logi!("duplicating layer {}", layer);
+        let copy = {
+            let source = &self.paintstate.layers[idx];
+            let mut copy = PaintLayer::new(self.dimensions, source.copyshader, source.pointshader, source.pointidx);
+            copy.style = source.style;
+            copy_target(&copy.target, &source.target, self.undo_shader);
+            copy
+        };
+        gl2::blend_func(gl2::ONE, gl2::ONE_MINUS_SRC_ALPHA);
+        self.paintstate.layers.insert(idx + 1, copy);
+        add_dirty(&mut self.layers_dirty, PixelRect::whole(self.dimensions));
+        let count = self.paintstate.layers.len() + 1;
+        self.restack_layers(idx + 2, count);
+        Ok(())
+    }
+
+    /// Sets up a canvas the same size as the surface.
     pub fn setup_graphics(w: i32, h: i32, events: &mut Events<'a>) -> GLInit<'a> {
+        GLInit::setup_graphics_with_canvas((w, h), (w, h), events)
+    }
+
+    pub fn setup_graphics_with_canvas(surface: (i32, i32), canvas: (i32, i32), events: &mut Events<'a>) -> GLInit<'a> {
         print_gl_string("Version", gl2::VERSION);
         print_gl_string("Vendor", gl2::VENDOR);
         print_gl_string("Renderer", gl2::RENDERER);
         print_gl_string("Extensions", gl2::EXTENSIONS);
 
-        debug_logi!("setupGraphics({},{})", w, h);
+        let (w, h) = canvas;
+        debug_logi!("setupGraphics({},{}) on a {:?} surface", w, h, surface);
+        events.set_canvas(canvas);
         let targets = [TextureTarget::new(w, h, PixelFormat::RGBA), TextureTarget::new(w, h, PixelFormat::RGBA)];
         let mut points: Vec<Vec<ShaderPaintPoint>> = Vec::new();
 
@@ -319,6 +1231,7 @@ impl<'a> GLInit<'a> {
         points.push(Vec::new());
         let data = GLInit {
             dimensions: (w, h),
+            surface: surface,
             targetdata: TargetData {
                 targets: targets,
                 current_target: 0,
@@ -326,6 +1239,15 @@ impl<'a> GLInit<'a> {
             points: points,
             paintstate: paintstate,
             undo_shader: outputshader,
+            placed_shader: builtin_copy_shader(include_str!("../includes/shaders/placed_copy.fsh")),
+            opaque_export_shader: builtin_copy_shader(include_str!("../includes/shaders/noalpha_copy.fsh")),
+            straight_export_shader: builtin_copy_shader(include_str!("../includes/shaders/unpremultiply_copy.fsh")),
+            prediction_horizon: 0f32,
+            prediction_target: None,
+            prediction_points: Vec::new(),
+            view: fit_view(canvas, surface),
+            layers_dirty: None,
+            opacity_target: None,
         };
 
         gl2::viewport(0, 0, w, h);
@@ -335,7 +1257,7 @@ impl<'a> GLInit<'a> {
         data
     }
 
-    pub fn unload_interpolator(&mut self, handler: &mut MotionEventConsumer, events: &'a mut Events<'a>, undo_callback: &JNICallbackClosure) -> GLResult<()> {
+    pub fn unload_interpolator(&mut self, handler: &mut MotionEventConsumer, events: &'a mut Events<'a>, undo_callback: &Fn(i32)) -> GLResult<()> {
         if let Some(interpolator) = self.paintstate.interpolator {
             debug_logi!("finishing {:?}", interpolator);
             unsafe {
@@ -347,22 +1269,125 @@ impl<'a> GLInit<'a> {
         }
     }
 
+    /// Saves the canvas, every layer, and how the layers are set up.
     pub fn push_undo_frame(&mut self) -> i32 {
-        let source = self.targetdata.get_current_texturetarget(); // should be identical when called from within lua callback
-        self.paintstate.undo_targets.push_new_buffer(source, self.undo_shader);
-        self.paintstate.undo_targets.len
+        let layers = self.paintstate.layers.iter().map(LayerSettings::of).collect();
+        {
+            // should be identical when called from within lua callback
+            let planes = collect_planes(self.targetdata.get_current_texturetarget(), self.paintstate.layers.as_slice());
+            self.paintstate.undo_targets.push_new_buffer(planes.as_slice(), layers, self.undo_shader);
+        }
+        self.paintstate.undo_targets.len()
     }
 
+    /// Restores the canvas and layers saved by push_undo_frame, setting the layers up again if
+    /// they've changed since.
     pub fn load_undo_frame(&mut self, idx: i32) {
-        let source = self.targetdata.get_current_texturetarget();
-        self.paintstate.undo_targets.load_buffer_at(idx, source, self.undo_shader);
+        let settings = {
+            let planes = collect_planes(self.targetdata.get_current_texturetarget(), self.paintstate.layers.as_slice());
+            match self.paintstate.undo_targets.load_buffer_at(idx, planes.as_slice(), self.undo_shader) {
+                Some(settings) => settings,
+                None => return,
+            }
+        };
+        let shader = self.undo_shader;
+        self.paintstate.layers.truncate(settings.len());
+        for (i, saved) in settings.into_iter().enumerate() {
+            self.ensure_points(saved.pointidx);
+            if let Some(layer) = self.paintstate.layers.get_mut(i) {
+                // the pixels came back with the rest of the planes
+                layer.copyshader = saved.copyshader;
+                layer.pointshader = saved.pointshader;
+                layer.pointidx = saved.pointidx;
+                layer.style = saved.style;
+                continue;
+            }
+            let mut layer = PaintLayer::new(self.dimensions, saved.copyshader, saved.pointshader, saved.pointidx);
+            layer.style = saved.style;
+            if let Some(plane) = self.paintstate.undo_targets.plane(i + 1) {
+                copy_target(&layer.target, plane, shader);
+            }
+            self.paintstate.layers.push(layer);
+        }
+        if self.paintstate.layers.len() > 0 {
+            // the layers came back with whatever hadn't been copied down when they were saved
+            add_dirty(&mut self.layers_dirty, PixelRect::whole(self.dimensions));
+        }
+        gl2::blend_func(gl2::ONE, gl2::ONE_MINUS_SRC_ALPHA);
     }
 
     pub fn clear_undo_frames(&mut self) {
         self.paintstate.undo_targets.clear_buffers();
     }
 
-    pub fn draw_queued_points(&mut self, handler: &mut MotionEventConsumer, events: &'a mut Events<'a>, matrix: &matrix::Matrix, undo_callback: &JNICallbackClosure) -> GLResult<()> {
+    /// The index of the undo frame the canvas was last saved to or loaded from, or -1.
+    pub fn undo_position(&self) -> i32 {
+        self.paintstate.undo_targets.position()
+    }
+
+    /// The frame undo would load, if there is one.
+    pub fn undo_target(&self) -> Option<i32> {
+        let pos = self.undo_position();
+        if pos > 0 { Some(pos - 1) } else { None }
+    }
+
+    /// The frame redo would load, if there is one.
+    pub fn redo_target(&self) -> Option<i32> {
+        let pos = self.undo_position();
+        if pos + 1 < self.paintstate.undo_targets.len() { Some(pos + 1) } else { None }
+    }
+
+    pub fn set_undo_budget(&mut self, budget: UndoBudget) {
+        self.paintstate.undo_targets.set_budget(budget);
+    }
+
+    /// Copies the base canvas, followed by each layer, into new targets.
+    pub fn snapshot_targets(&self) -> Vec<TextureTarget> {
+        let base = self.targetdata.get_current_texturetarget();
+        let (w, h) = base.texture.dimensions;
+        let mut snapshot = Vec::with_capacity(self.paintstate.layers.len() + 1);
+        let copy = TextureTarget::new(w, h, PixelFormat::RGBA);
+        copy_target(&copy, base, self.undo_shader);
+        snapshot.push(copy);
+        for layer in self.paintstate.layers.iter() {
+            let copy = TextureTarget::new(w, h, PixelFormat::RGBA);
+            copy_target(&copy, &layer.target, self.undo_shader);
+            snapshot.push(copy);
+        }
+        gl2::blend_func(gl2::ONE, gl2::ONE_MINUS_SRC_ALPHA);
+        snapshot
+    }
+
+    /// Restores targets saved by snapshot_targets.  The canvas and layers must already be set up
+    /// the way they were when the snapshot was taken.
+    pub fn restore_targets(&mut self, snapshot: &[TextureTarget]) -> GLResult<()> {
+        if snapshot.len() != self.paintstate.layers.len() + 1 {
+            return Err(format!("snapshot has {} layers, but there are {}", snapshot.len() - 1, self.paintstate.layers.len()).into_cow());
+        }
+        if snapshot[0].texture.dimensions != self.dimensions {
+            return Err(format!("snapshot is {:?}, but the canvas is {:?}", snapshot[0].texture.dimensions, self.dimensions).into_cow());
+        }
+        for target in self.targetdata.targets.iter() {
+            copy_target(target, &snapshot[0], self.undo_shader);
+        }
+        for (layer, saved) in self.paintstate.layers.iter().zip(snapshot.slice_from(1).iter()) {
+            copy_target(&layer.target, saved, self.undo_shader);
+        }
+        let whole = PixelRect::whole(self.dimensions);
+        for plane in range(0, snapshot.len()) {
+            self.paintstate.undo_targets.mark_dirty(plane, whole);
+        }
+        add_dirty(&mut self.layers_dirty, whole);
+        gl2::blend_func(gl2::ONE, gl2::ONE_MINUS_SRC_ALPHA);
+        Ok(())
+    }
+
+    pub fn draw_queued_points(&mut self, handler: &mut MotionEventConsumer, events: &'a mut Events<'a>, matrix: &matrix::Matrix, undo_callback: &Fn(i32)) -> GLResult<()> {
+        if let Some(change) = handler.take_view_change() {
+            self.view = self.view.then(&change.to_affine());
+        }
+        // points come in screen coordinates, but are drawn (and recorded) in canvas ones
+        handler.set_view_inverse(self.view.invert().unwrap_or(Affine::identity()));
         match (self.paintstate.pointshader, self.paintstate.copyshader, self.paintstate.brush) {
             (Some(point_shader), Some(copy_shader), Some(brush)) => {
                 let interp_error = match self.paintstate.interpolator {
@@ -382,26 +1407,60 @@ impl<'a> GLInit<'a> {
 
                 let back_buffer = &source.texture;
                 let drawvecs = self.points.as_mut_slice();
+                // the matrix is for surface pixels, but points have been mapped onto the canvas
+                let ((w, h), (sw, sh)) = (self.dimensions, self.surface);
+                let canvasmatrix = Affine::scale(sw as f32 / w as f32, sh as f32 / h as f32).to_matrix();
+                let matrix = matrix::multiply(matrix, &canvasmatrix);
                 let matrix = matrix.as_slice();
                 let color = self.paintstate.brush_color;
                 let size = self.paintstate.brush_size;
+                let eraser = self.paintstate.eraser_mode;
+                if let Some(rect) = points_extent(drawvecs[0].as_slice(), point_shader, size, matrix, (w, h)) {
+                    self.paintstate.undo_targets.mark_dirty(0, rect);
+                }
                 let baselayer = CompletedLayer {
                     copyshader: copy_shader,
                     pointshader: point_shader,
                     target: target,
                 };
-                draw_layer(baselayer, matrix, color, size, brush, back_buffer, drawvecs[0].as_slice());
+                draw_layer(baselayer, matrix, color, size, brush, back_buffer, drawvecs[0].as_slice(), eraser);
 
-                for layer in self.paintstate.layers.iter() {
+                for (i, layer) in self.paintstate.layers.iter().enumerate() {
                     let completed = layer.complete(copy_shader, point_shader);
                     let points = drawvecs[layer.pointidx as usize].as_slice();
-                    draw_layer(completed, matrix, color, size, brush, back_buffer, points);
+                    if let Some(rect) = points_extent(points, completed.pointshader, size, matrix, (w, h)) {
+                        add_dirty(&mut self.layers_dirty, rect);
+                        self.paintstate.undo_targets.mark_dirty(i + 1, rect);
+                    }
+                    draw_layer(completed, matrix, color, size, brush, back_buffer, points, eraser);
                 }
 
                 for drawvec in drawvecs.iter_mut() {
                     drawvec.clear();
                 }
 
+                if self.prediction_horizon > 0f32 {
+                    if self.prediction_target.is_none() {
+                        let (w, h) = target.texture.dimensions;
+                        self.prediction_target = Some(TextureTarget::new(w, h, PixelFormat::RGBA));
+                    }
+                    let prediction = self.prediction_target.as_ref().unwrap();
+                    gl2::bind_framebuffer(gl2::FRAMEBUFFER, prediction.framebuffer);
+                    gl2::clear_color(0f32, 0f32, 0f32, 0f32);
+                    gl2::clear(gl2::COLOR_BUFFER_BIT);
+                    // interpolators can't be asked about points that haven't happened, so this
+                    // is only ever a straight line with the base point shader
+                    let predicted = &mut self.prediction_points;
+                    glpoint::push_predictions(handler, self.prediction_horizon, predicted);
+                    let predictionlayer = CompletedLayer {
+                        copyshader: copy_shader,
+                        pointshader: point_shader,
+                        target: prediction,
+                    };
+                    draw_layer(predictionlayer, matrix, color, size, brush, back_buffer, predicted.as_slice(), eraser);
+                    predicted.clear();
+                }
+
                 interp_error
             },
             _ => { Ok(()) }
@@ -410,22 +1469,97 @@ impl<'a> GLInit<'a> {
 
     pub fn copy_layers_down(&mut self) {
         if let (Some(copy_shader), Some(point_shader)) = (self.paintstate.copyshader, self.paintstate.pointshader) {
+            // only what's been drawn since the last time needs copying
+            let dimensions = self.dimensions;
+            let dirty = match self.layers_dirty.take().and_then(|dirty| dirty.clip(dimensions)) {
+                Some(dirty) => dirty,
+                None => return,
+            };
+            for plane in range(0, self.paintstate.layers.len() + 1) {
+                self.paintstate.undo_targets.mark_dirty(plane, dirty);
+            }
             let copymatrix = matrix::IDENTITY.as_slice();
             let target = self.targetdata.get_current_texturetarget();
             gl2::enable(gl2::BLEND);
             gl2::blend_func(gl2::ONE, gl2::ONE_MINUS_SRC_ALPHA);
+            gl2::enable(gl2::SCISSOR_TEST);
+            gl2::scissor(dirty.x, dirty.y, dirty.width, dirty.height);
+            let mut kept = false;
             for layer in self.paintstate.layers.iter() {
+                if !layer.style.is_shown() {
+                    // hidden layers stay where they are until they're shown again
+                    kept = true;
+                    continue;
+                }
                 let completed = layer.complete(copy_shader, point_shader);
-                perform_copy(target.framebuffer, &layer.target.texture, completed.copyshader, copymatrix);
+                composite_layer(target.framebuffer, layer, completed.copyshader, copymatrix,
+                                dimensions, &mut self.opacity_target, self.undo_shader);
                 gl2::bind_framebuffer(gl2::FRAMEBUFFER, layer.target.framebuffer);
                 gl2::clear_color(0f32, 0f32, 0f32, 0f32);
                 gl2::clear(gl2::COLOR_BUFFER_BIT);
                 debug_logi!("copied brush layer down");
             }
+            if kept {
+                self.layers_dirty = Some(dirty);
+            }
+            gl2::disable(gl2::SCISSOR_TEST);
+        }
+    }
+
+    /// Puts the paint state back the way setup_graphics left it, so a replay starts from the
+    /// same place the recorded session did.  canvas is the size the session started at, if it's
+    /// known; the canvas is reallocated at that size, so recorded reframes apply to what they did.
+    pub fn reset_paintstate(&mut self, canvas: Option<(i32, i32)>) {
+        if let Some((w, h)) = canvas {
+            if (w, h) != self.dimensions {
+                debug_logi!("resetting canvas to {}x{}", w, h);
+                self.targetdata.targets = [TextureTarget::new(w, h, PixelFormat::RGBA), TextureTarget::new(w, h, PixelFormat::RGBA)];
+                self.prediction_target = None;
+                self.opacity_target = None;
+                self.dimensions = (w, h);
+                gl2::viewport(0, 0, w, h);
+                unsafe { let _ = lua_geom::ensure_lua_exists(w, h); }
+            }
+            self.view = fit_view(self.dimensions, self.surface);
+        }
+        self.layers_dirty = None;
+        let mut paintstate = PaintState::new();
+        paintstate.copyshader = Some(self.undo_shader);
+        mem::swap(&mut paintstate.undo_targets, &mut self.paintstate.undo_targets);
+        paintstate.undo_targets.clear_buffers();
+        self.paintstate = paintstate;
+        self.points.truncate(1);
+        self.targetdata.current_target = 0;
+        self.clear_buffer();
+    }
+
+    /// Resizes or crops the canvas, along with every layer and undo buffer, keeping their
+    /// contents.  The view goes back to showing the whole canvas.
+    pub fn reframe_canvas(&mut self, reframe: Reframe) {
+        debug_logi!("reframing canvas: {:?}", reframe);
+        let shader = self.undo_shader;
+        let targets = [reframe_target(&self.targetdata.targets[0], &reframe, shader),
+                       reframe_target(&self.targetdata.targets[1], &reframe, shader)];
+        self.targetdata.targets = targets;
+        for layer in self.paintstate.layers.iter_mut() {
+            let target = reframe_target(&layer.target, &reframe, shader);
+            layer.target = target;
+        }
+        self.paintstate.undo_targets.reframe(&reframe, shader);
+        if self.layers_dirty.is_some() {
+            self.layers_dirty = Some(PixelRect::whole((reframe.width, reframe.height)));
         }
+        self.prediction_target = None;
+        self.opacity_target = None;
+        self.dimensions = (reframe.width, reframe.height);
+        self.view = fit_view(self.dimensions, self.surface);
+        gl2::viewport(0, 0, reframe.width, reframe.height);
+        gl2::blend_func(gl2::ONE, gl2::ONE_MINUS_SRC_ALPHA);
+        unsafe { let _ = lua_geom::ensure_lua_exists(reframe.width, reframe.height); }
     }
 
     pub fn clear_buffer(&mut self) {
+        self.paintstate.undo_targets.mark_dirty(0, PixelRect::whole(self.dimensions));
         for target in self.targetdata.targets.iter() {
             gl2::bind_framebuffer(gl2::FRAMEBUFFER, target.framebuffer);
             gl2::clear_color(0f32, 0f32, 0f32, 0f32);
@@ -439,14 +1573,29 @@ impl<'a> GLInit<'a> {
             (Some(copy_shader), Some(anim_shader)) => {
                 self.targetdata.current_target = self.targetdata.current_target ^ 1;
                 let copymatrix = matrix::IDENTITY.as_slice();
+                let presentmatrix = self.present_matrix();
+                let presentmatrix = presentmatrix.as_slice();
                 gl2::disable(gl2::BLEND);
+                // the copy still has to happen for a plain anim shader, since point shaders read
+                // the previous frame from the other target
                 let (target, source) = self.targetdata.get_texturetargets();
                 perform_copy(target.framebuffer, &source.texture, anim_shader, copymatrix);
-                perform_copy(0 as GLuint, &target.texture, copy_shader, copymatrix);
+                if self.paintstate.animating {
+                    // animations can change any pixel, every frame
+                    self.paintstate.undo_targets.mark_dirty(0, PixelRect::whole(self.dimensions));
+                }
+                let ((w, h), (sw, sh)) = (self.dimensions, self.surface);
+                gl2::viewport(0, 0, sw, sh);
+                perform_copy(0 as GLuint, &target.texture, copy_shader, presentmatrix);
                 gl2::enable(gl2::BLEND);
                 for layer in self.paintstate.layers.iter() {
-                    perform_copy(0 as GLuint, &layer.target.texture, layer.copyshader.unwrap_or(copy_shader), copymatrix);
+                    composite_layer(0 as GLuint, layer, layer.copyshader.unwrap_or(copy_shader), presentmatrix,
+                                    (sw, sh), &mut self.opacity_target, self.undo_shader);
+                }
+                if let Some(ref prediction) = self.prediction_target {
+                    perform_copy(0 as GLuint, &prediction.texture, copy_shader, presentmatrix);
                 }
+                gl2::viewport(0, 0, w, h);
                 eglinit::egl_swap();
             },
             (x, y) => {
@@ -494,3 +1643,84 @@ fn test_all() {
         //}
     }
 }
+
+#[test]
+fn dirty_rects_round_out_to_tiles() {
+    let point = PixelRect::around_point(Coordinate { x: 100f32, y: 10f32 }, 20f32, 200);
+    assert_eq!(point, PixelRect { x: 90, y: 180, width: 20, height: 20 });
+    let tiles = point.to_tiles((300, 200)).unwrap();
+    assert_eq!(tiles, PixelRect { x: 64, y: 128, width: 64, height: 72 });
+    let offcanvas = PixelRect { x: -30, y: -30, width: 10, height: 10 };
+    assert_eq!(offcanvas.to_tiles((300, 200)), None);
+    assert_eq!(point.union(&offcanvas), PixelRect { x: -30, y: -30, width: 140, height: 230 });
+}
+
+#[test]
+fn dirty_rects_clip_to_the_canvas() {
+    let partly = PixelRect { x: -5, y: -5, width: 10, height: 10 };
+    assert_eq!(partly.clip((8, 8)), Some(PixelRect { x: 0, y: 0, width: 5, height: 5 }));
+    assert_eq!(PixelRect { x: 10, y: 10, width: 2, height: 2 }.clip((8, 8)), None);
+    assert_eq!(PixelRect::whole((8, 8)).clip((8, 8)), Some(PixelRect::whole((8, 8))));
+    // tiles round away from zero on both sides, and stop at the canvas edge
+    let straddling = PixelRect { x: -10, y: 90, width: 20, height: 20 };
+    assert_eq!(straddling.to_tiles((100, 100)), Some(PixelRect { x: 0, y: 64, width: 64, height: 36 }));
+    assert_eq!(PixelRect { x: 70, y: 10, width: 5, height: 5 }.to_tiles((200, 100)),
+               Some(PixelRect { x: 64, y: 0, width: 64, height: 64 }));
+}
+
+#[test]
+fn dirty_rects_follow_reframes() {
+    let rect = PixelRect { x: 64, y: 0, width: 64, height: 64 };
+    // cropping 32 pixels off the left and bottom of a 256x256 canvas
+    let crop = Reframe::crop((256, 256), 32, 0, 224, 224);
+    assert_eq!(rect.reframe((256, 256), &crop), Some((PixelRect { x: 32, y: 0, width: 64, height: 32 },
+                                                       PixelRect { x: 0, y: -32, width: 64, height: 64 })));
+    let half = Reframe::scale(128, 128);
+    assert_eq!(rect.reframe((256, 256), &half), Some((PixelRect { x: 32, y: 0, width: 32, height: 32 },
+                                                       PixelRect { x: 0, y: 0, width: 32, height: 32 })));
+    let away = Reframe::crop((256, 256), 0, 0, 32, 32);
+    assert_eq!(rect.reframe((256, 256), &away), None);
+}
+
+#[test]
+fn undo_budget_forgets_the_oldest_snapshots_first() {
+    // compressed changes only, so nothing here needs gl
+    let compressed = |bytes: usize| UndoSnapshot::Compressed((1, 1), iter::repeat(0u8).take(bytes).collect());
+    let mut history = UndoHistory::new();
+    for i in range(0, 4) {
+        let mut deltas = Vec::new();
+        if i > 0 {
+            deltas.push(Some(UndoDelta { rect: PixelRect::whole((1, 1)), before: compressed(i as usize * 10), after: compressed(i as usize * 10) }));
+        }
+        history.entries.push(UndoEntry { layers: Vec::new(), deltas: deltas });
+    }
+    history.pos = 4;
+    assert_eq!(history.compressed_total(), 120);
+
+    history.set_budget(UndoBudget { gpu_bytes: 0, cpu_bytes: 60 });
+    // dropping the first snapshot also drops the second one's changes, leaving 100 bytes;
+    // dropping the second leaves 60, which fits
+    assert_eq!(history.len(), 2);
+    assert_eq!(history.position(), 1);
+    assert!(history.entries[0].deltas.is_empty());
+    assert_eq!(history.compressed_total(), 60);
+
+    // the current snapshot stays, however small the budget
+    history.set_budget(UndoBudget { gpu_bytes: 0, cpu_bytes: 0 });
+    assert_eq!(history.len(), 1);
+    assert_eq!(history.position(), 0);
+    assert_eq!(history.compressed_total(), 0);
+}
+
+#[test]
+fn placed_images_map_onto_the_canvas() {
+    // a 10x20 image scaled up twice and moved to (30, 40) on a 100x100 canvas
+    let placement = Affine::scale(2f32, 2f32).then(&Affine::translate(30f32, 40f32));
+    let matrix = image_texture_from_target(placement, (10, 20), (100, 100)).unwrap();
+    // the target's texture coordinates start at the bottom left of the canvas
+    let top_left = matrix.apply(Coordinate { x: 0.3f32, y: 0.6f32 });
+    assert!(top_left.x.abs() < 1e-5f32 && top_left.y.abs() < 1e-5f32);
+    let bottom_right = matrix.apply(Coordinate { x: 0.5f32, y: 0.2f32 });
+    assert!((bottom_right.x - 1f32).abs() < 1e-5f32 && (bottom_right.y - 1f32).abs() < 1e-5f32);
+    assert!(image_texture_from_target(Affine::scale(0f32, 1f32), (10, 20), (100, 100)).is_none());
+}
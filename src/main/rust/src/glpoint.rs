@@ -9,20 +9,47 @@ use alloc::boxed::Box;
 use std::sync::mpsc;
 
 use motionevent;
-use motionevent::append_motion_event;
-use android::input::AInputEvent;
+use motionevent::{append_motion_event, InputEvent};
 
 use point;
 use point::{ShaderPaintPoint, Coordinate, PointEntry, PointConsumer, PointProducer, PointInfo, ShaderPointEvent};
 use drawevent::Events;
-
-rolling_average_count!(RollingAverage16, 16);
+use smoothing::{Filter, StrokeFilter};
+use gesture::{GestureEvent, GestureSettings, Transform};
+use matrix::{Affine, wrap_angle};
 
 /// lifetime storage for a pointer's past state
 struct PointStorage {
     info: Option<ShaderPaintPoint>,
-    sizeavg: RollingAverage16<f32>,
-    speedavg: RollingAverage16<Coordinate>,
+    // the point before info, for prediction
+    previous: Option<ShaderPaintPoint>,
+    position: Filter<Coordinate>,
+    size: Filter<f32>,
+    speed: Filter<Coordinate>,
+    pressure: Filter<f32>,
+    tilt: Filter<f32>,
+    // filtered as unit vectors, so angles on either side of +/-pi don't cancel out
+    orientation: Filter<Coordinate>,
+}
+
+impl PointStorage {
+    fn new(filter: &StrokeFilter) -> PointStorage {
+        PointStorage {
+            info: None,
+            previous: None,
+            position: Filter::new(filter.position),
+            size: Filter::new(filter.size),
+            speed: Filter::new(filter.speed),
+            pressure: Filter::new(filter.size),
+            tilt: Filter::new(filter.size),
+            orientation: Filter::new(filter.speed),
+        }
+    }
+}
+
+#[inline]
+fn direction(angle: f32) -> Coordinate {
+    Coordinate { x: angle.cos(), y: angle.sin() }
 }
 
 pub struct MotionEventConsumer {
@@ -30,37 +57,90 @@ pub struct MotionEventConsumer {
     current_points: VecMap<PointStorage>,
     point_counter: i32,
     point_count: i32,
+    view_changes: mpsc::Receiver<Transform>,
+    // takes incoming screen coordinates onto the canvas, or None if they're already there
+    view_inverse: Option<Affine>,
+}
+
+impl MotionEventConsumer {
+    /// number of pointers currently down, as far as drawing has got
+    pub fn active_pointers(&self) -> i32 {
+        self.point_count
+    }
+
+    /// Everything gestures have done to the view since the last call, combined into one.
+    pub fn take_view_change(&mut self) -> Option<Transform> {
+        let mut change = None;
+        while let Ok(next) = self.view_changes.try_recv() {
+            change = Some(change.map_or(next, |t: Transform| t.then(&next)));
+        }
+        change
+    }
+
+    /// Has no effect on replay handlers.
+    pub fn set_view_inverse(&mut self, view_inverse: Affine) {
+        if self.view_inverse.is_some() {
+            self.view_inverse = Some(view_inverse);
+        }
+    }
 }
 
 pub struct MotionEventProducer {
     pub producer: PointProducer,
     pointer_data: motionevent::Data,
+    view_changes: mpsc::Sender<Transform>,
 }
 
 pub fn create_motion_event_handler(left_edge: i32) -> (MotionEventConsumer, MotionEventProducer) {
     let (producer, consumer) = mpsc::channel::<PointEntry>();
+    let (view_producer, view_consumer) = mpsc::channel::<Transform>();
     let handler = MotionEventConsumer {
         consumer: consumer,
         current_points: VecMap::new(),
         point_counter: 0, // unique value for each new pointer
         point_count: 0, // # of currently active pointers
+        view_changes: view_consumer,
+        view_inverse: Some(Affine::identity()),
     };
     let producer = MotionEventProducer {
         producer: producer,
         pointer_data: motionevent::Data::new(left_edge),
+        view_changes: view_producer,
     };
     debug_logi!("created motion event pair");
     (handler, producer)
 }
 
+/// A handler for recorded points, which are already in canvas coordinates.
+pub fn create_replay_handler() -> (MotionEventConsumer, MotionEventProducer) {
+    let (mut consumer, producer) = create_motion_event_handler(0);
+    consumer.view_inverse = None;
+    (consumer, producer)
+}
+
 pub unsafe fn destroy_motion_event_handler(consumer: Box<MotionEventConsumer>, producer: Box<MotionEventProducer>) {
     mem::drop(consumer);
     mem::drop(producer);
 }
 
-//FIXME: needs meaningful name
-pub fn jni_append_motion_event(s: &mut MotionEventProducer, evt: *const AInputEvent) {
-    append_motion_event(&mut s.pointer_data, evt, &mut s.producer);
+/// Queues the event's points for drawing.  Gestures that change the view are passed along to
+/// the consumer; anything else a gesture asks for is returned, for the caller to act on.
+pub fn append_input_event<E: InputEvent>(s: &mut MotionEventProducer, evt: &E) -> Option<GestureEvent> {
+    match append_motion_event(&mut s.pointer_data, evt, &mut s.producer) {
+        Some(GestureEvent::Transform(change)) => {
+            let _ = s.view_changes.send(change);
+            None
+        },
+        other => other,
+    }
+}
+
+pub fn set_gestures(s: &mut MotionEventProducer, settings: Option<GestureSettings>) {
+    s.pointer_data.set_gestures(settings);
+}
+
+pub fn set_rejection_rules(s: &mut MotionEventProducer, rules: motionevent::RejectionRules) {
+    s.pointer_data.set_rejection_rules(rules);
 }
 
 pub fn jni_pause_motion_event(s: &mut MotionEventProducer) {
@@ -73,36 +153,48 @@ fn manhattan_distance(a: Coordinate, b: Coordinate) -> f32 {
     return if x > y { x } else { y };
 }
 
+/// Takes the next entry off the queue and turns it into a pointer event, smoothing it with the
+/// filters the pointer went down with.
 #[inline]
-pub fn next_point(s: &mut MotionEventConsumer, e: &mut Events) -> (point::ShaderPointEvent, u8) {
+pub fn next_point(s: &mut MotionEventConsumer, e: &mut Events, filter: &StrokeFilter) -> (point::ShaderPointEvent, u8) {
     let ref mut queue = s.consumer;
     let ref mut current_points = s.current_points;
     match queue.try_recv() {
-        Ok(point) => {
+        Ok(mut point) => {
+            if let (&mut PointInfo::Point(ref mut p), Some(view_inverse)) = (&mut point.entry, s.view_inverse) {
+                p.pos = view_inverse.apply(p.pos);
+            }
             e.pushpoint(point);
             let idx = point.index;
             let newpoint = point.entry;
-            if !current_points.contains_key(&(idx as usize)) {
-                current_points.insert(idx as usize, PointStorage {
-                    info: None,
-                    sizeavg: RollingAverage16::new(),
-                    speedavg: RollingAverage16::new(),
-                });
+            let starting = match (current_points.get(&(idx as usize)), newpoint) {
+                (None, _) => true,
+                (Some(&PointStorage { info: None, .. }), PointInfo::Point(_)) => true,
+                _ => false,
+            };
+            if starting {
+                current_points.insert(idx as usize, PointStorage::new(filter));
             }
             let oldpoint = current_points.get_mut(&(idx as usize)).unwrap();
             let pointevent = match (oldpoint.info, newpoint) {
                 (Some(op), PointInfo::Point(np)) => {
-                    let dist = manhattan_distance(op.pos, np.pos);
-                    let avgsize = oldpoint.sizeavg.push(np.size);
-                    let avgspeed = oldpoint.speedavg.push(op.pos - np.pos);
+                    let pos = oldpoint.position.push(np.pos, np.time);
+                    let dist = manhattan_distance(op.pos, pos);
+                    let heading = oldpoint.orientation.push(direction(np.orientation), np.time);
                     let npdata = ShaderPaintPoint {
-                        pos: np.pos,
+                        pos: pos,
                         time: np.time,
-                        size: avgsize,
-                        speed: avgspeed,
+                        size: oldpoint.size.push(np.size, np.time),
+                        speed: oldpoint.speed.push(op.pos - pos, np.time),
                         distance: op.distance + dist,
                         counter: op.counter,
+                        pressure: oldpoint.pressure.push(np.pressure, np.time),
+                        tilt: oldpoint.tilt.push(np.tilt, np.time),
+                        orientation: heading.y.atan2(heading.x),
+                        tool: point.tool as u8 as f32,
+                        buttons: point.buttons as f32,
                     };
+                    oldpoint.previous = Some(op);
                     oldpoint.info = Some(npdata);
                     ShaderPointEvent::Move(op, npdata)
                 },
@@ -117,10 +209,14 @@ pub fn next_point(s: &mut MotionEventConsumer, e: &mut Events) -> (point::Shader
                         speed: Coordinate { x: 0f32, y: 0f32 },
                         distance: 0f32,
                         counter: 0f32,
+                        pressure: 0f32,
+                        tilt: 0f32,
+                        orientation: 0f32,
+                        tool: 0f32,
+                        buttons: 0f32,
                     });
                     oldpoint.info = None;
-                    oldpoint.sizeavg.clear();
-                    oldpoint.speedavg.clear();
+                    oldpoint.previous = None;
                     s.point_count -= 1;
                     ShaderPointEvent::Up(op)
                 },
@@ -128,13 +224,20 @@ pub fn next_point(s: &mut MotionEventConsumer, e: &mut Events) -> (point::Shader
                     let old_counter = s.point_counter;
                     s.point_counter += 1;
                     s.point_count += 1;
+                    // prime the filters, so the first move is smoothed against the down point
+                    oldpoint.orientation.push(direction(p.orientation), p.time);
                     let npdata = ShaderPaintPoint {
-                        pos: p.pos,
+                        pos: oldpoint.position.push(p.pos, p.time),
                         time: p.time,
-                        size: p.size,
+                        size: oldpoint.size.push(p.size, p.time),
                         distance: 0f32,
                         speed: Coordinate { x: 0f32, y: 0f32 },
                         counter: old_counter as f32,
+                        pressure: oldpoint.pressure.push(p.pressure, p.time),
+                        tilt: oldpoint.tilt.push(p.tilt, p.time),
+                        orientation: p.orientation,
+                        tool: point.tool as u8 as f32,
+                        buttons: point.buttons as f32,
                     };
                     oldpoint.info = Some(npdata);
                     ShaderPointEvent::Down(npdata)
@@ -148,6 +251,25 @@ pub fn next_point(s: &mut MotionEventConsumer, e: &mut Events) -> (point::Shader
     }
 }
 
+/// Extrapolates each pointer that's still down `horizon` seconds past its latest point, at the
+/// speed it was last moving, and queues a line out to there.  Pointers that haven't moved yet
+/// aren't predicted.
+pub fn push_predictions(s: &MotionEventConsumer, horizon: f32, drawvec: &mut Vec<ShaderPaintPoint>) {
+    for (_, storage) in s.current_points.iter() {
+        if let (Some(latest), Some(previous)) = (storage.info, storage.previous) {
+            let interval = latest.time - previous.time;
+            if interval <= 0f32 {
+                continue;
+            }
+            let mut predicted = latest;
+            predicted.pos = latest.pos + (latest.pos - previous.pos) * (horizon / interval);
+            predicted.time = latest.time + horizon;
+            predicted.distance = latest.distance + manhattan_distance(latest.pos, predicted.pos);
+            push_line(drawvec, &latest, &predicted);
+        }
+    }
+}
+
 #[inline]
 fn get_count(a: &ShaderPaintPoint, b: &ShaderPaintPoint) -> i32 {
     let distx = if (*a).pos.x > (*b).pos.x { (*a).pos.x - (*b).pos.x } else { (*b).pos.x - (*a).pos.x };
@@ -174,6 +296,9 @@ pub fn push_line(drawvec: &mut Vec<ShaderPaintPoint>, a: &ShaderPaintPoint, b: &
     let stepspeedx = ((*b).speed.x - (*a).speed.x) / count as f32;
     let stepspeedy = ((*b).speed.y - (*a).speed.y) / count as f32;
     let stepdistance = ((*b).distance - (*a).distance) / count as f32;
+    let steppressure = ((*b).pressure - (*a).pressure) / count as f32;
+    let steptilt = ((*b).tilt - (*a).tilt) / count as f32;
+    let steporientation = angle_between((*a).orientation, (*b).orientation) / count as f32;
     let mut addpoint = *a;
     addpoint.time = (addpoint.time / timescale) % 1f32;
     for _ in range(0, count) {
@@ -186,9 +311,18 @@ pub fn push_line(drawvec: &mut Vec<ShaderPaintPoint>, a: &ShaderPaintPoint, b: &
         addpoint.speed.x += stepspeedx;
         addpoint.speed.y += stepspeedy;
         addpoint.distance += stepdistance;
+        addpoint.pressure += steppressure;
+        addpoint.tilt += steptilt;
+        addpoint.orientation = wrap_angle(addpoint.orientation + steporientation);
     }
 }
 
+/// Signed shortest turn from a to b, in radians.
+#[inline]
+fn angle_between(a: f32, b: f32) -> f32 {
+    wrap_angle(b - a)
+}
+
 #[inline]
 //pub fn push_catmullrom(drawvec: &mut Vec<ShaderPaintPoint>, a: &ShaderPaintPoint, b: ShaderPaintPoint, c: ShaderPaintPoint, d: ShaderPaintPoint) {
 pub fn push_catmullrom(drawvec: &mut Vec<ShaderPaintPoint>, points: &[ShaderPaintPoint; 4]) {
@@ -222,6 +356,9 @@ fn push_splinepts<T: Spline<Coordinate>>(drawvec: &mut Vec<ShaderPaintPoint>, po
     let stepspeedx = ((*b).speed.x - (*a).speed.x) / count as f32;
     let stepspeedy = ((*b).speed.y - (*a).speed.y) / count as f32;
     let stepdistance = ((*b).distance - (*a).distance) / count as f32;
+    let steppressure = ((*b).pressure - (*a).pressure) / count as f32;
+    let steptilt = ((*b).tilt - (*a).tilt) / count as f32;
+    let steporientation = angle_between((*a).orientation, (*b).orientation) / count as f32;
     addpoint.time = (addpoint.time / timescale) % 1f32;
 
     for _ in range(0, count) {
@@ -235,6 +372,9 @@ fn push_splinepts<T: Spline<Coordinate>>(drawvec: &mut Vec<ShaderPaintPoint>, po
         addpoint.speed.x += stepspeedx;
         addpoint.speed.y += stepspeedy;
         addpoint.distance += stepdistance;
+        addpoint.pressure += steppressure;
+        addpoint.tilt += steptilt;
+        addpoint.orientation = wrap_angle(addpoint.orientation + steporientation);
     }
 }
 
@@ -300,3 +440,27 @@ impl Spline<Coordinate> for CubicBezier {
         p0 + p1 + p2 + p3
     }
 }
+
+#[test]
+fn prediction_extrapolates_last_move() {
+    use point::{PaintPoint, PointInfo, ToolType};
+    let (mut consumer, producer) = create_motion_event_handler(0);
+    let mut events = Events::new();
+    let filter = StrokeFilter::default();
+    for &(x, time) in [(0f32, 0f32), (10f32, 0.01f32)].iter() {
+        let point = PaintPoint { pos: Coordinate { x: x, y: 0f32 }, time: time, size: 1f32, pressure: 1f32, tilt: 0f32, orientation: 0f32 };
+        let _ = producer.producer.send(PointEntry { index: 0, entry: PointInfo::Point(point), tool: ToolType::Finger, buttons: 0 });
+        next_point(&mut consumer, &mut events, &filter);
+    }
+    let mut predicted = Vec::new();
+    push_predictions(&consumer, 0.005f32, &mut predicted);
+    assert_eq!(predicted.len(), 5);
+    assert_eq!(predicted[0].pos.x, 10f32);
+    assert_eq!(predicted[4].pos.x, 14f32);
+
+    let _ = producer.producer.send(PointEntry::stop(0));
+    next_point(&mut consumer, &mut events, &filter);
+    predicted.clear();
+    push_predictions(&consumer, 0.005f32, &mut predicted);
+    assert_eq!(predicted.len(), 0);
+}
@@ -1,7 +1,6 @@
 /// DrawObjectList interns shaders, brushes, and scripts, returning references that can be stored
 /// in the event queue.
 /// TODO: scripts
-/// TODO: serialization
 /// TODO: further backing store, caching init objs by sha1 or so
 /// TODO: free shaders + textures on gl pause
 /// TODO: cleanup, deduplication
@@ -50,6 +49,15 @@ impl<T> DrawObjectIndex<T> {
     pub fn error() -> DrawObjectIndex<T> {
         unsafe { mem::transmute(-1i32) }
     }
+    #[inline]
+    pub fn from_i32(idx: i32) -> DrawObjectIndex<T> {
+        DrawObjectIndex(idx)
+    }
+    #[inline]
+    pub fn to_i32(&self) -> i32 {
+        let DrawObjectIndex(idx) = *self;
+        idx
+    }
 }
 
 // again, wtf, why is this needed
@@ -170,6 +178,25 @@ impl<'a, T: UsingDefaults<Init>, Init: Hash<HashType>+Eq+Debug> DrawObjectList<'
         }
     }
 
+    /// Appends without consulting the cache, so loaded objects land at the index they were saved
+    /// from even if two of them happen to share a source.
+    pub fn push_object_uncached(&mut self, init: Init) -> GLResult<DrawObjectIndex<T>> {
+        let inited: T = try!(UsingDefaults::<Init>::maybe_init(init));
+        let ptr = self.arena.alloc(inited);
+        unsafe {
+            self.list.push(mem::transmute(ptr));
+        }
+        Ok(DrawObjectIndex((self.list.len() - 1) as i32))
+    }
+
+    pub fn len(&self) -> usize {
+        self.list.len()
+    }
+
+    pub fn objects(&self) -> &[&'a T] {
+        self.list.as_slice()
+    }
+
     pub fn get_object(&self, i: DrawObjectIndex<T>) -> &'a T {
         let DrawObjectIndex(idx) = i;
         self.list[idx as usize]
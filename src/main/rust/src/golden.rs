@@ -0,0 +1,195 @@
+/// Golden-image checks: render a fixed stroke through a brush package and compare the result
+/// against a stored reference image.
+///
+/// Needs a current gl context, see headless.rs.  Reference images are plain RGBA PNGs named
+/// after the package; when a comparison fails, the actual output and a diff image are written
+/// next to each other for inspection.
+
+use core::prelude::*;
+use core::borrow::IntoCow;
+use core::cmp::max;
+use collections::vec::Vec;
+use collections::string::String;
+use std::old_path::Path;
+
+use glcommon::GLResult;
+use gltexture::PixelFormat;
+use drawevent::Events;
+use sessionfile::SessionData;
+use point::{PointEntry, PointInfo};
+use unibrush::{UniBrushSource, ShaderSource};
+use paintlayer::LayerStyle;
+use pngfile::{PngImage, load_png, save_png};
+use headless;
+use lua_geom;
+
+/// How far output may drift from its reference before the check fails.
+#[derive(Copy, Debug)]
+pub struct Tolerance {
+    /// Largest per-channel difference that still counts as the same pixel.
+    pub channel: u8,
+    /// Fraction of pixels that may differ by more than that.
+    pub fraction: f32,
+}
+
+#[derive(Copy)]
+pub struct GoldenSettings {
+    pub dimensions: (i32, i32),
+    pub brush_color: i32,
+    pub brush_size: f32,
+    pub tolerance: Tolerance,
+}
+
+pub struct Comparison {
+    pub differing: usize,
+    pub max_delta: u8,
+    /// Same size as the inputs: differing pixels in red, scaled by how far off they are, over
+    /// a dimmed grey copy of the reference.
+    pub diff: Vec<u8>,
+}
+
+impl Comparison {
+    pub fn passes(&self, tolerance: Tolerance, pixelcount: usize) -> bool {
+        self.differing as f32 <= tolerance.fraction * pixelcount as f32
+    }
+}
+
+fn or_default(shader: &Option<ShaderSource>) -> ShaderSource {
+    shader.clone().unwrap_or((None, None))
+}
+
+/// Builds a recorded session that selects the package the way MainActivity.loadUniBrush does,
+/// starting from the defaults, and then draws the strokes.  Compiles everything it loads, so
+/// broken shaders and scripts fail here.
+pub fn build_session(brush: &UniBrushSource, strokes: &[PointEntry], settings: &GoldenSettings) -> GLResult<SessionData> {
+    let (w, h) = settings.dimensions;
+    unsafe { try!(lua_geom::ensure_lua_exists(w, h)); }
+    let mut events = Events::new();
+    events.set_canvas((w, h));
+
+    let mut pointshaders = Vec::new();
+    for &(ref vert, ref frag) in brush.pointshaders.iter() {
+        pointshaders.push(try!(events.load_pointshader(vert.clone(), frag.clone())));
+    }
+    let mut animshaders = Vec::new();
+    for &(ref vert, ref frag) in brush.animshaders.iter() {
+        animshaders.push(try!(events.load_copyshader(vert.clone(), frag.clone())));
+    }
+    events.clear_layers();
+    for layer in brush.layers.iter() {
+        let pointshader = match layer.pointshader {
+            Some(idx) => pointshaders[idx].clone(),
+            None => try!(events.load_pointshader(None, None)),
+        };
+        let copyshader = match layer.copyshader {
+            Some(idx) => animshaders[idx].clone(),
+            None => try!(events.load_copyshader(None, None)),
+        };
+        try!(events.add_layer(settings.dimensions, Some(copyshader), Some(pointshader), layer.pointsrc));
+    }
+    for (idx, layer) in brush.layers.iter().enumerate() {
+        if layer.style != LayerStyle::default() {
+            events.use_layer_style(idx as i32 + 1, layer.style);
+        }
+    }
+
+    let brushidx = match brush.brush {
+        Some(ref b) => events.load_brush(b.dimensions.0, b.dimensions.1, b.pixels.as_slice(), b.format()),
+        // a solid square, so packages without a brush image still draw something
+        None => events.load_brush(1, 1, &[255u8], PixelFormat::ALPHA),
+    };
+    try!(events.use_brush(brushidx));
+    let (vert, frag) = or_default(&brush.baseanimshader);
+    let anim = try!(events.load_copyshader(vert, frag));
+    try!(events.use_animshader(anim));
+    let (vert, frag) = or_default(&brush.basepointshader);
+    let point = try!(events.load_pointshader(vert, frag));
+    try!(events.use_pointshader(point));
+    let (vert, frag) = or_default(&brush.basecopyshader);
+    let copy = try!(events.load_copyshader(vert, frag));
+    try!(events.use_copyshader(copy));
+    let interp = try!(events.load_interpolator(brush.interpolator.clone()));
+    try!(events.use_interpolator(interp));
+    events.use_brush_color(settings.brush_color);
+    events.use_brush_size(settings.brush_size);
+    if let Some(filter) = brush.smoothing {
+        events.use_stroke_filter(filter);
+    }
+
+    for entry in strokes.iter() {
+        events.pushpoint(*entry);
+        if entry.entry == PointInfo::FrameStop {
+            events.pushframe();
+        }
+    }
+    events.pushframe();
+    Ok(events.to_session())
+}
+
+pub fn compare(expected: &PngImage, actual: &[u8], tolerance: Tolerance) -> GLResult<Comparison> {
+    if expected.pixels.len() != actual.len() {
+        return Err(format!("reference is {}x{}, which doesn't match the output", expected.width, expected.height).into_cow());
+    }
+    let mut differing = 0;
+    let mut max_delta = 0u8;
+    let mut diff = Vec::with_capacity(actual.len());
+    for (e, a) in expected.pixels.chunks(4).zip(actual.chunks(4)) {
+        let delta = range(0, 4).fold(0u8, |d, i| max(d, if e[i] > a[i] { e[i] - a[i] } else { a[i] - e[i] }));
+        max_delta = max(max_delta, delta);
+        if delta > tolerance.channel {
+            differing += 1;
+            diff.push_all(&[max(delta, 64), 0, 0, 255]);
+        } else {
+            let grey = ((e[0] as u32 + e[1] as u32 + e[2] as u32) / 12) as u8;
+            diff.push_all(&[grey, grey, grey, 255]);
+        }
+    }
+    Ok(Comparison { differing: differing, max_delta: max_delta, diff: diff })
+}
+
+fn path_str(path: &Path) -> String {
+    format!("{}", path.display())
+}
+
+/// Renders one package and checks it against refdir/<name>.png.  With bless set, the output
+/// replaces the reference instead.  Returns whether the check passed.
+pub fn check_package(name: &str, brush: &UniBrushSource, strokes: &[PointEntry], settings: &GoldenSettings,
+                     refdir: &Path, outdir: &Path, bless: bool) -> GLResult<bool> {
+    let (w, h) = settings.dimensions;
+    let session = try!(build_session(brush, strokes, settings));
+    let pixels = try!(headless::render_session(session, w, h));
+    let refpath = refdir.join(format!("{}.png", name));
+    if bless {
+        try!(save_png(path_str(&refpath).as_slice(), w, h, pixels.as_slice()));
+        logi!("{}: wrote new reference image", name);
+        return Ok(true);
+    }
+    let expected = match load_png(path_str(&refpath).as_slice()) {
+        Ok(image) => image,
+        Err(e) => return Err(format!("{}: no usable reference image ({}); bless it first", name, e).into_cow()),
+    };
+    let comparison = try!(compare(&expected, pixels.as_slice(), settings.tolerance));
+    if comparison.passes(settings.tolerance, (w * h) as usize) {
+        return Ok(true);
+    }
+    let actualpath = outdir.join(format!("{}.png", name));
+    let diffpath = outdir.join(format!("{}-diff.png", name));
+    try!(save_png(path_str(&actualpath).as_slice(), w, h, pixels.as_slice()));
+    try!(save_png(path_str(&diffpath).as_slice(), w, h, comparison.diff.as_slice()));
+    loge!("{}: {} pixels differ, by up to {}; see {}", name, comparison.differing, comparison.max_delta, path_str(&diffpath));
+    Ok(false)
+}
+
+#[test]
+fn compare_applies_tolerance() {
+    let expected = PngImage { width: 2, height: 1, has_alpha: true, pixels: [10u8, 10, 10, 255, 0, 0, 0, 255].to_vec() };
+    let tolerance = Tolerance { channel: 4, fraction: 0f32 };
+    let close = compare(&expected, &[12u8, 8, 10, 255, 0, 0, 4, 255], tolerance).unwrap();
+    assert_eq!((close.differing, close.max_delta), (0, 4));
+    assert!(close.passes(tolerance, 2));
+    let far = compare(&expected, &[10u8, 10, 10, 255, 0, 90, 0, 255], tolerance).unwrap();
+    assert_eq!((far.differing, far.max_delta), (1, 90));
+    assert!(!far.passes(tolerance, 2));
+    assert!(far.passes(Tolerance { channel: 4, fraction: 0.5f32 }, 2));
+    assert_eq!(far.diff.slice_from(4), [90u8, 0, 0, 255].as_slice());
+}
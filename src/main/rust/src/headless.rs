@@ -0,0 +1,69 @@
+/// Renders a recorded session without Android, a window, or the JNI layer.
+///
+/// The caller is responsible for making a gl context current first, e.g. with
+/// eglinit::egl_init_pbuffer. The session is replayed as fast as possible, with a frame
+/// rendered at each recorded frame boundary so animshaders advance the same way they did live.
+
+use core::prelude::*;
+use core::mem;
+use core::iter::repeat;
+use collections::vec::Vec;
+
+use glcommon::GLResult;
+use glinit::{GLInit, ExportAlpha};
+use drawevent::Events;
+use drawevent::event_stream::{EventStream, EventState};
+use sessionfile::SessionData;
+use matrix;
+use lua_geom;
+
+struct HeadlessData<'a> {
+    glinit: GLInit<'a>,
+    events: Events<'a>,
+}
+
+// same trick as rustjni::get_safe_data: GLInit and Events borrow each other for 'a
+fn get_data<'a>(data: *mut HeadlessData) -> &'a mut HeadlessData<'a> {
+    unsafe { mem::transmute(data) }
+}
+
+/// Replays the session on a w*h surface and returns its RGBA pixels, rows top to bottom.  The
+/// canvas starts at the size the session was recorded at, or w*h if it doesn't say, so the
+/// pixels are however big the canvas ends up.
+pub fn render_session(session: SessionData, w: i32, h: i32) -> GLResult<Vec<u8>> {
+    unsafe { try!(lua_geom::ensure_lua_exists(w, h)); }
+    let mut events = Events::new();
+    let glinit = GLInit::setup_graphics(w, h, &mut events);
+    let mut headless = HeadlessData { glinit: glinit, events: events };
+    let ptr: *mut HeadlessData = &mut headless;
+
+    try!(get_data(ptr).events.load_session(session));
+    let canvas = get_data(ptr).events.canvas();
+    get_data(ptr).glinit.reset_paintstate(canvas);
+
+    // same mapping as the android frontend: pixel coordinates, origin at top left
+    let pointmatrix = matrix::ortho(0f32, w as f32, h as f32, 0f32, -1f32, 1f32);
+    let undo_callback = |_: i32| { };
+    let mut replay = EventStream::new(&mut get_data(ptr).events);
+    loop {
+        let data = get_data(ptr);
+        let state = replay.advance_frame(&mut data.glinit, &mut data.events, 0);
+        let data = get_data(ptr);
+        try!(data.glinit.draw_queued_points(&mut replay.consumer, &mut data.events, &pointmatrix, &undo_callback));
+        match state {
+            EventState::Done => break,
+            EventState::Frame => get_data(ptr).glinit.render_frame(),
+            EventState::Draw | EventState::NoFrame => { },
+        }
+    }
+    // let the interpolator finish any stroke still in progress, e.g. to merge its layers
+    let data = get_data(ptr);
+    try!(data.glinit.unload_interpolator(&mut replay.consumer, &mut data.events, &undo_callback));
+    get_data(ptr).glinit.render_frame();
+
+    let (w, h) = get_data(ptr).glinit.get_buffer_dimensions();
+    let mut pixels: Vec<u8> = repeat(0u8).take((w * h * 4) as usize).collect();
+    try!(get_data(ptr).glinit.get_pixels(pixels.as_mut_slice(), ExportAlpha::Opaque));
+    unsafe { get_data(ptr).glinit.destroy(); }
+    Ok(pixels)
+}
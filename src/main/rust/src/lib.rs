@@ -1,5 +1,5 @@
 #![feature(unboxed_closures, unsafe_destructor)]
-#![feature(no_std, core, hash, std_misc, rustc_private, alloc, collections, libc)]
+#![feature(no_std, core, hash, std_misc, rustc_private, alloc, collections, libc, io, path)]
 #![crate_name = "rustgl"]
 #![crate_type = "staticlib"]
 #![no_std]
@@ -13,6 +13,8 @@ extern crate collections;
 extern crate alloc;
 extern crate std;
 extern crate arena;
+extern crate serialize;
+extern crate flate;
 extern crate lua;
 
 pub mod bindgen_builtins;
@@ -28,12 +30,12 @@ pub mod macros;
 pub mod log;
 #[macro_use]
 pub mod glcommon;
-#[macro_use]
-pub mod rollingaverage;
 
 pub mod glpoint;
+pub mod smoothing;
 pub mod activestate;
 pub mod motionevent;
+pub mod gesture;
 pub mod pointshader;
 pub mod glinit;
 pub mod eglinit;
@@ -42,6 +44,12 @@ pub mod gltexture;
 pub mod point;
 pub mod matrix;
 pub mod drawevent;
+pub mod sessionfile;
+pub mod pngfile;
+pub mod pointfile;
+pub mod headless;
+pub mod unibrush;
+pub mod golden;
 pub mod glstore;
 pub mod luascript;
 pub mod paintlayer;
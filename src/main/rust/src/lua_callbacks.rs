@@ -11,27 +11,24 @@ use glpoint::MotionEventConsumer;
 use glinit::GLInit;
 use drawevent::Events;
 use lua_geom::rust_raise_lua_err;
-use rustjni::JNICallbackClosure;
 
 static MOVE: u8 = 0u8;
 static DONE: u8 = 1u8;
 static DOWN: u8 = 2u8;
 static UP:   u8 = 3u8;
 
-//pub type UndoCallback<'a> = ::rustjni::JNICallbackClosure<'a>;
-
 pub struct LuaCallbackType<'a, 'b, 'c: 'b, 'd> {
     consumer: &'a mut MotionEventConsumer,
     events: &'c mut Events<'c>,
     glinit: &'b mut GLInit<'c>,
-    undo_callback: &'d JNICallbackClosure<'d>,
+    undo_callback: &'d (Fn(i32) + 'd),
 }
 
 pub trait LuaCallback { }
 impl<'a,'b,'c,'d> LuaCallback for LuaCallbackType<'a,'b,'c,'d> { }
 
 impl<'a, 'b, 'c, 'd> LuaCallbackType<'a, 'b, 'c, 'd> {
-    pub fn new(glinit: &'b mut GLInit<'c>, events: &'c mut Events<'c>, s: &'a mut MotionEventConsumer, undo_callback: &'d JNICallbackClosure) -> LuaCallbackType<'a, 'b, 'c, 'd> {
+    pub fn new(glinit: &'b mut GLInit<'c>, events: &'c mut Events<'c>, s: &'a mut MotionEventConsumer, undo_callback: &'d (Fn(i32) + 'd)) -> LuaCallbackType<'a, 'b, 'c, 'd> {
         LuaCallbackType {
             consumer: s,
             events: events,
@@ -47,7 +44,8 @@ impl<'a, 'b, 'c, 'd> LuaCallbackType<'a, 'b, 'c, 'd> {
 #[no_mangle]
 pub extern "C" fn lua_nextpoint(data: &mut &mut LuaCallbackType, points: &mut (ShaderPaintPoint, ShaderPaintPoint)) -> u16 {
     let events: &mut Events = data.events;
-    let (state, pointer) = glpoint::next_point(data.consumer, events);
+    let filter = data.glinit.paintstate.stroke_filter;
+    let (state, pointer) = glpoint::next_point(data.consumer, events, &filter);
     let (newpoints, luastate) = match state {
         Move(a, b) => ((a,b), MOVE),
         Down(a) => unsafe { ((a, mem::uninitialized()), DOWN) },
@@ -114,6 +112,38 @@ pub unsafe extern "C" fn lua_savelayers(data: &mut &mut LuaCallbackType) {
     data.glinit.copy_layers_down();
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn lua_removelayer(data: &mut &mut LuaCallbackType, layer: i32) {
+    if let Err(msg) = data.glinit.remove_layer(layer) {
+        loge!("{}", msg.as_slice());
+        rust_raise_lua_err(None, msg.as_slice());
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn lua_movelayer(data: &mut &mut LuaCallbackType, from: i32, to: i32) {
+    if let Err(msg) = data.glinit.move_layer(from, to) {
+        loge!("{}", msg.as_slice());
+        rust_raise_lua_err(None, msg.as_slice());
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn lua_mergelayer(data: &mut &mut LuaCallbackType, layer: i32) {
+    if let Err(msg) = data.glinit.merge_layer_down(layer) {
+        loge!("{}", msg.as_slice());
+        rust_raise_lua_err(None, msg.as_slice());
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn lua_duplicatelayer(data: &mut &mut LuaCallbackType, layer: i32) {
+    if let Err(msg) = data.glinit.duplicate_layer(layer) {
+        loge!("{}", msg.as_slice());
+        rust_raise_lua_err(None, msg.as_slice());
+    }
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn lua_pushcatmullrom(data: &mut &mut LuaCallbackType, queue: i32, a: &ShaderPaintPoint, b: &ShaderPaintPoint, c: &ShaderPaintPoint, d: &ShaderPaintPoint) {
     let points = [*a, *b, *c, *d];
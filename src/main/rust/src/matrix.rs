@@ -1,9 +1,103 @@
 use core::prelude::*;
+use core::num::Float;
+use core::f32;
+
+use point::Coordinate;
 
 pub type Matrix = [f32; 16];
 
+/// A 2d affine transform, taking (x, y) to (a*x + c*y + tx, b*x + d*y + ty).
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Affine {
+    pub a: f32,
+    pub b: f32,
+    pub c: f32,
+    pub d: f32,
+    pub tx: f32,
+    pub ty: f32,
+}
+
+impl Affine {
+    pub fn identity() -> Affine {
+        Affine { a: 1f32, b: 0f32, c: 0f32, d: 1f32, tx: 0f32, ty: 0f32 }
+    }
+
+    pub fn translate(x: f32, y: f32) -> Affine {
+        Affine { tx: x, ty: y, .. Affine::identity() }
+    }
+
+    pub fn scale(x: f32, y: f32) -> Affine {
+        Affine { a: x, d: y, .. Affine::identity() }
+    }
+
+    /// Rotates by angle radians, from the x axis towards the y axis.
+    pub fn rotate(angle: f32) -> Affine {
+        let (sin, cos) = angle.sin_cos();
+        Affine { a: cos, b: sin, c: -sin, d: cos, tx: 0f32, ty: 0f32 }
+    }
+
+    /// This transform followed by next.
+    pub fn then(&self, next: &Affine) -> Affine {
+        Affine {
+            a: next.a * self.a + next.c * self.b,
+            b: next.b * self.a + next.d * self.b,
+            c: next.a * self.c + next.c * self.d,
+            d: next.b * self.c + next.d * self.d,
+            tx: next.a * self.tx + next.c * self.ty + next.tx,
+            ty: next.b * self.tx + next.d * self.ty + next.ty,
+        }
+    }
+
+    /// The transform that undoes this one, if there is one.
+    pub fn invert(&self) -> Option<Affine> {
+        let det = self.a * self.d - self.b * self.c;
+        if det.abs() < 1e-12f32 {
+            return None;
+        }
+        let (a, b, c, d) = (self.d / det, -self.b / det, -self.c / det, self.a / det);
+        Some(Affine { a: a, b: b, c: c, d: d, tx: -(a * self.tx + c * self.ty), ty: -(b * self.tx + d * self.ty) })
+    }
+
+    pub fn apply(&self, p: Coordinate) -> Coordinate {
+        Coordinate { x: self.a * p.x + self.c * p.y + self.tx, y: self.b * p.x + self.d * p.y + self.ty }
+    }
+
+    /// Applies just the linear part, for directions and distances rather than positions.
+    pub fn apply_vector(&self, v: Coordinate) -> Coordinate {
+        Coordinate { x: self.a * v.x + self.c * v.y, y: self.b * v.x + self.d * v.y }
+    }
+
+    /// How much lengths are scaled by, on average.
+    pub fn scale_factor(&self) -> f32 {
+        (self.a * self.d - self.b * self.c).abs().sqrt()
+    }
+
+    /// As a column-major 4x4 matrix, leaving z alone.
+    pub fn to_matrix(&self) -> Matrix {
+        [self.a,  self.b,  0f32, 0f32,
+         self.c,  self.d,  0f32, 0f32,
+         0f32,    0f32,    1f32, 0f32,
+         self.tx, self.ty, 0f32, 1f32,]
+    }
+}
+
+/// a * b for column-major matrices, so the result applies b first.
+pub fn multiply(a: &Matrix, b: &Matrix) -> Matrix {
+    let mut result = [0f32; 16];
+    for col in range(0, 4) {
+        for row in range(0, 4) {
+            let mut sum = 0f32;
+            for k in range(0, 4) {
+                sum += a[k * 4 + row] * b[col * 4 + k];
+            }
+            result[col * 4 + row] = sum;
+        }
+    }
+    result
+}
+
 #[repr(i32)]
-#[derive(Copy, Debug, PartialEq, Eq)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum Rotation {
     Rotation0 = 0,
     Rotation90 = 1,
@@ -11,6 +105,18 @@ pub enum Rotation {
     Rotation270 = 3,
 }
 
+impl Rotation {
+    pub fn from_i32(rotation: i32) -> Option<Rotation> {
+        match rotation {
+            0 => Some(Rotation::Rotation0),
+            1 => Some(Rotation::Rotation90),
+            2 => Some(Rotation::Rotation180),
+            3 => Some(Rotation::Rotation270),
+            _ => None,
+        }
+    }
+}
+
 /// copied from android.opengl.matrix
 /// intended for framebuffers, which range from (-1, -1) to (1, 1), and not textures, which range
 /// from (0, 0) to (1, 1)
@@ -25,6 +131,20 @@ pub fn ortho(left: f32, right: f32, bottom: f32, top: f32, near: f32, far: f32)
       tx,     ty,    tz,  1f32,]
 }
 
+/// Brings any angle into -pi..pi, in radians.
+pub fn wrap_angle(angle: f32) -> f32 {
+    let turn = 2f32 * f32::consts::PI;
+    // keeps the sign of angle, so it's within a turn of the range
+    let angle = angle % turn;
+    if angle > f32::consts::PI {
+        angle - turn
+    } else if angle < -f32::consts::PI {
+        angle + turn
+    } else {
+        angle
+    }
+}
+
 pub static IDENTITY: Matrix =
     [1f32, 0f32, 0f32, 0f32,
      0f32, 1f32, 0f32, 0f32,
@@ -94,3 +214,36 @@ pub fn fit_inside(srcdimensions: (i32, i32), targetdimensions: (i32, i32), rotat
                         (1f32 + glratioy) / 2f32, (0f32 + glratiox) / 2f32, 0f32, 0f32],
                         */
 }
+
+#[cfg(test)]
+fn close(a: Coordinate, b: Coordinate) -> bool {
+    (a.x - b.x).abs() < 0.001f32 && (a.y - b.y).abs() < 0.001f32
+}
+
+#[test]
+fn affine_compose_and_invert() {
+    let t = Affine::scale(2f32, 3f32).then(&Affine::rotate(0.7f32)).then(&Affine::translate(5f32, -1f32));
+    let p = Coordinate { x: 3f32, y: 4f32 };
+    let expected = Affine::translate(5f32, -1f32).apply(Affine::rotate(0.7f32).apply(Affine::scale(2f32, 3f32).apply(p)));
+    assert!(close(t.apply(p), expected));
+    assert!(close(t.invert().unwrap().apply(t.apply(p)), p));
+    assert!(Affine::scale(0f32, 1f32).invert().is_none());
+}
+
+#[test]
+fn affine_matches_matrix() {
+    let t = Affine::rotate(1.2f32).then(&Affine::translate(2f32, 3f32));
+    let m = multiply(&IDENTITY, &t.to_matrix());
+    let p = Coordinate { x: -1f32, y: 6f32 };
+    let mapped = Coordinate { x: m[0] * p.x + m[4] * p.y + m[12], y: m[1] * p.x + m[5] * p.y + m[13] };
+    assert!(close(mapped, t.apply(p)));
+}
+
+#[test]
+fn angles_wrap_from_any_number_of_turns() {
+    use core::f32::consts::PI;
+    for &(angle, expected) in [(0.5f32, 0.5f32), (PI + 0.5f32, 0.5f32 - PI), (-PI - 0.5f32, PI - 0.5f32),
+                               (7f32 * PI + 0.5f32, 0.5f32 - PI), (-10f32 * PI - 0.5f32, -0.5f32)].iter() {
+        assert!((wrap_angle(angle) - expected).abs() < 0.001f32);
+    }
+}
@@ -1,31 +1,185 @@
 use core::prelude::*;
 
+use collections::vec::Vec;
 use collections::vec_map::VecMap;
+use std::sync::mpsc;
 
-use android::input::*;
-
-use point::{PaintPoint, Coordinate, PointEntry, PointProducer, PointInfo};
+use point::{PaintPoint, Coordinate, PointEntry, PointConsumer, PointProducer, PointInfo, ToolType};
 use activestate;
 use activestate::ActiveState;
+use gesture::{GestureRecognizer, GestureSettings, GestureEvent, Decision};
+
+/// What an input event did, independent of where it came from.
+/// PointerDown and PointerUp carry the id of the pointer that changed.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum InputAction {
+    Down,
+    Up,
+    Cancel,
+    PointerDown(i32),
+    PointerUp(i32),
+    Move,
+    /// A stylus came into range above the screen, moved there, or left.
+    HoverEnter,
+    HoverMove,
+    HoverExit,
+    Key,
+    Unknown(u32),
+}
 
-static AMOTION_EVENT_ACTION_POINTER_INDEX_SHIFT: usize = 8;
+/// A multi-pointer input event, modeled on android's MotionEvent.
+/// Pointers are addressed by their position in the event; ids stay stable across events.
+/// History entries are ordered oldest first and come before the current point.
+pub trait InputEvent {
+    fn action(&self) -> InputAction;
+    fn pointer_count(&self) -> usize;
+    fn pointer_id(&self, ptr: usize) -> i32;
+    fn history_size(&self) -> usize;
+    fn historical_point(&self, ptr: usize, hist: usize) -> PaintPoint;
+    fn current_point(&self, ptr: usize) -> PaintPoint;
+    fn tool_type(&self, ptr: usize) -> ToolType;
+    /// Buttons held for the whole event, as point::BUTTON_* bits.
+    fn button_state(&self) -> u32;
+}
+
+/// A pointer in a SyntheticEvent.  Every pointer must have the same amount of history.
+#[derive(Clone, Debug)]
+pub struct SyntheticPointer {
+    pub id: i32,
+    pub tool: ToolType,
+    pub history: Vec<PaintPoint>,
+    pub current: PaintPoint,
+}
+
+/// An InputEvent built by hand, for desktop frontends and tests.
+#[derive(Clone, Debug)]
+pub struct SyntheticEvent {
+    pub action: InputAction,
+    pub buttons: u32,
+    pub pointers: Vec<SyntheticPointer>,
+}
+
+impl InputEvent for SyntheticEvent {
+    fn action(&self) -> InputAction { self.action }
+    fn pointer_count(&self) -> usize { self.pointers.len() }
+    fn pointer_id(&self, ptr: usize) -> i32 { self.pointers[ptr].id }
+    fn history_size(&self) -> usize {
+        self.pointers.first().map_or(0, |p| p.history.len())
+    }
+    fn historical_point(&self, ptr: usize, hist: usize) -> PaintPoint { self.pointers[ptr].history[hist] }
+    fn current_point(&self, ptr: usize) -> PaintPoint { self.pointers[ptr].current }
+    fn tool_type(&self, ptr: usize) -> ToolType { self.pointers[ptr].tool }
+    fn button_state(&self) -> u32 { self.buttons }
+}
 
 // TODO: consider eliminating entirely and putting faith in ACTION_POINTER_UP/DOWN
 type PointerState = VecMap<ActiveState>;
 
+/// A screen area, in pixels.  Contains points on its left and top edges, but not its right and
+/// bottom ones.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Rect {
+    pub left: f32,
+    pub top: f32,
+    pub right: f32,
+    pub bottom: f32,
+}
+
+impl Rect {
+    pub fn contains(&self, p: Coordinate) -> bool {
+        p.x >= self.left && p.x < self.right && p.y >= self.top && p.y < self.bottom
+    }
+}
+
+/// Which touches to ignore.  A rejected pointer draws nothing more until it's lifted; if it
+/// was already drawing, its stroke ends where it was rejected.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RejectionRules {
+    /// touches with a larger contact size than this are taken to be palms
+    pub max_contact_size: Option<f32>,
+    /// fingers are ignored while a stylus is touching or hovering over the screen
+    pub reject_fingers_near_stylus: bool,
+    /// touches starting in any of these areas are ignored
+    pub excluded: Vec<Rect>,
+}
+
+impl RejectionRules {
+    pub fn default() -> RejectionRules {
+        RejectionRules { max_contact_size: None, reject_fingers_near_stylus: true, excluded: Vec::new() }
+    }
+}
+
+struct Rejection {
+    rules: RejectionRules,
+    // ids of pointers that have been rejected and haven't been lifted yet
+    rejected: Vec<i32>,
+    stylus_in_range: bool,
+}
+
+impl Rejection {
+    fn rejecting_fingers(&self) -> bool {
+        self.rules.reject_fingers_near_stylus && self.stylus_in_range
+    }
+
+    /// Checks a pointer against the rules, and remembers it if it's rejected.
+    fn check<E: InputEvent>(&mut self, evt: &E, ptr: usize, starting: bool) -> bool {
+        let id = evt.pointer_id(ptr);
+        if self.rejected.contains(&id) {
+            return true;
+        }
+        let point = evt.current_point(ptr);
+        let rejected = (self.rejecting_fingers() && evt.tool_type(ptr) == ToolType::Finger)
+            || self.rules.max_contact_size.map_or(false, |max| point.size > max)
+            || (starting && self.rules.excluded.iter().any(|rect| rect.contains(point.pos)));
+        if rejected {
+            self.rejected.push(id);
+        }
+        rejected
+    }
+
+    fn lifted(&mut self, id: i32) {
+        self.rejected.retain(|&x| x != id);
+    }
+}
+
 pub struct Data {
     pointer_states: PointerState,
     left_edge: i32,
     attend_points: bool,
+    gestures: Option<GestureRecognizer>,
+    // points waiting on the gesture recognizer to decide what they are
+    held: (PointProducer, PointConsumer),
+    rejection: Rejection,
 }
 
 impl Data {
     pub fn new(left_edge: i32) -> Data {
-        Data { pointer_states: VecMap::new(), left_edge: left_edge, attend_points: true }
+        Data {
+            pointer_states: VecMap::new(),
+            left_edge: left_edge,
+            attend_points: true,
+            gestures: None,
+            held: mpsc::channel::<PointEntry>(),
+            rejection: Rejection { rules: RejectionRules::default(), rejected: Vec::new(), stylus_in_range: false },
+        }
+    }
+
+    /// Replaces the palm rejection rules.  Pointers already rejected stay rejected.
+    pub fn set_rejection_rules(&mut self, rules: RejectionRules) {
+        self.rejection.rules = rules;
+    }
+
+    /// Turns gesture recognition on, or off with None.  Without it, every touch paints.
+    pub fn set_gestures(&mut self, settings: Option<GestureSettings>) {
+        self.gestures = settings.map(GestureRecognizer::new);
     }
 }
 
 pub fn pause(data: &mut Data, queue: &mut PointProducer) {
+    // the stops below would otherwise be for pointers the queue has never seen
+    while let Ok(entry) = data.held.1.try_recv() {
+        let _ = queue.send(entry);
+    }
     let active = &mut data.pointer_states;
     for (_, state) in active.iter_mut() {
         *state = state.push(false);
@@ -36,65 +190,126 @@ pub fn pause(data: &mut Data, queue: &mut PointProducer) {
     // pro: straightforward, con: extra 4 bytes on every pointentry
     // could fold index into pointinfo, or have a magic index like -1 to indicate framestop
     // maybe this entire approach isn't such a good one after all?
-    let _ = queue.send(PointEntry { index: 0, entry: PointInfo::FrameStop });
+    let _ = queue.send(PointEntry::framestop());
+}
+
+/// Queues the event's points, unless they turn out to be part of a gesture, in which case
+/// anything the gesture asks for is returned instead.
+pub fn append_motion_event<E: InputEvent>(data: &mut Data, evt: &E, queue: &mut PointProducer) -> Option<GestureEvent> {
+    let is_stylus = |ptr| match evt.tool_type(ptr) {
+        ToolType::Stylus | ToolType::Eraser => true,
+        _ => false,
+    };
+    let stylus_present = range(0, evt.pointer_count()).any(is_stylus);
+    match evt.action() {
+        InputAction::HoverEnter | InputAction::HoverMove => {
+            data.rejection.stylus_in_range = stylus_present;
+            return None;
+        },
+        InputAction::HoverExit => {
+            data.rejection.stylus_in_range = false;
+            return None;
+        },
+        // a hover enter follows if the stylus is still in range
+        InputAction::Up | InputAction::Cancel => if stylus_present { data.rejection.stylus_in_range = false },
+        _ => if stylus_present { data.rejection.stylus_in_range = true },
+    }
+    // while fingers are ignored, they're palms rather than gestures
+    let decision = match data.gestures {
+        Some(ref mut gestures) if !data.rejection.rejecting_fingers() => gestures.feed(evt),
+        _ => Decision::Draw,
+    };
+    match decision {
+        Decision::Draw => push_event(data, evt, queue),
+        Decision::Hold => {
+            let mut held = data.held.0.clone();
+            push_event(data, evt, &mut held);
+        },
+        Decision::Flush => {
+            while let Ok(entry) = data.held.1.try_recv() {
+                let _ = queue.send(entry);
+            }
+            push_event(data, evt, queue);
+        },
+        Decision::Discard => {
+            while let Ok(_) = data.held.1.try_recv() { }
+            // nothing held ever reached the queue, so there's nothing to stop
+            data.pointer_states.clear();
+        },
+        Decision::Gesture(event) => return event,
+    }
+    None
 }
 
-pub fn append_motion_event(data: &mut Data, evt: *const AInputEvent, queue: &mut PointProducer) -> () {
+fn push_event<E: InputEvent>(data: &mut Data, evt: &E, queue: &mut PointProducer) {
     let active = &mut data.pointer_states;
+    let rejection = &mut data.rejection;
     for (_, state) in active.iter_mut() {
         *state = state.push(false);
     }
 
-    match unsafe { AInputEvent_getType(evt) } as u32 {
-        AINPUT_EVENT_TYPE_KEY => { logi!("got key event??"); return; },
-        _ => { }
+    let action = evt.action();
+    if action == InputAction::Key {
+        logi!("got key event??");
+        return;
     }
-    let full_action = unsafe { AMotionEvent_getAction(evt) } as u32;
-    let (action_event, action_index): (u32, u32) = (full_action & AMOTION_EVENT_ACTION_MASK, (full_action & AMOTION_EVENT_ACTION_POINTER_INDEX_MASK) >> AMOTION_EVENT_ACTION_POINTER_INDEX_SHIFT);
-    let action_id = unsafe { AMotionEvent_getPointerId(evt, action_index as size_t) };
-    match (data.attend_points, action_event) {
-        (_, AMOTION_EVENT_ACTION_DOWN) => {
+    match (data.attend_points, action) {
+        (_, InputAction::Down) => {
             push_stops(queue, active); // in case it's not paired with an action_up
+            rejection.rejected.clear();
             data.attend_points = is_valid_start_point(evt, data.left_edge);
             if data.attend_points {
-                push_moves(queue, active, evt);
+                push_moves(queue, active, rejection, evt);
             }
         }
-        (_, AMOTION_EVENT_ACTION_UP) => {
+        (_, InputAction::Up) => {
             data.attend_points = true;
+            rejection.rejected.clear();
             push_stops(queue, active);
         }
-        (_, AMOTION_EVENT_ACTION_CANCEL) => {
+        (_, InputAction::Cancel) => {
             data.attend_points = true;
+            rejection.rejected.clear();
             push_stops(queue, active);
         }
-        (true, AMOTION_EVENT_ACTION_POINTER_UP) => {
+        (true, InputAction::PointerUp(action_id)) => {
             make_active(queue, active, action_id, false);
-            push_moves(queue, active, evt);
+            push_moves(queue, active, rejection, evt);
+            rejection.lifted(action_id);
         }
-        (true, AMOTION_EVENT_ACTION_POINTER_DOWN) => {
+        (true, InputAction::PointerDown(action_id)) => {
             make_active(queue, active, action_id, false); // in case it's not paired with an action_pointer_up
-            push_moves(queue, active, evt);
+            rejection.lifted(action_id);
+            push_moves(queue, active, rejection, evt);
         }
-        (true, AMOTION_EVENT_ACTION_MOVE) => {
-            push_moves(queue, active, evt);
+        (true, InputAction::Move) => {
+            push_moves(queue, active, rejection, evt);
         },
         (true, unknown) => {
-            logi!("unknown action event: {}", unknown);
+            logi!("unknown action event: {:?}", unknown);
         }
         (false, _) => { }
     }
 }
 
-fn push_moves(queue: &mut PointProducer, active: &mut PointerState, evt: *const AInputEvent) {
-    let ptrcount = unsafe { AMotionEvent_getPointerCount(evt) };
-    let historycount = unsafe { AMotionEvent_getHistorySize(evt) };
+fn push_moves<E: InputEvent>(queue: &mut PointProducer, active: &mut PointerState, rejection: &mut Rejection, evt: &E) {
+    let ptrcount = evt.pointer_count();
+    let historycount = evt.history_size();
+    let buttons = evt.button_state();
     for ptr in range(0, ptrcount) {
-        let id = unsafe { AMotionEvent_getPointerId(evt, ptr) };
+        let id = evt.pointer_id(ptr);
+        // anything still active has been pushed to STOPPING by now
+        let starting = active.get(&(id as usize)).map_or(true, |&state| state != activestate::STOPPING);
+        if rejection.check(evt, ptr, starting) {
+            // left inactive, so push_stops ends it if it was drawing
+            continue;
+        }
+        let tool = evt.tool_type(ptr);
+        let entry = |point| PointEntry { index: id, entry: PointInfo::Point(point), tool: tool, buttons: buttons };
         for hist in range(0, historycount) {
-            push_historical_point(queue, evt, id, ptr, hist);
+            let _ = queue.send(entry(evt.historical_point(ptr, hist)));
         }
-        push_current_point(queue, evt, id, ptr);
+        let _ = queue.send(entry(evt.current_point(ptr)));
         make_active(queue, active, id, true);
     }
     push_stops(queue, active);
@@ -105,42 +320,256 @@ fn make_active(queue: &mut PointProducer, active: &mut PointerState, id: i32, ne
     active.insert(id as usize, updated);
     if updated == activestate::STOPPING {
         // really not anything to do if this fails
-        let _ = queue.send(PointEntry { index: id, entry: PointInfo::Stop });
+        let _ = queue.send(PointEntry::stop(id));
     }
 }
 
-fn push_historical_point(queue: &mut PointProducer, evt: *const AInputEvent, id: i32, ptr: size_t, hist: size_t) {
-    let _ = queue.send(PointEntry { index: id, entry: PointInfo::Point(PaintPoint {
-        pos: Coordinate {
-             x: unsafe { AMotionEvent_getHistoricalX(evt, ptr, hist) },
-             y: unsafe { AMotionEvent_getHistoricalY(evt, ptr, hist) },
-        },
-        time: (unsafe { AMotionEvent_getHistoricalEventTime(evt, hist) } / 1000) as f32 / 1000000f32,
-        size: unsafe { AMotionEvent_getHistoricalSize(evt, ptr, hist) },
-    })});
-}
-
-fn push_current_point(queue: &mut PointProducer, evt: *const AInputEvent, id: i32, ptr: size_t) {
-    let _ = queue.send(PointEntry { index: id, entry: PointInfo::Point(PaintPoint {
-        pos: Coordinate {
-            x: unsafe { AMotionEvent_getX(evt, ptr) },
-            y: unsafe { AMotionEvent_getY(evt, ptr) },
-        },
-        time: (unsafe { AMotionEvent_getEventTime(evt) } / 1000) as f32 / 1000000f32,
-        size: unsafe { AMotionEvent_getSize(evt, ptr) },
-    })});
-}
-
 fn push_stops(queue: &mut PointProducer, active: &mut PointerState) {
     for (idx, active) in active.iter_mut() {
         if *active == activestate::STOPPING {
-            let _ = queue.send(PointEntry { index: idx as i32, entry: PointInfo::Stop });
+            let _ = queue.send(PointEntry::stop(idx as i32));
         }
     }
 }
 
-pub fn is_valid_start_point(ptr: *const AInputEvent, left_edge: i32) -> bool {
-    let result = unsafe { AMotionEvent_getX(ptr, 0) as i32 >= left_edge };
-    result
+pub fn is_valid_start_point<E: InputEvent>(evt: &E, left_edge: i32) -> bool {
+    evt.pointer_count() > 0 && evt.current_point(0).pos.x as i32 >= left_edge
 }
 
+#[cfg(test)]
+mod tests {
+    use core::prelude::*;
+    use core::mem;
+    use collections::vec::Vec;
+    use std::sync::mpsc;
+    use point::{PaintPoint, Coordinate, PointEntry, PointInfo, ToolType, BUTTON_STYLUS_PRIMARY};
+    use gesture::{GestureSettings, GestureEvent};
+    use super::{Data, SyntheticEvent, SyntheticPointer, InputAction, RejectionRules, Rect, append_motion_event};
+
+    fn point_at(x: f32) -> PaintPoint {
+        PaintPoint { pos: Coordinate { x: x, y: 0f32 }, time: 0f32, size: 1f32, pressure: 1f32, tilt: 0f32, orientation: 0f32 }
+    }
+
+    fn event(action: InputAction, pointers: &[(i32, f32)]) -> SyntheticEvent {
+        SyntheticEvent {
+            action: action,
+            buttons: 0,
+            pointers: pointers.iter().map(|&(id, x)| SyntheticPointer {
+                id: id,
+                tool: ToolType::Finger,
+                history: Vec::new(),
+                current: point_at(x),
+            }).collect(),
+        }
+    }
+
+    /// Feeds the events through a fresh Data and returns (index, is_stop) for each entry.
+    fn run(left_edge: i32, events: &[SyntheticEvent]) -> Vec<(i32, bool)> {
+        let (mut producer, consumer) = mpsc::channel::<PointEntry>();
+        let mut data = Data::new(left_edge);
+        for evt in events.iter() {
+            append_motion_event(&mut data, evt, &mut producer);
+        }
+        mem::drop(producer);
+        consumer.iter().map(|e| (e.index, e.entry == PointInfo::Stop)).collect()
+    }
+
+    fn timed_event(action: InputAction, time: f32, pointers: &[(i32, f32)]) -> SyntheticEvent {
+        let mut evt = event(action, pointers);
+        for pointer in evt.pointers.iter_mut() {
+            pointer.current.time = time;
+        }
+        evt
+    }
+
+    /// Like run, with gestures turned on, and also returns the gestures.
+    fn run_gestures(events: &[SyntheticEvent]) -> (Vec<(i32, bool)>, Vec<GestureEvent>) {
+        let (mut producer, consumer) = mpsc::channel::<PointEntry>();
+        let mut data = Data::new(0);
+        data.set_gestures(Some(GestureSettings::default()));
+        let gestures = events.iter().filter_map(|evt| append_motion_event(&mut data, evt, &mut producer)).collect();
+        mem::drop(producer);
+        (consumer.iter().map(|e| (e.index, e.entry == PointInfo::Stop)).collect(), gestures)
+    }
+
+    #[test]
+    fn single_stroke() {
+        let result = run(0, &[
+            event(InputAction::Down, &[(0, 10f32)]),
+            event(InputAction::Move, &[(0, 20f32)]),
+            event(InputAction::Up, &[(0, 20f32)]),
+        ]);
+        assert_eq!(result, [(0, false), (0, false), (0, true)].to_vec());
+    }
+
+    #[test]
+    fn second_pointer_lifts_first() {
+        let result = run(0, &[
+            event(InputAction::Down, &[(0, 10f32)]),
+            event(InputAction::PointerDown(1), &[(0, 10f32), (1, 50f32)]),
+            event(InputAction::PointerUp(1), &[(0, 10f32), (1, 50f32)]),
+            event(InputAction::Move, &[(0, 15f32)]),
+            event(InputAction::Up, &[(0, 15f32)]),
+        ]);
+        assert_eq!(result, [
+            (0, false),
+            (0, false), (1, false),
+            (0, false), (1, false),
+            (0, false), (1, true),
+            (0, true),
+        ].to_vec());
+    }
+
+    #[test]
+    fn edge_swipe_ignored() {
+        let result = run(30, &[
+            event(InputAction::Down, &[(0, 5f32)]),
+            event(InputAction::Move, &[(0, 60f32)]),
+            event(InputAction::PointerDown(1), &[(0, 60f32), (1, 90f32)]),
+            event(InputAction::Up, &[(0, 60f32)]),
+        ]);
+        assert_eq!(result, Vec::new());
+    }
+
+    #[test]
+    fn history_comes_first() {
+        let mut evt = event(InputAction::Down, &[(4, 10f32)]);
+        evt.pointers[0].history.push(point_at(1f32));
+        let (mut producer, consumer) = mpsc::channel::<PointEntry>();
+        append_motion_event(&mut Data::new(0), &evt, &mut producer);
+        mem::drop(producer);
+        let xs: Vec<f32> = consumer.iter().filter_map(|e| match e.entry {
+            PointInfo::Point(p) => Some(p.pos.x),
+            _ => None,
+        }).collect();
+        assert_eq!(xs, [1f32, 10f32].to_vec());
+    }
+
+    #[test]
+    fn tool_and_buttons_carried() {
+        let mut evt = event(InputAction::Down, &[(0, 10f32)]);
+        evt.pointers[0].tool = ToolType::Eraser;
+        evt.buttons = BUTTON_STYLUS_PRIMARY;
+        let (mut producer, consumer) = mpsc::channel::<PointEntry>();
+        let mut data = Data::new(0);
+        append_motion_event(&mut data, &evt, &mut producer);
+        append_motion_event(&mut data, &event(InputAction::Up, &[(0, 10f32)]), &mut producer);
+        mem::drop(producer);
+        let entries: Vec<(ToolType, u32)> = consumer.iter().map(|e| (e.tool, e.buttons)).collect();
+        assert_eq!(entries, [(ToolType::Eraser, BUTTON_STYLUS_PRIMARY), (ToolType::Unknown, 0)].to_vec());
+    }
+
+    #[test]
+    fn two_finger_tap() {
+        let (points, gestures) = run_gestures(&[
+            timed_event(InputAction::Down, 0f32, &[(0, 10f32)]),
+            timed_event(InputAction::PointerDown(1), 0.05f32, &[(0, 11f32), (1, 60f32)]),
+            timed_event(InputAction::PointerUp(1), 0.15f32, &[(0, 11f32), (1, 61f32)]),
+            timed_event(InputAction::Up, 0.2f32, &[(0, 10f32)]),
+        ]);
+        assert_eq!(points, Vec::new());
+        assert_eq!(gestures, [GestureEvent::Tap(2)].to_vec());
+    }
+
+    #[test]
+    fn pinch_scales_view() {
+        let (points, gestures) = run_gestures(&[
+            timed_event(InputAction::Down, 0f32, &[(0, 0f32)]),
+            timed_event(InputAction::PointerDown(1), 0.02f32, &[(0, 0f32), (1, 100f32)]),
+            timed_event(InputAction::Move, 0.1f32, &[(0, -50f32), (1, 150f32)]),
+            timed_event(InputAction::PointerUp(1), 0.5f32, &[(0, -50f32), (1, 150f32)]),
+            timed_event(InputAction::Up, 0.6f32, &[(0, -50f32)]),
+        ]);
+        assert_eq!(points, Vec::new());
+        assert_eq!(gestures.len(), 1);
+        match gestures[0] {
+            GestureEvent::Transform(t) => {
+                assert!((t.scale - 2f32).abs() < 0.001f32);
+                assert!(t.rotation.abs() < 0.001f32);
+            },
+            other => panic!("expected a transform, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn moving_first_finger_is_a_stroke() {
+        let (points, gestures) = run_gestures(&[
+            timed_event(InputAction::Down, 0f32, &[(0, 10f32)]),
+            timed_event(InputAction::Move, 0.03f32, &[(0, 40f32)]),
+            timed_event(InputAction::PointerDown(1), 0.06f32, &[(0, 50f32), (1, 90f32)]),
+            timed_event(InputAction::Up, 0.1f32, &[(0, 50f32)]),
+        ]);
+        assert_eq!(points, [
+            (0, false),
+            (0, false),
+            (0, false), (1, false),
+            (0, true), (1, true),
+        ].to_vec());
+        assert_eq!(gestures, Vec::new());
+    }
+
+    #[test]
+    fn late_second_finger_is_a_stroke() {
+        let (points, gestures) = run_gestures(&[
+            timed_event(InputAction::Down, 0f32, &[(0, 10f32)]),
+            timed_event(InputAction::Move, 0.3f32, &[(0, 10f32)]),
+            timed_event(InputAction::PointerDown(1), 0.35f32, &[(0, 10f32), (1, 90f32)]),
+        ]);
+        assert_eq!(points, [(0, false), (0, false), (0, false), (1, false)].to_vec());
+        assert_eq!(gestures, Vec::new());
+    }
+
+    fn run_data(data: &mut Data, events: &[SyntheticEvent]) -> Vec<(i32, bool)> {
+        let (mut producer, consumer) = mpsc::channel::<PointEntry>();
+        for evt in events.iter() {
+            append_motion_event(data, evt, &mut producer);
+        }
+        mem::drop(producer);
+        consumer.iter().map(|e| (e.index, e.entry == PointInfo::Stop)).collect()
+    }
+
+    #[test]
+    fn fingers_ignored_near_stylus() {
+        let mut hover = event(InputAction::HoverEnter, &[(0, 10f32)]);
+        hover.pointers[0].tool = ToolType::Stylus;
+        let mut stylus = event(InputAction::PointerDown(1), &[(0, 80f32), (1, 10f32)]);
+        stylus.pointers[1].tool = ToolType::Stylus;
+        let result = run_data(&mut Data::new(0), &[
+            hover,
+            event(InputAction::Down, &[(0, 80f32)]),
+            stylus,
+        ]);
+        assert_eq!(result, [(1, false)].to_vec());
+    }
+
+    #[test]
+    fn palm_ends_stroke_when_it_grows() {
+        let mut data = Data::new(0);
+        data.set_rejection_rules(RejectionRules { max_contact_size: Some(0.5f32), .. RejectionRules::default() });
+        let mut palm = event(InputAction::Move, &[(0, 20f32)]);
+        palm.pointers[0].current.size = 0.8f32;
+        let result = run_data(&mut data, &[
+            event(InputAction::Down, &[(0, 10f32)]),
+            palm,
+            event(InputAction::Move, &[(0, 30f32)]),
+            event(InputAction::Up, &[(0, 30f32)]),
+        ]);
+        assert_eq!(result, [(0, false), (0, true)].to_vec());
+    }
+
+    #[test]
+    fn excluded_area_only_blocks_starts() {
+        let mut data = Data::new(0);
+        let toolbar = Rect { left: 0f32, top: 0f32, right: 50f32, bottom: 50f32 };
+        data.set_rejection_rules(RejectionRules { excluded: [toolbar].to_vec(), .. RejectionRules::default() });
+        let result = run_data(&mut data, &[
+            event(InputAction::Down, &[(0, 10f32)]),
+            event(InputAction::Move, &[(0, 60f32)]),
+            event(InputAction::Up, &[(0, 60f32)]),
+            event(InputAction::Down, &[(0, 60f32)]),
+            event(InputAction::Move, &[(0, 10f32)]),
+            event(InputAction::Up, &[(0, 10f32)]),
+        ]);
+        assert_eq!(result, [(0, false), (0, false), (0, true)].to_vec());
+    }
+}
@@ -13,11 +13,80 @@ pub struct TextureTarget {
     pub texture: Texture,
 }
 
+/// How a layer combines with what's under it.  Everything is premultiplied, so these work on
+/// the canvas and on screen alike.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum BlendMode {
+    Normal,
+    /// Darkens by the layer's color.  Exact where the canvas underneath is opaque.
+    Multiply,
+    Screen,
+    Add,
+    /// Cuts the layer's alpha out of what's underneath, like the eraser does.
+    Erase,
+}
+
+impl BlendMode {
+    pub fn from_i32(mode: i32) -> Option<BlendMode> {
+        match mode {
+            0 => Some(BlendMode::Normal),
+            1 => Some(BlendMode::Multiply),
+            2 => Some(BlendMode::Screen),
+            3 => Some(BlendMode::Add),
+            4 => Some(BlendMode::Erase),
+            _ => None,
+        }
+    }
+
+    pub fn to_i32(self) -> i32 {
+        match self {
+            BlendMode::Normal => 0,
+            BlendMode::Multiply => 1,
+            BlendMode::Screen => 2,
+            BlendMode::Add => 3,
+            BlendMode::Erase => 4,
+        }
+    }
+
+    /// Sets the gl blend function.  Blending has to be enabled separately.
+    pub fn apply(self) {
+        match self {
+            BlendMode::Normal => gl2::blend_func(gl2::ONE, gl2::ONE_MINUS_SRC_ALPHA),
+            BlendMode::Multiply => gl2::blend_func(gl2::DST_COLOR, gl2::ONE_MINUS_SRC_ALPHA),
+            BlendMode::Screen => gl2::blend_func(gl2::ONE, gl2::ONE_MINUS_SRC_COLOR),
+            BlendMode::Add => gl2::blend_func(gl2::ONE, gl2::ONE),
+            BlendMode::Erase => gl2::blend_func(gl2::ZERO, gl2::ONE_MINUS_SRC_ALPHA),
+        }
+    }
+}
+
+/// How a layer is composited, on screen and when it's copied down.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct LayerStyle {
+    pub blend: BlendMode,
+    /// 0 to 1, scaling the whole layer
+    pub opacity: f32,
+    /// Hidden layers aren't shown, and stay put instead of being copied down.
+    pub visible: bool,
+}
+
+impl LayerStyle {
+    pub fn default() -> LayerStyle {
+        LayerStyle { blend: BlendMode::Normal, opacity: 1f32, visible: true }
+    }
+
+    /// Whether compositing the layer could change anything.
+    pub fn is_shown(&self) -> bool {
+        self.visible && self.opacity > 0f32
+    }
+}
+
 pub struct PaintLayer<'a> {
     pub copyshader: Option<&'a CopyShader>,
     pub pointshader: Option<&'a PointShader>,
     pub target: TextureTarget,
     pub pointidx: i32,
+    pub style: LayerStyle,
 }
 
 pub struct CompletedLayer<'a, 'b> {
@@ -55,6 +124,7 @@ impl<'a> PaintLayer<'a> {
             pointshader: pointshader,
             target: TextureTarget::new(w, h, PixelFormat::RGBA),
             pointidx: pointidx,
+            style: LayerStyle::default(),
         }
     }
 
@@ -66,3 +136,12 @@ impl<'a> PaintLayer<'a> {
         }
     }
 }
+
+#[test]
+fn blend_modes_roundtrip() {
+    for &mode in [BlendMode::Normal, BlendMode::Multiply, BlendMode::Screen, BlendMode::Add, BlendMode::Erase].iter() {
+        assert_eq!(BlendMode::from_i32(mode.to_i32()), Some(mode));
+    }
+    assert_eq!(BlendMode::from_i32(-1), None);
+    assert_eq!(BlendMode::from_i32(5), None);
+}
@@ -0,0 +1,240 @@
+/// Minimal PNG support for canvas dumps, brush images, and reference images.
+///
+/// Writes 8-bit RGBA without row filters.  Reads non-interlaced 8-bit greyscale, grey+alpha,
+/// RGB and RGBA images, which covers everything shipped in assets/ and unibrushes/.
+
+use core::prelude::*;
+use core::borrow::IntoCow;
+use collections::vec::Vec;
+use std::old_io::{Writer, Reader, IoResult, File, BufferedWriter, BufferedReader};
+use std::old_path::Path;
+use flate;
+
+use glcommon::{GLResult, MString};
+
+static SIGNATURE: &'static [u8] = b"\x89PNG\r\n\x1a\n";
+const COLOR_TYPE_GREY: u8 = 0;
+const COLOR_TYPE_RGB: u8 = 2;
+const COLOR_TYPE_GREY_ALPHA: u8 = 4;
+const COLOR_TYPE_RGBA: u8 = 6;
+
+/// A decoded image, always expanded to RGBA, rows top to bottom.
+pub struct PngImage {
+    pub width: i32,
+    pub height: i32,
+    /// False if the file had no alpha channel, in which case every alpha byte is 255.
+    pub has_alpha: bool,
+    pub pixels: Vec<u8>,
+}
+
+impl PngImage {
+    /// One byte per pixel, the way android decodes a brush into an ALPHA_8 bitmap: the alpha
+    /// channel if there is one, otherwise the grey level.
+    pub fn to_alpha(&self) -> Vec<u8> {
+        let channel = if self.has_alpha { 3 } else { 0 };
+        self.pixels.chunks(4).map(|px| px[channel]).collect()
+    }
+}
+
+fn crc_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    for n in range(0, 256) {
+        let mut c = n as u32;
+        for _ in range(0, 8) {
+            c = if c & 1 != 0 { 0xedb88320 ^ (c >> 1) } else { c >> 1 };
+        }
+        table[n] = c;
+    }
+    table
+}
+
+fn crc32(table: &[u32; 256], chunks: &[&[u8]]) -> u32 {
+    let mut crc = 0xffffffffu32;
+    for chunk in chunks.iter() {
+        for &b in chunk.iter() {
+            crc = table[((crc ^ b as u32) & 0xff) as usize] ^ (crc >> 8);
+        }
+    }
+    crc ^ 0xffffffff
+}
+
+fn be_u32(bytes: &[u8]) -> u32 {
+    (bytes[0] as u32 << 24) | (bytes[1] as u32 << 16) | (bytes[2] as u32 << 8) | bytes[3] as u32
+}
+
+fn write_chunk<W: Writer>(w: &mut W, table: &[u32; 256], kind: &[u8], data: &[u8]) -> IoResult<()> {
+    try!(w.write_be_u32(data.len() as u32));
+    try!(w.write_all(kind));
+    try!(w.write_all(data));
+    w.write_be_u32(crc32(table, &[kind, data]))
+}
+
+/// Writes w*h RGBA pixels, rows top to bottom, as a PNG.
+pub fn write_png<W: Writer>(writer: &mut W, w: i32, h: i32, pixels: &[u8]) -> GLResult<()> {
+    let rowlen = w as usize * 4;
+    if w <= 0 || h <= 0 || pixels.len() != rowlen * h as usize {
+        return Err(format!("can't write {} bytes as a {}x{} png", pixels.len(), w, h).into_cow());
+    }
+    let mut raw = Vec::with_capacity((rowlen + 1) * h as usize);
+    for row in pixels.chunks(rowlen) {
+        raw.push(0u8); // filter type none
+        raw.push_all(row);
+    }
+    let compressed = match flate::deflate_bytes_zlib(raw.as_slice()) {
+        Some(bytes) => bytes,
+        None => return Err("png error: deflate failed".into_cow()),
+    };
+    let mut header = Vec::with_capacity(13);
+    for &dim in [w, h].iter() {
+        for shift in [24u32, 16, 8, 0].iter() {
+            header.push((dim as u32 >> *shift) as u8);
+        }
+    }
+    header.push_all(&[8u8, COLOR_TYPE_RGBA, 0, 0, 0]);
+
+    let table = crc_table();
+    try!(to_glresult(writer.write_all(SIGNATURE)));
+    try!(to_glresult(write_chunk(writer, &table, b"IHDR", header.as_slice())));
+    try!(to_glresult(write_chunk(writer, &table, b"IDAT", compressed.as_slice())));
+    to_glresult(write_chunk(writer, &table, b"IEND", &[]))
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i32 + b as i32 - c as i32;
+    let (pa, pb, pc) = ((p - a as i32).abs(), (p - b as i32).abs(), (p - c as i32).abs());
+    if pa <= pb && pa <= pc { a } else if pb <= pc { b } else { c }
+}
+
+/// Reverses the per-row filters, returning rows without their filter bytes.
+fn unfilter(data: &[u8], rowlen: usize, height: usize, bpp: usize) -> GLResult<Vec<u8>> {
+    let mut out: Vec<u8> = Vec::with_capacity(rowlen * height);
+    for (y, row) in data.chunks(rowlen + 1).enumerate() {
+        let (filter, row) = (row[0], &row[1..]);
+        let start = out.len();
+        for x in range(0, rowlen) {
+            let a = if x >= bpp { out[start + x - bpp] } else { 0 };
+            let b = if y > 0 { out[start + x - rowlen] } else { 0 };
+            let c = if y > 0 && x >= bpp { out[start + x - rowlen - bpp] } else { 0 };
+            let predicted = match filter {
+                0 => 0,
+                1 => a,
+                2 => b,
+                3 => ((a as u16 + b as u16) / 2) as u8,
+                4 => paeth(a, b, c),
+                x => return Err(format!("png error: unknown filter type {}", x).into_cow()),
+            };
+            out.push(((row[x] as u16 + predicted as u16) & 0xff) as u8);
+        }
+    }
+    Ok(out)
+}
+
+pub fn read_png<R: Reader>(reader: &mut R) -> GLResult<PngImage> {
+    let signature = try!(to_glresult(reader.read_exact(SIGNATURE.len())));
+    if signature.as_slice() != SIGNATURE {
+        return Err("png error: not a png file".into_cow());
+    }
+    let table = crc_table();
+    let mut header: Option<(i32, i32, u8)> = None;
+    let mut idat = Vec::new();
+    loop {
+        let len = try!(to_glresult(reader.read_be_u32()));
+        let kind = try!(to_glresult(reader.read_exact(4)));
+        let data = try!(to_glresult(reader.read_exact(len as usize)));
+        let crc = try!(to_glresult(reader.read_be_u32()));
+        if crc != crc32(&table, &[kind.as_slice(), data.as_slice()]) {
+            return Err("png error: bad chunk checksum".into_cow());
+        }
+        let kind = kind.as_slice();
+        if kind == b"IHDR".as_slice() {
+            if data.len() != 13 {
+                return Err("png error: bad header".into_cow());
+            }
+            let (depth, colortype, interlace) = (data[8], data[9], data[12]);
+            if depth != 8 || interlace != 0 {
+                return Err(format!("png error: unsupported bit depth {} or interlacing {}", depth, interlace).into_cow());
+            }
+            header = Some((be_u32(&data[0..4]) as i32, be_u32(&data[4..8]) as i32, colortype));
+        } else if kind == b"IDAT".as_slice() {
+            idat.push_all(data.as_slice());
+        } else if kind == b"IEND".as_slice() {
+            break;
+        }
+        // ancillary chunks don't matter here, and PLTE only matters for palettes
+    }
+    let (width, height, colortype) = match header {
+        Some(x) => x,
+        None => return Err("png error: missing header".into_cow()),
+    };
+    let channels = match colortype {
+        COLOR_TYPE_GREY => 1,
+        COLOR_TYPE_GREY_ALPHA => 2,
+        COLOR_TYPE_RGB => 3,
+        COLOR_TYPE_RGBA => 4,
+        x => return Err(format!("png error: unsupported color type {}", x).into_cow()),
+    };
+    let raw = match flate::inflate_bytes_zlib(idat.as_slice()) {
+        Some(bytes) => bytes,
+        None => return Err("png error: corrupt image data".into_cow()),
+    };
+    let rowlen = width as usize * channels;
+    if raw.as_slice().len() != (rowlen + 1) * height as usize {
+        return Err("png error: image data doesn't match dimensions".into_cow());
+    }
+    let rows = try!(unfilter(raw.as_slice(), rowlen, height as usize, channels));
+    let mut pixels = Vec::with_capacity(width as usize * height as usize * 4);
+    for px in rows.chunks(channels) {
+        let rgba = match channels {
+            1 => [px[0], px[0], px[0], 255],
+            2 => [px[0], px[0], px[0], px[1]],
+            3 => [px[0], px[1], px[2], 255],
+            _ => [px[0], px[1], px[2], px[3]],
+        };
+        pixels.push_all(&rgba);
+    }
+    Ok(PngImage {
+        width: width,
+        height: height,
+        has_alpha: colortype == COLOR_TYPE_GREY_ALPHA || colortype == COLOR_TYPE_RGBA,
+        pixels: pixels,
+    })
+}
+
+pub fn save_png(path: &str, w: i32, h: i32, pixels: &[u8]) -> GLResult<()> {
+    let file = try!(to_glresult(File::create(&Path::new(path))));
+    let mut writer = BufferedWriter::new(file);
+    try!(write_png(&mut writer, w, h, pixels));
+    to_glresult(writer.flush())
+}
+
+pub fn load_png(path: &str) -> GLResult<PngImage> {
+    let file = try!(to_glresult(File::open(&Path::new(path))));
+    read_png(&mut BufferedReader::new(file))
+}
+
+fn to_glresult<T>(result: IoResult<T>) -> GLResult<T> {
+    result.map_err(|e| -> MString { format!("png error: {}", e).into_cow() })
+}
+
+#[test]
+fn png_crc() {
+    assert_eq!(crc32(&crc_table(), &[b"IEND"]), 0xae426082);
+}
+
+#[test]
+fn png_roundtrip() {
+    use std::old_io::{MemReader, MemWriter};
+    let pixels: Vec<u8> = range(0u8, 24).collect();
+    let mut writer = MemWriter::new();
+    write_png(&mut writer, 3, 2, pixels.as_slice()).unwrap();
+    let image = read_png(&mut MemReader::new(writer.into_inner())).unwrap();
+    assert_eq!((image.width, image.height, image.has_alpha), (3, 2, true));
+    assert_eq!(image.pixels, pixels);
+}
+
+#[test]
+fn png_reads_grey_alpha() {
+    let image = load_png("../unibrushes/Kittens/xneko.png").unwrap();
+    assert_eq!((image.width, image.height, image.has_alpha), (128, 288, true));
+    assert_eq!(image.to_alpha().len(), 128 * 288);
+}
@@ -25,12 +25,16 @@ impl AsSelf<f32> for f32 {
 pub fn as_self<T, U: AsSelf<T>>(u: &U) -> &T { u.as_self() }
 
 /// Holds data from motionevent entries.
+/// Inputs without stylus axes report full pressure and no tilt or orientation.
 #[derive(Clone, Debug, PartialEq, Copy)]
 #[repr(C)]
 pub struct PaintPoint {
     pub pos: Coordinate,
     pub time: f32, // floating-point seconds
     pub size: f32,
+    pub pressure: f32, // usually 0 to 1
+    pub tilt: f32, // radians from perpendicular to the screen
+    pub orientation: f32, // radians, -pi to pi, 0 is pointing up
 }
 
 /// Holds raw data used for pointshader attribs.
@@ -45,15 +49,50 @@ pub struct ShaderPaintPoint {
     pub speed: Coordinate,
     pub distance: f32,
     pub counter: f32, // could become a uniform? only floating-point allowed for attribs
+    pub pressure: f32,
+    pub tilt: f32,
+    pub orientation: f32,
+    pub tool: f32, // ToolType as f32
+    pub buttons: f32, // button bits as f32
 }
 
+/// What a pointer is, with the same values as android's MotionEvent.TOOL_TYPE_*.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[repr(u8)]
+pub enum ToolType {
+    Unknown = 0,
+    Finger = 1,
+    Stylus = 2,
+    Mouse = 3,
+    Eraser = 4,
+}
+
+impl ToolType {
+    pub fn from_u8(tool: u8) -> ToolType {
+        match tool {
+            1 => ToolType::Finger,
+            2 => ToolType::Stylus,
+            3 => ToolType::Mouse,
+            4 => ToolType::Eraser,
+            _ => ToolType::Unknown,
+        }
+    }
+}
+
+/// Button bits, with the same values as android's MotionEvent.BUTTON_*.
+pub const BUTTON_PRIMARY: u32 = 0x01;
+pub const BUTTON_SECONDARY: u32 = 0x02;
+pub const BUTTON_TERTIARY: u32 = 0x04;
+pub const BUTTON_STYLUS_PRIMARY: u32 = 0x20;
+pub const BUTTON_STYLUS_SECONDARY: u32 = 0x40;
+
 /// Pointer state, corresponding to a single motionevent historical entry
 /// Stop, unsurprisingly, indicates a pointer has been lifted
 /// this enables us to use raw motionevent pointer ids, which get recycled regularly
 /// it's arguably simpler than ensuring each pointer gets a unique queue for its entire
 /// lifetime and maintaining an up-to-date pointer id -> queue mapping
 /// FrameStop indicates that we should stop reading 
-#[derive(PartialEq, Copy, Clone)]
+#[derive(PartialEq, Copy, Clone, Debug)]
 pub enum PointInfo {
     Stop,
     FrameStop,
@@ -69,10 +108,23 @@ pub enum ShaderPointEvent {
 }
 
 /// A single entry in the point queue.
-#[derive(PartialEq, Copy, Clone)]
+/// Stops and FrameStops don't come from a tool, and have ToolType::Unknown and no buttons.
+#[derive(PartialEq, Copy, Clone, Debug)]
 pub struct PointEntry {
     pub index: i32,
     pub entry: PointInfo,
+    pub tool: ToolType,
+    pub buttons: u32,
+}
+
+impl PointEntry {
+    pub fn stop(index: i32) -> PointEntry {
+        PointEntry { index: index, entry: PointInfo::Stop, tool: ToolType::Unknown, buttons: 0 }
+    }
+
+    pub fn framestop() -> PointEntry {
+        PointEntry { index: 0, entry: PointInfo::FrameStop, tool: ToolType::Unknown, buttons: 0 }
+    }
 }
 
 pub type PointConsumer = mpsc::Receiver<PointEntry>;
@@ -0,0 +1,216 @@
+/// Line-delimited JSON format for point queue entries, for stroke fixtures and input diffs.
+///
+/// Each line holds one PointEntry:
+///
+///     {"index":0,"type":"point","x":12.5,"y":40,"time":0.016,"size":0.2,"pressure":0.8,"tilt":0,"orientation":0,"tool":"stylus","buttons":0}
+///     {"index":0,"type":"stop"}
+///     {"index":0,"type":"framestop"}
+///
+/// Keys are always written in that order so recordings diff cleanly.  Blank lines are skipped
+/// when reading, and points without stylus axes get full pressure and no tilt or orientation.
+/// Points without a tool are read as an unknown tool with no buttons held.
+
+use core::prelude::*;
+use core::borrow::IntoCow;
+use collections::vec::Vec;
+use collections::string::String;
+use std::old_io::{Writer, Buffer, IoResult, File, BufferedReader, BufferedWriter};
+use std::old_path::Path;
+use serialize::json::Json;
+
+use drawevent::DrawEvent;
+use point::{PointEntry, PointInfo, PaintPoint, Coordinate, PointProducer, ToolType};
+use glcommon::{GLResult, MString};
+
+fn to_glresult<T>(result: IoResult<T>) -> GLResult<T> {
+    result.map_err(|e| -> MString { format!("point file error: {}", e).into_cow() })
+}
+
+fn tool_name(tool: ToolType) -> &'static str {
+    match tool {
+        ToolType::Unknown => "unknown",
+        ToolType::Finger => "finger",
+        ToolType::Stylus => "stylus",
+        ToolType::Mouse => "mouse",
+        ToolType::Eraser => "eraser",
+    }
+}
+
+fn parse_tool(name: &str) -> GLResult<ToolType> {
+    match name {
+        "unknown" => Ok(ToolType::Unknown),
+        "finger" => Ok(ToolType::Finger),
+        "stylus" => Ok(ToolType::Stylus),
+        "mouse" => Ok(ToolType::Mouse),
+        "eraser" => Ok(ToolType::Eraser),
+        x => Err(format!("unknown tool \"{}\"", x).into_cow()),
+    }
+}
+
+pub fn format_entry(entry: &PointEntry) -> String {
+    match entry.entry {
+        PointInfo::Point(p) => format!("{{\"index\":{},\"type\":\"point\",\"x\":{},\"y\":{},\"time\":{},\"size\":{},\"pressure\":{},\"tilt\":{},\"orientation\":{},\"tool\":\"{}\",\"buttons\":{}}}",
+                                       entry.index, p.pos.x, p.pos.y, p.time, p.size, p.pressure, p.tilt, p.orientation,
+                                       tool_name(entry.tool), entry.buttons),
+        PointInfo::Stop => format!("{{\"index\":{},\"type\":\"stop\"}}", entry.index),
+        PointInfo::FrameStop => format!("{{\"index\":{},\"type\":\"framestop\"}}", entry.index),
+    }
+}
+
+fn get_f32(json: &Json, key: &str) -> GLResult<f32> {
+    match json.find(key).and_then(|x| x.as_f64()) {
+        Some(x) => Ok(x as f32),
+        None => Err(format!("missing number \"{}\"", key).into_cow()),
+    }
+}
+
+fn get_f32_or(json: &Json, key: &str, default: f32) -> GLResult<f32> {
+    match json.find(key) {
+        Some(_) => get_f32(json, key),
+        None => Ok(default),
+    }
+}
+
+pub fn parse_entry(line: &str) -> GLResult<PointEntry> {
+    let json = match Json::from_str(line) {
+        Ok(json) => json,
+        Err(e) => return Err(format!("invalid json: {:?}", e).into_cow()),
+    };
+    let index = match json.find("index").and_then(|x| x.as_i64()) {
+        Some(x) => x as i32,
+        None => return Err("missing integer \"index\"".into_cow()),
+    };
+    let tool = match json.find("tool") {
+        Some(tool) => match tool.as_string() {
+            Some(name) => try!(parse_tool(name)),
+            None => return Err("\"tool\" isn't a string".into_cow()),
+        },
+        None => ToolType::Unknown,
+    };
+    let buttons = match json.find("buttons") {
+        Some(buttons) => match buttons.as_u64() {
+            Some(x) => x as u32,
+            None => return Err("\"buttons\" isn't an integer".into_cow()),
+        },
+        None => 0,
+    };
+    let info = match json.find("type").and_then(|x| x.as_string()) {
+        Some("point") => PointInfo::Point(PaintPoint {
+            pos: Coordinate { x: try!(get_f32(&json, "x")), y: try!(get_f32(&json, "y")) },
+            time: try!(get_f32(&json, "time")),
+            size: try!(get_f32(&json, "size")),
+            pressure: try!(get_f32_or(&json, "pressure", 1f32)),
+            tilt: try!(get_f32_or(&json, "tilt", 0f32)),
+            orientation: try!(get_f32_or(&json, "orientation", 0f32)),
+        }),
+        Some("stop") => PointInfo::Stop,
+        Some("framestop") => PointInfo::FrameStop,
+        Some(x) => return Err(format!("unknown entry type \"{}\"", x).into_cow()),
+        None => return Err("missing string \"type\"".into_cow()),
+    };
+    Ok(PointEntry { index: index, entry: info, tool: tool, buttons: buttons })
+}
+
+pub fn write_entries<W: Writer>(w: &mut W, entries: &[PointEntry]) -> GLResult<()> {
+    for entry in entries.iter() {
+        try!(to_glresult(w.write_line(format_entry(entry).as_slice())));
+    }
+    Ok(())
+}
+
+pub fn read_entries<B: Buffer>(r: &mut B) -> GLResult<Vec<PointEntry>> {
+    let mut entries = Vec::new();
+    for (lineno, line) in r.lines().enumerate() {
+        let line = try!(to_glresult(line));
+        let line = line.as_slice().trim();
+        if line.is_empty() {
+            continue;
+        }
+        match parse_entry(line) {
+            Ok(entry) => entries.push(entry),
+            Err(e) => return Err(format!("line {}: {}", lineno + 1, e).into_cow()),
+        }
+    }
+    Ok(entries)
+}
+
+pub fn save_to_file(path: &str, entries: &[PointEntry]) -> GLResult<()> {
+    let file = try!(to_glresult(File::create(&Path::new(path))));
+    let mut writer = BufferedWriter::new(file);
+    try!(write_entries(&mut writer, entries));
+    to_glresult(writer.flush())
+}
+
+pub fn load_from_file(path: &str) -> GLResult<Vec<PointEntry>> {
+    let file = try!(to_glresult(File::open(&Path::new(path))));
+    read_entries(&mut BufferedReader::new(file))
+}
+
+/// Pulls the point entries out of a recorded event log, in the order they were consumed.
+pub fn entries_from_events(events: &[DrawEvent]) -> Vec<PointEntry> {
+    events.iter().filter_map(|e| match *e {
+        DrawEvent::Point(p) => Some(p),
+        _ => None,
+    }).collect()
+}
+
+/// Queues entries for the MotionEventConsumer on the other end of the producer.
+pub fn play_entries(queue: &mut PointProducer, entries: &[PointEntry]) {
+    for entry in entries.iter() {
+        // nothing to do if the consumer is gone
+        let _ = queue.send(*entry);
+    }
+}
+
+#[test]
+fn entries_roundtrip() {
+    use std::old_io::{MemReader, MemWriter};
+    let entries = read_entries(&mut MemReader::new(include_bytes!("../testdata/strokes/line.jsonl").to_vec())).unwrap();
+    assert_eq!(entries.len(), 7);
+    let mut writer = MemWriter::new();
+    write_entries(&mut writer, entries.as_slice()).unwrap();
+    let reread = read_entries(&mut MemReader::new(writer.into_inner())).unwrap();
+    assert_eq!(entries, reread);
+}
+
+#[test]
+fn bad_lines_report_position() {
+    use std::old_io::MemReader;
+    let text = b"{\"index\":0,\"type\":\"stop\"}\n\n{\"index\":0,\"type\":\"point\",\"x\":1}\n";
+    let err = read_entries(&mut MemReader::new(text.to_vec())).unwrap_err();
+    assert!(err.as_slice().starts_with("line 3:"));
+}
+
+#[test]
+fn tool_defaults_and_roundtrips() {
+    let bare = parse_entry("{\"index\":1,\"type\":\"point\",\"x\":1,\"y\":2,\"time\":0,\"size\":1}").unwrap();
+    assert_eq!((bare.tool, bare.buttons), (ToolType::Unknown, 0));
+    let mut eraser = bare;
+    eraser.tool = ToolType::Eraser;
+    eraser.buttons = 0x20;
+    assert_eq!(parse_entry(format_entry(&eraser).as_slice()).unwrap(), eraser);
+    assert!(parse_entry("{\"index\":1,\"type\":\"stop\",\"tool\":\"crayon\"}").is_err());
+}
+
+#[test]
+fn playback_through_next_point() {
+    use glpoint::{create_motion_event_handler, next_point};
+    use drawevent::Events;
+    use point::ShaderPointEvent;
+    use smoothing::StrokeFilter;
+    let entries = read_entries(&mut ::std::old_io::MemReader::new(include_bytes!("../testdata/strokes/line.jsonl").to_vec())).unwrap();
+    let (mut consumer, mut producer) = create_motion_event_handler(0);
+    let mut events = Events::new();
+    play_entries(&mut producer.producer, entries.as_slice());
+    let mut kinds = String::new();
+    for _ in range(0, entries.len()) {
+        kinds.push(match next_point(&mut consumer, &mut events, &StrokeFilter::default()).0 {
+            ShaderPointEvent::Down(_) => 'd',
+            ShaderPointEvent::Move(_, _) => 'm',
+            ShaderPointEvent::Up(_) => 'u',
+            ShaderPointEvent::NoEvent => '-',
+        });
+    }
+    assert_eq!(kinds.as_slice(), "dmmmmu-");
+    assert_eq!(consumer.active_pointers(), 0);
+}
@@ -26,11 +26,22 @@ pub struct PointShader {
     pointer_handle: Option<GLuint>,
     speed_handle: Option<GLuint>,
     distance_handle: Option<GLuint>,
+    pressure_handle: Option<GLuint>,
+    tilt_handle: Option<GLuint>,
+    orientation_handle: Option<GLuint>,
     back_buffer_handle: Option<GLint>,
     texture_size_handle: GLint,
     pub source: (MString, MString),
 }
 
+impl PointShader {
+    /// Whether points are drawn at the default vertex shader's size, 40 pixels per unit of brush
+    /// size.  Any other vertex shader sets gl_PointSize however it likes.
+    pub fn has_default_size(&self) -> bool {
+        self.source.0.as_slice() == DEFAULT_VERTEX_SHADER
+    }
+}
+
 impl Shader for PointShader {
     fn new(vert: MString, frag: MString) -> GLResult<PointShader> {
         let program = try!(glcommon::create_program(vert.as_slice(), frag.as_slice()));
@@ -51,6 +62,9 @@ impl Shader for PointShader {
                     pointer_handle: get_shader_handle(program, "vPointer"),
                     speed_handle: get_shader_handle(program, "vSpeed"),
                     distance_handle: get_shader_handle(program, "vDistance"),
+                    pressure_handle: get_shader_handle(program, "vPressure"),
+                    tilt_handle: get_shader_handle(program, "vTilt"),
+                    orientation_handle: get_shader_handle(program, "vOrientation"),
                     back_buffer_handle: get_uniform_handle_option(program, "backbuffer"),
                     texture_size_handle: gl2::get_uniform_location(program, "texturesize"),
                     source: (vert, frag),
@@ -101,6 +115,18 @@ impl PointShader {
             glattrib_f32!(dh, 1, points, distance);
         });
 
+        self.pressure_handle.map(|ph| {
+            glattrib_f32!(ph, 1, points, pressure);
+        });
+
+        self.tilt_handle.map(|th| {
+            glattrib_f32!(th, 1, points, tilt);
+        });
+
+        self.orientation_handle.map(|oh| {
+            glattrib_f32!(oh, 1, points, orientation);
+        });
+
         self.back_buffer_handle.map(|bb| {
             gl_bindtexture!(1, gl2::TEXTURE_2D, backbuffer.texture, bb);
         });
@@ -2,19 +2,110 @@ use core::prelude::*;
 use core::mem;
 use core::cmp::min;
 use alloc::boxed::Box;
-use jni::{jint, jobject, jclass, jfieldID, JNIEnv, JNINativeMethod};
-use android::input::AInputEvent;
+use collections::vec::Vec;
+use jni::{jint, jfloat, jboolean, jfloatArray, jobject, jclass, jfieldID, JNIEnv, JNINativeMethod};
+use android::input::*;
 
 use glpoint;
+use point::{PaintPoint, Coordinate, ToolType};
+use motionevent::{InputEvent, InputAction, RejectionRules, Rect};
+use gesture::{GestureEvent, GestureSettings};
 use rustjni::{register_classmethods, jpointer, get_jpointer};
 
 static mut MOTION_CLASS: jclass = 0 as jclass;
 static mut MOTIONEVENT_NATIVE_PTR_FIELD: jfieldID = 0 as jfieldID;
 
+static AMOTION_EVENT_ACTION_POINTER_INDEX_SHIFT: usize = 8;
+
+/// InputEvent backed by the ndk's AInputEvent.
+pub struct AndroidMotionEvent(pub *const AInputEvent);
+
+fn ns_to_seconds(nanos: i64) -> f32 {
+    (nanos / 1000) as f32 / 1000000f32
+}
+
+impl InputEvent for AndroidMotionEvent {
+    fn action(&self) -> InputAction {
+        let evt = self.0;
+        if unsafe { AInputEvent_getType(evt) } as u32 == AINPUT_EVENT_TYPE_KEY {
+            return InputAction::Key;
+        }
+        let full_action = unsafe { AMotionEvent_getAction(evt) } as u32;
+        let (action_event, action_index): (u32, u32) = (full_action & AMOTION_EVENT_ACTION_MASK, (full_action & AMOTION_EVENT_ACTION_POINTER_INDEX_MASK) >> AMOTION_EVENT_ACTION_POINTER_INDEX_SHIFT);
+        match action_event {
+            AMOTION_EVENT_ACTION_DOWN => InputAction::Down,
+            AMOTION_EVENT_ACTION_UP => InputAction::Up,
+            AMOTION_EVENT_ACTION_CANCEL => InputAction::Cancel,
+            AMOTION_EVENT_ACTION_POINTER_UP => InputAction::PointerUp(self.pointer_id(action_index as usize)),
+            AMOTION_EVENT_ACTION_POINTER_DOWN => InputAction::PointerDown(self.pointer_id(action_index as usize)),
+            AMOTION_EVENT_ACTION_MOVE => InputAction::Move,
+            AMOTION_EVENT_ACTION_HOVER_ENTER => InputAction::HoverEnter,
+            AMOTION_EVENT_ACTION_HOVER_MOVE => InputAction::HoverMove,
+            AMOTION_EVENT_ACTION_HOVER_EXIT => InputAction::HoverExit,
+            unknown => InputAction::Unknown(unknown),
+        }
+    }
+
+    fn pointer_count(&self) -> usize {
+        unsafe { AMotionEvent_getPointerCount(self.0) as usize }
+    }
+
+    fn pointer_id(&self, ptr: usize) -> i32 {
+        unsafe { AMotionEvent_getPointerId(self.0, ptr as size_t) }
+    }
+
+    fn history_size(&self) -> usize {
+        unsafe { AMotionEvent_getHistorySize(self.0) as usize }
+    }
+
+    fn historical_point(&self, ptr: usize, hist: usize) -> PaintPoint {
+        let (evt, ptr, hist) = (self.0, ptr as size_t, hist as size_t);
+        unsafe {
+            PaintPoint {
+                pos: Coordinate {
+                    x: AMotionEvent_getHistoricalX(evt, ptr, hist),
+                    y: AMotionEvent_getHistoricalY(evt, ptr, hist),
+                },
+                time: ns_to_seconds(AMotionEvent_getHistoricalEventTime(evt, hist)),
+                size: AMotionEvent_getHistoricalSize(evt, ptr, hist),
+                pressure: AMotionEvent_getHistoricalPressure(evt, ptr, hist),
+                tilt: AMotionEvent_getHistoricalAxisValue(evt, AMOTION_EVENT_AXIS_TILT as i32, ptr, hist),
+                orientation: AMotionEvent_getHistoricalOrientation(evt, ptr, hist),
+            }
+        }
+    }
+
+    fn current_point(&self, ptr: usize) -> PaintPoint {
+        let (evt, ptr) = (self.0, ptr as size_t);
+        unsafe {
+            PaintPoint {
+                pos: Coordinate {
+                    x: AMotionEvent_getX(evt, ptr),
+                    y: AMotionEvent_getY(evt, ptr),
+                },
+                time: ns_to_seconds(AMotionEvent_getEventTime(evt)),
+                size: AMotionEvent_getSize(evt, ptr),
+                pressure: AMotionEvent_getPressure(evt, ptr),
+                tilt: AMotionEvent_getAxisValue(evt, AMOTION_EVENT_AXIS_TILT as i32, ptr),
+                orientation: AMotionEvent_getOrientation(evt, ptr),
+            }
+        }
+    }
+
+    fn tool_type(&self, ptr: usize) -> ToolType {
+        ToolType::from_u8(unsafe { AMotionEvent_getToolType(self.0, ptr as size_t) } as u8)
+    }
+
+    fn button_state(&self) -> u32 {
+        unsafe { AMotionEvent_getButtonState(self.0) as u32 }
+    }
+}
+
 unsafe extern "C" fn init_motion_event_handler(env: *mut JNIEnv, _: jobject, width: jint, height: jint) -> jobject {
     let left = min(30, width / 10);
     let _ = height;
-    let (consumer, producer) = glpoint::create_motion_event_handler(left);
+    let (consumer, mut producer) = glpoint::create_motion_event_handler(left);
+    glpoint::set_gestures(&mut producer, Some(GestureSettings::default()));
     let (consumer, producer) = (Box::new(consumer), Box::new(producer));
     let pairclass = ((**env).FindClass)(env, cstr!("com/github/wartman4404/gldraw/MotionEventHandlerPair"));
     let constructor = ((**env).GetMethodID)(env, pairclass, cstr!("<init>"), cstr!("(II)V"));
@@ -31,9 +122,27 @@ unsafe extern "C" fn destroy_motion_event_handler(env: *mut JNIEnv, _: jobject,
     glpoint::destroy_motion_event_handler(mem::transmute(consumer), mem::transmute(producer));
 }
 
-unsafe extern "C" fn native_append_motion_event(env: *mut JNIEnv, _: jobject, handler: jpointer, evtobj: jobject) {
+/// Returns the number of fingers in a tap gesture, or 0 if there wasn't one.
+unsafe extern "C" fn native_append_motion_event(env: *mut JNIEnv, _: jobject, handler: jpointer, evtobj: jobject) -> jint {
     let evtptr = ((**env).GetIntField)(env, evtobj, MOTIONEVENT_NATIVE_PTR_FIELD);
-    glpoint::jni_append_motion_event(mem::transmute(handler), evtptr as *const AInputEvent);
+    match glpoint::append_input_event(mem::transmute(handler), &AndroidMotionEvent(evtptr as *const AInputEvent)) {
+        Some(GestureEvent::Tap(fingers)) => fingers as jint,
+        _ => 0,
+    }
+}
+
+/// Excluded areas come as a flat array of left, top, right, bottom.  A max contact size of 0 or
+/// less turns that rule off.
+unsafe extern "C" fn native_set_rejection_rules(env: *mut JNIEnv, _: jobject, handler: jpointer, max_contact_size: jfloat, reject_fingers_near_stylus: jboolean, java_rects: jfloatArray) {
+    let count = ((**env).GetArrayLength)(env, java_rects) as usize;
+    let mut coords: Vec<f32> = range(0, count).map(|_| 0f32).collect();
+    ((**env).GetFloatArrayRegion)(env, java_rects, 0, count as jint, coords.as_mut_ptr());
+    let rules = RejectionRules {
+        max_contact_size: if max_contact_size > 0f32 { Some(max_contact_size) } else { None },
+        reject_fingers_near_stylus: reject_fingers_near_stylus != 0,
+        excluded: coords.chunks(4).filter(|c| c.len() == 4).map(|c| Rect { left: c[0], top: c[1], right: c[2], bottom: c[3] }).collect(),
+    };
+    glpoint::set_rejection_rules(mem::transmute(handler), rules);
 }
 
 unsafe extern "C" fn native_pause_motion_event(_: *mut JNIEnv, _: jobject, handler: jpointer) {
@@ -47,8 +156,9 @@ pub unsafe fn init(env: *mut JNIEnv) {
     debug_logi!("got motion classes");
 
     let producermethods = [
-        native_method!("nativeAppendMotionEvent", "(ILandroid/view/MotionEvent;)V", native_append_motion_event),
+        native_method!("nativeAppendMotionEvent", "(ILandroid/view/MotionEvent;)I", native_append_motion_event),
         native_method!("nativePauseMotionEvent", "(I)V", native_pause_motion_event),
+        native_method!("nativeSetRejectionRules", "(IFZ[F)V", native_set_rejection_rules),
     ];
     register_classmethods(env, cstr!("com/github/wartman4404/gldraw/MotionEventProducer$"), &producermethods);
 
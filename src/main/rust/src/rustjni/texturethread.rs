@@ -1,19 +1,22 @@
 use core::prelude::*;
 use core::mem;
 use alloc::boxed::Box;
-use jni::{jobject, jclass, jmethodID, JNIEnv, jint, jfloat, jboolean, jfloatArray, JNINativeMethod};
+use jni::{jobject, jclass, jmethodID, JNIEnv, jint, jlong, jfloat, jboolean, jfloatArray, jstring, JNINativeMethod};
 
 use glcommon::GLResult;
-use glinit::GLInit;
-use drawevent::event_stream::EventStream;
+use glinit::{GLInit, EraserMode, Reframe, UndoBudget, ExportAlpha};
+use drawevent::event_stream::{EventStream, EventState, ReplaySpeed};
 use rustjni::android_bitmap::AndroidBitmap;
 use drawevent::Events;
-use matrix::Matrix;
+use matrix::{Matrix, Affine, Rotation};
+use paintlayer::{LayerStyle, BlendMode};
 
-use rustjni::{register_classmethods, CaseClass, get_safe_data, str_to_jstring, GLInitEvents, JNIUndoCallback, JNICallbackClosure, jpointer, GL_EXCEPTION};
+use rustjni::{register_classmethods, CaseClass, get_safe_data, get_string, str_to_jstring, GLInitEvents, JNIUndoCallback, JNICallbackClosure, jpointer, GL_EXCEPTION};
 use jni_helpers::ToJValue;
 use jni_constants::*;
 use lua_geom;
+use sessionfile;
+use smoothing;
 
 static mut LUA_EXCEPTION: CaseClass = CaseClass { constructor: 0 as jmethodID, class: 0 as jclass };
 static mut RUNTIME_EXCEPTION: CaseClass = CaseClass { constructor: 0 as jmethodID, class: 0 as jclass };
@@ -36,11 +39,13 @@ unsafe fn rethrow_lua_result(env: *mut JNIEnv, result: GLResult<()>) {
     }
 }
 
-unsafe extern "C" fn init_gl(env: *mut JNIEnv, _: jobject, w: jint, h: jint, callback: jobject) -> jpointer {
+/// A canvas size of 0 uses the surface size.
+unsafe extern "C" fn init_gl(env: *mut JNIEnv, _: jobject, w: jint, h: jint, canvasw: jint, canvash: jint, callback: jobject) -> jpointer {
     let mut events = Events::new();
-    let glinit = GLInit::setup_graphics(w, h, &mut events);
+    let canvas = if canvasw > 0 && canvash > 0 { (canvasw, canvash) } else { (w, h) };
+    let glinit = GLInit::setup_graphics_with_canvas((w, h), canvas, &mut events);
     let jni_undo_callback = JNIUndoCallback::new(env, callback);
-    let _ = lua_geom::ensure_lua_exists(w, h);
+    let _ = lua_geom::ensure_lua_exists(canvas.0, canvas.1);
     mem::transmute(Box::new(GLInitEvents {
         glinit: glinit,
         events: events,
@@ -111,16 +116,68 @@ unsafe extern "C" fn clear_framebuffer(_: *mut JNIEnv, _: jobject, data: jpointe
     data.glinit.clear_buffer();
 }
 
-pub unsafe extern "C" fn export_pixels(env: *mut JNIEnv, _: jobject, data: jpointer) -> jobject {
+pub unsafe extern "C" fn export_pixels(env: *mut JNIEnv, _: jobject, data: jpointer, keep_alpha: jboolean) -> jobject {
     let glinit = &mut get_safe_data(data).glinit;
     let (w, h) = glinit.get_buffer_dimensions();
     let mut bitmap = AndroidBitmap::new(env, w, h);
-    glinit.get_pixels(bitmap.as_mut_slice().unwrap());
-    bitmap.set_premultiplied(true);
-    bitmap.obj
+    let alpha = if keep_alpha != 0 { ExportAlpha::Straight } else { ExportAlpha::Opaque };
+    let result = glinit.get_pixels(bitmap.as_mut_slice().unwrap(), alpha);
+    mark_premultiplied(&mut bitmap, alpha);
+    let obj = bitmap.obj;
+    // as in draw_image, the pixels have to be unlocked before anything can be thrown
+    mem::drop(bitmap);
+    try_or_throw!(env, GL_EXCEPTION, result, 0 as jobject);
+    obj
+}
+
+/// Tells android whether exported pixels have their alpha premultiplied.
+unsafe fn mark_premultiplied(bitmap: &mut AndroidBitmap, alpha: ExportAlpha) {
+    let premultiplied = alpha == ExportAlpha::Opaque;
+    if !bitmap.set_premultiplied(premultiplied) && !premultiplied {
+        loge!("couldn't mark exported bitmap as unpremultiplied, so its colors will be off");
+    }
+}
+
+pub unsafe extern "C" fn export_layer_pixels(env: *mut JNIEnv, _: jobject, data: jpointer, layer: jint, keep_alpha: jboolean) -> jobject {
+    let glinit = &mut get_safe_data(data).glinit;
+    let (w, h) = glinit.get_buffer_dimensions();
+    let mut bitmap = AndroidBitmap::new(env, w, h);
+    let alpha = if keep_alpha != 0 { ExportAlpha::Straight } else { ExportAlpha::Opaque };
+    let result = glinit.get_layer_pixels(layer, bitmap.as_mut_slice().unwrap(), alpha);
+    mark_premultiplied(&mut bitmap, alpha);
+    let obj = bitmap.obj;
+    // as in draw_image, the pixels have to be unlocked before anything can be thrown
+    mem::drop(bitmap);
+    try_or_throw!(env, GL_EXCEPTION, result, 0 as jobject);
+    obj
+}
+
+/// placement is the nine values of an android.graphics.Matrix, taking bitmap pixels to canvas
+/// pixels; anything but the affine part is ignored.
+unsafe extern "C" fn draw_layer_image(env: *mut JNIEnv, _: jobject, data: jpointer, layer: jint, bitmap: jobject, placement: jfloatArray) {
+    let mut values = [0f32; 9];
+    ((**env).GetFloatArrayRegion)(env, placement, 0, 9, values.as_mut_ptr());
+    let placement = Affine { a: values[0], c: values[1], tx: values[2], b: values[3], d: values[4], ty: values[5] };
+    let bitmap = AndroidBitmap::from_jobject(env, bitmap);
+    let result = match bitmap.as_slice() {
+        Ok(pixels) => {
+            let data = get_safe_data(data);
+            let (w, h) = (bitmap.info.width as i32, bitmap.info.height as i32);
+            let result = data.glinit.draw_layer_image(layer, w, h, pixels, placement);
+            if result.is_ok() {
+                data.events.draw_layer_image(layer, (w, h), pixels, placement);
+            }
+            result
+        },
+        Err(err) => Err(err),
+    };
+    // as in draw_image, the pixels have to be unlocked before anything can be thrown
+    mem::drop(bitmap);
+    try_or_throw!(env, GL_EXCEPTION, result);
 }
 
 pub unsafe extern "C" fn draw_image(env: *mut JNIEnv, _: jobject, data: jpointer, bitmap: jobject, rotation: jint) {
+    let rotation = try_or_throw!(env, GL_EXCEPTION, Rotation::from_i32(rotation).ok_or("unknown rotation"));
     let bitmap = AndroidBitmap::from_jobject(env, bitmap);
 
     // This is really dumb.
@@ -134,7 +191,9 @@ pub unsafe extern "C" fn draw_image(env: *mut JNIEnv, _: jobject, data: jpointer
     let exception = match bitmap.as_slice() {
         Ok(pixels) => {
             let data = get_safe_data(data);
-            data.glinit.draw_image(bitmap.info.width as i32, bitmap.info.height as i32, pixels, mem::transmute(rotation));
+            let (w, h) = (bitmap.info.width as i32, bitmap.info.height as i32);
+            data.events.draw_image((w, h), pixels, rotation);
+            data.glinit.draw_image(w, h, pixels, rotation);
             return;
         },
         Err(err) => {
@@ -154,11 +213,11 @@ unsafe extern "C" fn jni_lua_set_interpolator(env: *mut JNIEnv, _: jobject, data
     data.glinit.set_interpolator(script);
 }
 
-unsafe extern "C" fn jni_add_layer(_: *mut JNIEnv, _: jobject, data: jpointer, copyshader: jint, pointshader: jint, pointidx: jint) {
+unsafe extern "C" fn jni_add_layer(env: *mut JNIEnv, _: jobject, data: jpointer, copyshader: jint, pointshader: jint, pointidx: jint) {
     let data = get_safe_data(data);
     let copyshader = Some(mem::transmute(copyshader));
     let pointshader = Some(mem::transmute(pointshader));
-    let layer = data.events.add_layer(data.glinit.dimensions, copyshader, pointshader, mem::transmute(pointidx));
+    let layer = try_or_throw!(env, RUNTIME_EXCEPTION, data.events.add_layer(data.glinit.dimensions, copyshader, pointshader, mem::transmute(pointidx)));
     data.glinit.add_layer(layer);
 }
 
@@ -168,23 +227,106 @@ unsafe extern "C" fn jni_clear_layers(_: *mut JNIEnv, _: jobject, data: jpointer
     data.glinit.clear_layers();
 }
 
+unsafe extern "C" fn jni_set_layer_style(env: *mut JNIEnv, _: jobject, data: jpointer, layer: jint, blend: jint, opacity: jfloat, visible: jboolean) {
+    let data = get_safe_data(data);
+    let blend = try_or_throw!(env, GL_EXCEPTION, BlendMode::from_i32(blend).ok_or("unknown blend mode"));
+    let opacity = if opacity < 0f32 { 0f32 } else if opacity > 1f32 { 1f32 } else { opacity };
+    let style = LayerStyle { blend: blend, opacity: opacity, visible: visible != 0 };
+    try_or_throw!(env, GL_EXCEPTION, data.glinit.set_layer_style(layer, style));
+    data.events.use_layer_style(layer, style);
+}
+
+unsafe extern "C" fn jni_remove_layer(env: *mut JNIEnv, _: jobject, data: jpointer, layer: jint) {
+    let data = get_safe_data(data);
+    try_or_throw!(env, GL_EXCEPTION, data.glinit.remove_layer(layer));
+    data.events.remove_layer(layer);
+}
+
+unsafe extern "C" fn jni_move_layer(env: *mut JNIEnv, _: jobject, data: jpointer, from: jint, to: jint) {
+    let data = get_safe_data(data);
+    try_or_throw!(env, GL_EXCEPTION, data.glinit.move_layer(from, to));
+    data.events.move_layer(from, to);
+}
+
+unsafe extern "C" fn jni_merge_layer(env: *mut JNIEnv, _: jobject, data: jpointer, layer: jint) {
+    let data = get_safe_data(data);
+    try_or_throw!(env, GL_EXCEPTION, data.glinit.merge_layer_down(layer));
+    data.events.merge_layer(layer);
+}
+
+unsafe extern "C" fn jni_duplicate_layer(env: *mut JNIEnv, _: jobject, data: jpointer, layer: jint) {
+    let data = get_safe_data(data);
+    try_or_throw!(env, GL_EXCEPTION, data.glinit.duplicate_layer(layer));
+    data.events.duplicate_layer(layer);
+}
+
 unsafe extern "C" fn jni_replay_begin(_: *mut JNIEnv, _: jobject, data: jpointer) -> jpointer {
     let data = get_safe_data(data);
-    data.glinit.clear_layers();
-    data.glinit.clear_buffer();
-    mem::transmute(Box::new(EventStream::new()))
+    data.glinit.reset_paintstate(data.events.canvas());
+    mem::transmute(Box::new(EventStream::new(&mut data.events)))
 }
 
-#[allow(unused)]
-unsafe extern "C" fn jni_replay_advance_frame(env: *mut JNIEnv, _: jobject, data: jpointer, replay: jpointer, java_matrix: jfloatArray) -> jboolean {
+/// Advances the replay and draws what it queues, until it stops for a frame or runs out.
+unsafe fn run_replay(env: *mut JNIEnv, data: &mut GLInitEvents, replay: &mut EventStream, matrix: &Matrix, now: jlong) -> jboolean {
+    let callback = data.jni_undo_callback.create_closure(env);
+    loop {
+        let state = replay.advance_frame(&mut data.glinit, &mut data.events, now);
+        let luaerr = data.glinit.draw_queued_points(&mut replay.consumer, &mut data.events, matrix, &callback);
+        if luaerr.is_err() {
+            rethrow_lua_result(env, luaerr);
+            return JNI_FALSE as jboolean;
+        }
+        match state {
+            EventState::Done => return JNI_TRUE as jboolean,
+            EventState::Frame => return JNI_FALSE as jboolean,
+            EventState::Draw | EventState::NoFrame => { },
+        }
+    }
+}
+
+unsafe extern "C" fn jni_replay_advance_frame(env: *mut JNIEnv, _: jobject, data: jpointer, replay: jpointer, java_matrix: jfloatArray, now: jlong) -> jboolean {
     let data = get_safe_data(data);
     let replay: &mut EventStream = mem::transmute(replay);
     let mut matrix: Matrix = mem::uninitialized();
     ((**env).GetFloatArrayRegion)(env, java_matrix, 0, 16, matrix.as_mut_ptr());
-    let done = replay.advance_frame(&mut data.glinit, &mut data.events);
-    let callback = data.jni_undo_callback.create_closure(env);
-    data.glinit.draw_queued_points(&mut replay.consumer, &mut data.events, &matrix, &callback);
-    if done { JNI_TRUE as jboolean } else { JNI_FALSE as jboolean }
+    run_replay(env, data, replay, &matrix, now)
+}
+
+unsafe extern "C" fn jni_replay_seek(env: *mut JNIEnv, _: jobject, data: jpointer, replay: jpointer, java_matrix: jfloatArray, position: jint, now: jlong) -> jboolean {
+    let data = get_safe_data(data);
+    let replay: &mut EventStream = mem::transmute(replay);
+    let mut matrix: Matrix = mem::uninitialized();
+    ((**env).GetFloatArrayRegion)(env, java_matrix, 0, 16, matrix.as_mut_ptr());
+    try_or_throw!(env, GL_EXCEPTION, replay.seek(&mut data.glinit, &mut data.events, position), JNI_FALSE as jboolean);
+    run_replay(env, data, replay, &matrix, now)
+}
+
+unsafe extern "C" fn jni_replay_event_count(_: *mut JNIEnv, _: jobject, replay: jpointer) -> jint {
+    let replay: &mut EventStream = mem::transmute(replay);
+    replay.len()
+}
+
+unsafe extern "C" fn jni_replay_position(_: *mut JNIEnv, _: jobject, replay: jpointer) -> jint {
+    let replay: &mut EventStream = mem::transmute(replay);
+    replay.position()
+}
+
+/// mode 0 follows recorded frames, 1 follows point timestamps at `factor` times real time, and 2
+/// runs to the end at once
+unsafe extern "C" fn jni_replay_set_speed(env: *mut JNIEnv, _: jobject, replay: jpointer, mode: jint, factor: jfloat) {
+    let replay: &mut EventStream = mem::transmute(replay);
+    let speed = match mode {
+        0 => ReplaySpeed::Frames,
+        1 if factor > 0f32 => ReplaySpeed::Timed(factor),
+        2 => ReplaySpeed::Instant,
+        _ => {
+            let errmsg = str_to_jstring(env, format!("invalid replay speed {} ({})", mode, factor).as_slice()).as_jvalue();
+            let err = RUNTIME_EXCEPTION.construct(env, [errmsg].as_mut_slice());
+            ((**env).Throw)(env, err);
+            return;
+        },
+    };
+    replay.set_speed(speed);
 }
 
 unsafe extern "C" fn jni_replay_destroy(_: *mut JNIEnv, _: jobject, replay: jpointer) {
@@ -192,27 +334,121 @@ unsafe extern "C" fn jni_replay_destroy(_: *mut JNIEnv, _: jobject, replay: jpoi
     mem::drop(replay);
 }
 
+unsafe extern "C" fn jni_save_session(env: *mut JNIEnv, _: jobject, data: jpointer, path: jstring) {
+    let data = get_safe_data(data);
+    let path = try_or_throw!(env, GL_EXCEPTION, get_string(env, path).ok_or("couldn't read session path"));
+    let session = data.events.to_session();
+    try_or_throw!(env, GL_EXCEPTION, sessionfile::save_to_file(path.as_slice(), &session));
+}
+
+unsafe extern "C" fn jni_load_session(env: *mut JNIEnv, _: jobject, data: jpointer, path: jstring) {
+    let data = get_safe_data(data);
+    let path = try_or_throw!(env, GL_EXCEPTION, get_string(env, path).ok_or("couldn't read session path"));
+    let session = try_or_throw!(env, GL_EXCEPTION, sessionfile::load_from_file(path.as_slice()));
+    try_or_throw!(env, GL_EXCEPTION, data.events.load_session(session));
+    data.glinit.reset_paintstate(data.events.canvas());
+}
+
 unsafe extern "C" fn jni_load_undo(_: *mut JNIEnv, _: jobject, data: jpointer, idx: jint) {
     let data = get_safe_data(data);
+    data.events.load_undo(idx);
     data.glinit.load_undo_frame(idx);
 }
 
 unsafe extern "C" fn jni_push_undo_frame(_: *mut JNIEnv, _: jobject, data: jpointer) -> jint {
     let data = get_safe_data(data);
+    data.events.push_undo();
     data.glinit.push_undo_frame()
 }
 
+/// Steps back one undo frame, returning the new position or -1 if there's nothing to undo.
+unsafe extern "C" fn jni_undo(_: *mut JNIEnv, _: jobject, data: jpointer) -> jint {
+    let data = get_safe_data(data);
+    match data.glinit.undo_target() {
+        Some(idx) => {
+            data.events.load_undo(idx);
+            data.glinit.load_undo_frame(idx);
+            idx
+        },
+        None => -1,
+    }
+}
+
+/// Steps forward one undo frame, returning the new position or -1 if there's nothing to redo.
+unsafe extern "C" fn jni_redo(_: *mut JNIEnv, _: jobject, data: jpointer) -> jint {
+    let data = get_safe_data(data);
+    match data.glinit.redo_target() {
+        Some(idx) => {
+            data.events.load_undo(idx);
+            data.glinit.load_undo_frame(idx);
+            idx
+        },
+        None => -1,
+    }
+}
+
+unsafe extern "C" fn jni_set_undo_budget(_: *mut JNIEnv, _: jobject, data: jpointer, gpu_mb: jint, cpu_mb: jint) {
+    let data = get_safe_data(data);
+    let megabytes = |mb: jint| if mb < 0 { 0 } else { (mb as usize) << 20 };
+    data.glinit.set_undo_budget(UndoBudget { gpu_bytes: megabytes(gpu_mb), cpu_bytes: megabytes(cpu_mb) });
+}
+
 unsafe extern "C" fn jni_clear_undo_frames(_: *mut JNIEnv, _: jobject, data: jpointer) {
     let data = get_safe_data(data);
+    data.events.clear_undo();
     data.glinit.clear_undo_frames();
 }
 
 unsafe extern "C" fn jni_set_brush_color(_: *mut JNIEnv, _: jobject, data: jpointer, color: jint) {
-    get_safe_data(data).glinit.set_brush_color(color);
+    let data = get_safe_data(data);
+    data.events.use_brush_color(color);
+    data.glinit.set_brush_color(color);
 }
 
 unsafe extern "C" fn jni_set_brush_size(_: *mut JNIEnv, _: jobject, data: jpointer, size: jfloat) {
-    get_safe_data(data).glinit.set_brush_size(size);
+    let data = get_safe_data(data);
+    data.events.use_brush_size(size);
+    data.glinit.set_brush_size(size);
+}
+
+unsafe extern "C" fn jni_set_eraser_mode(_: *mut JNIEnv, _: jobject, data: jpointer, erase: jboolean) {
+    let data = get_safe_data(data);
+    let mode = if erase != 0 { EraserMode::Erase } else { EraserMode::Paint };
+    data.events.use_eraser_mode(mode);
+    data.glinit.set_eraser_mode(mode);
+}
+
+unsafe extern "C" fn jni_set_prediction(_: *mut JNIEnv, _: jobject, data: jpointer, millis: jfloat) {
+    let data = get_safe_data(data);
+    data.glinit.set_prediction(millis / 1000f32);
+}
+
+unsafe extern "C" fn jni_resize_canvas(_: *mut JNIEnv, _: jobject, data: jpointer, w: jint, h: jint) {
+    let data = get_safe_data(data);
+    let reframe = Reframe::scale(w, h);
+    data.events.use_reframe(reframe);
+    data.glinit.reframe_canvas(reframe);
+}
+
+unsafe extern "C" fn jni_crop_canvas(_: *mut JNIEnv, _: jobject, data: jpointer, x: jint, y: jint, w: jint, h: jint) {
+    let data = get_safe_data(data);
+    let reframe = Reframe::crop(data.glinit.dimensions, x, y, w, h);
+    data.events.use_reframe(reframe);
+    data.glinit.reframe_canvas(reframe);
+}
+
+unsafe extern "C" fn jni_reset_view(_: *mut JNIEnv, _: jobject, data: jpointer) {
+    let data = get_safe_data(data);
+    data.glinit.set_view(Affine::identity());
+}
+
+/// Takes the same json as a unibrush's "smoothing" key.
+unsafe extern "C" fn jni_set_stroke_filter(env: *mut JNIEnv, _: jobject, data: jpointer, json: jstring) {
+    let data = get_safe_data(data);
+    let json = try_or_throw!(env, GL_EXCEPTION, get_string(env, json).ok_or("couldn't read stroke filter"));
+    let filter = try_or_throw!(env, GL_EXCEPTION, smoothing::parse_stroke_filter_str(json.as_slice()));
+    data.events.use_stroke_filter(filter);
+    data.glinit.set_stroke_filter(filter);
 }
 
 pub unsafe fn init(env: *mut JNIEnv) {
@@ -222,7 +458,7 @@ pub unsafe fn init(env: *mut JNIEnv) {
     
 
     let glinitstaticmethods = [
-        native_method!("initGL", "(IILcom/github/wartman4404/gldraw/UndoCallback;)I", init_gl),
+        native_method!("initGL", "(IIIILcom/github/wartman4404/gldraw/UndoCallback;)I", init_gl),
         native_method!("destroy", "(I)V", finish_gl),
     ];
     register_classmethods(env, cstr!("com/github/wartman4404/gldraw/GLInit$"), &glinitstaticmethods);
@@ -237,15 +473,33 @@ pub unsafe fn init(env: *mut JNIEnv) {
         native_method!("nativeSetCopyShader", "(II)Z", set_copy_shader),
         native_method!("nativeSetPointShader", "(II)Z", set_point_shader),
         native_method!("nativeSetBrushTexture", "(II)V", set_brush_texture),
-        native_method!("nativeExportPixels", "(I)Landroid/graphics/Bitmap;", export_pixels),
+        native_method!("nativeExportPixels", "(IZ)Landroid/graphics/Bitmap;", export_pixels),
+        native_method!("nativeExportLayerPixels", "(IIZ)Landroid/graphics/Bitmap;", export_layer_pixels),
+        native_method!("nativeDrawLayerImage", "(IILandroid/graphics/Bitmap;[F)V", draw_layer_image),
         native_method!("nativeSetInterpolator", "(II)V", jni_lua_set_interpolator),
         native_method!("nativeAddLayer", "(IIII)V", jni_add_layer),
         native_method!("nativeClearLayers", "(I)V", jni_clear_layers),
+        native_method!("nativeSetLayerStyle", "(IIIFZ)V", jni_set_layer_style),
+        native_method!("nativeRemoveLayer", "(II)V", jni_remove_layer),
+        native_method!("nativeMoveLayer", "(III)V", jni_move_layer),
+        native_method!("nativeMergeLayer", "(II)V", jni_merge_layer),
+        native_method!("nativeDuplicateLayer", "(II)V", jni_duplicate_layer),
         native_method!("nativeLoadUndo", "(II)V", jni_load_undo),
         native_method!("nativePushUndoFrame", "(I)I", jni_push_undo_frame),
         native_method!("nativeClearUndoFrames", "(I)V", jni_clear_undo_frames),
+        native_method!("nativeUndo", "(I)I", jni_undo),
+        native_method!("nativeRedo", "(I)I", jni_redo),
+        native_method!("nativeSetUndoBudget", "(III)V", jni_set_undo_budget),
         native_method!("nativeSetBrushColor", "(II)V", jni_set_brush_color),
         native_method!("nativeSetBrushSize", "(IF)V", jni_set_brush_size),
+        native_method!("nativeSetEraserMode", "(IZ)V", jni_set_eraser_mode),
+        native_method!("nativeSetStrokeFilter", "(ILjava/lang/String;)V", jni_set_stroke_filter),
+        native_method!("nativeSetPrediction", "(IF)V", jni_set_prediction),
+        native_method!("nativeResetView", "(I)V", jni_reset_view),
+        native_method!("nativeResizeCanvas", "(III)V", jni_resize_canvas),
+        native_method!("nativeCropCanvas", "(IIIII)V", jni_crop_canvas),
+        native_method!("nativeSaveSession", "(ILjava/lang/String;)V", jni_save_session),
+        native_method!("nativeLoadSession", "(ILjava/lang/String;)V", jni_load_session),
     ];
     register_classmethods(env, cstr!("com/github/wartman4404/gldraw/TextureSurfaceThread"), &texturemethods);
     debug_logi!("registered texture thread methods!");
@@ -253,7 +507,11 @@ pub unsafe fn init(env: *mut JNIEnv) {
     let replayhandlerstaticmethods = [
         native_method!("init", "(I)I", jni_replay_begin),
         native_method!("destroy", "(I)V", jni_replay_destroy),
-        native_method!("advanceFrame", "(II[F)Z", jni_replay_advance_frame),
+        native_method!("advanceFrame", "(II[FJ)Z", jni_replay_advance_frame),
+        native_method!("setSpeed", "(IIF)V", jni_replay_set_speed),
+        native_method!("seek", "(II[FIJ)Z", jni_replay_seek),
+        native_method!("eventCount", "(I)I", jni_replay_event_count),
+        native_method!("position", "(I)I", jni_replay_position),
     ];
     register_classmethods(env, cstr!("com/github/wartman4404/gldraw/Replay$"), &replayhandlerstaticmethods);
     debug_logi!("registered replay methods!");
@@ -0,0 +1,636 @@
+/// Versioned binary format for a recorded session: the event log, plus the source of every
+/// shader, brush, and script it refers to, in DrawObjectList index order, and the pixels of every
+/// image drawn.
+///
+/// Everything is little-endian.  Strings and byte arrays are prefixed with a u32 length, which
+/// is refused past MAX_BYTES.  Optional object indices are written as -1 when absent.
+///
+/// The version is followed by the width and height the canvas started at, written as 0 by 0
+/// when it isn't known.  Images come after the scripts, as a width, height and RGBA byte array
+/// each.
+///
+/// Canvas reframes are written as the new width and height, then the x, y, width and height
+/// the old canvas was drawn into.
+///
+/// Layer styles are written as the layer number, a blend mode byte, the opacity as an f32, and a
+/// visibility byte.  Removing, merging and duplicating a layer are written as its number, and
+/// moving one as the number it had and the number it ends up with.  Drawing an imported image
+/// onto a layer is written as the layer number, the image's index, and the a, b, c, d, tx and ty
+/// of its placement as f32s.  An image fitted onto the canvas is written as the image's index and
+/// a rotation byte, counting quarter turns.
+///
+/// Stroke filters are written as a type byte and three f32 settings for each of position, size
+/// and speed, with unused settings left as 0.
+
+use core::prelude::*;
+use core::borrow::IntoCow;
+use collections::vec::Vec;
+use collections::string::String;
+use std::old_io::{Reader, Writer, IoResult, IoError, OtherIoError};
+use std::old_io::{File, BufferedReader, BufferedWriter};
+use std::old_path::Path;
+
+use opengles::gl2;
+
+use drawevent::{DrawEvent, LayerImageValues};
+use glstore::{DrawObjectIndex, BrushInitValues};
+use gltexture::PixelFormat;
+use point::{PointEntry, PointInfo, PaintPoint, Coordinate, ToolType};
+use glinit::{EraserMode, Reframe};
+use matrix::{Affine, Rotation};
+use paintlayer::{LayerStyle, BlendMode};
+use smoothing::{StrokeFilter, FilterKind};
+use glcommon::{GLResult, MString};
+
+static MAGIC: &'static [u8] = b"EDRW";
+pub const VERSION: u32 = 1;
+/// More than any string, brush or canvas-sized image could need; anything longer is corrupt.
+const MAX_BYTES: u32 = 1 << 27;
+
+const EVENT_USE_ANIM_SHADER: u8 = 0;
+const EVENT_USE_COPY_SHADER: u8 = 1;
+const EVENT_USE_POINT_SHADER: u8 = 2;
+const EVENT_USE_BRUSH: u8 = 3;
+const EVENT_USE_INTERPOLATOR: u8 = 4;
+const EVENT_BRUSH_COLOR: u8 = 5;
+const EVENT_BRUSH_SIZE: u8 = 6;
+const EVENT_POINT: u8 = 7;
+const EVENT_ADD_LAYER: u8 = 8;
+const EVENT_CLEAR_LAYERS: u8 = 9;
+const EVENT_PUSH_UNDO: u8 = 10;
+const EVENT_LOAD_UNDO: u8 = 11;
+const EVENT_CLEAR_UNDO: u8 = 12;
+const EVENT_CLEAR: u8 = 13;
+const EVENT_FRAME: u8 = 14;
+const EVENT_ERASER_MODE: u8 = 15;
+const EVENT_STROKE_FILTER: u8 = 16;
+const EVENT_REFRAME: u8 = 17;
+const EVENT_LAYER_STYLE: u8 = 18;
+const EVENT_REMOVE_LAYER: u8 = 19;
+const EVENT_MOVE_LAYER: u8 = 20;
+const EVENT_MERGE_LAYER: u8 = 21;
+const EVENT_DUPLICATE_LAYER: u8 = 22;
+const EVENT_LAYER_IMAGE: u8 = 23;
+const EVENT_IMAGE: u8 = 24;
+
+const FILTER_PASSTHROUGH: u8 = 0;
+const FILTER_MOVING_AVERAGE: u8 = 1;
+const FILTER_EXPONENTIAL: u8 = 2;
+const FILTER_ONE_EURO: u8 = 3;
+const FILTER_LAZY_BRUSH: u8 = 4;
+
+const POINT_STOP: u8 = 0;
+const POINT_FRAMESTOP: u8 = 1;
+const POINT_POINT: u8 = 2;
+
+/// Everything needed to rebuild an Events, without any gl objects.
+pub struct SessionData {
+    pub canvas: Option<(i32, i32)>,
+    pub pointshaders: Vec<(MString, MString)>,
+    pub copyshaders: Vec<(MString, MString)>,
+    pub textures: Vec<BrushInitValues>,
+    pub luascripts: Vec<MString>,
+    pub images: Vec<LayerImageValues>,
+    pub events: Vec<DrawEvent>,
+}
+
+fn invalid(desc: &'static str, detail: String) -> IoError {
+    IoError { kind: OtherIoError, desc: desc, detail: Some(detail) }
+}
+
+fn to_glresult<T>(result: IoResult<T>) -> GLResult<T> {
+    match result {
+        Ok(x) => Ok(x),
+        Err(e) => Err(format!("session file error: {}", e).into_cow()),
+    }
+}
+
+fn write_str<W: Writer>(w: &mut W, s: &str) -> IoResult<()> {
+    try!(w.write_le_u32(s.len() as u32));
+    w.write_all(s.as_bytes())
+}
+
+/// Reads a u32 length and that many bytes.
+fn read_bytes<R: Reader>(r: &mut R) -> IoResult<Vec<u8>> {
+    let len = try!(r.read_le_u32());
+    if len > MAX_BYTES {
+        return Err(invalid("byte array too long", format!("{} bytes, expected at most {}", len, MAX_BYTES)));
+    }
+    r.read_exact(len as usize)
+}
+
+fn read_str<R: Reader>(r: &mut R) -> IoResult<MString> {
+    let bytes = try!(read_bytes(r));
+    let len = bytes.len();
+    match String::from_utf8(bytes) {
+        Ok(s) => Ok(s.into_cow()),
+        Err(_) => Err(invalid("invalid string", format!("{} bytes of non-utf8 data", len))),
+    }
+}
+
+fn write_index<W: Writer, T>(w: &mut W, idx: &Option<DrawObjectIndex<T>>) -> IoResult<()> {
+    match *idx {
+        Some(ref idx) => w.write_le_i32(idx.to_i32()),
+        None => w.write_le_i32(-1),
+    }
+}
+
+fn read_index<R: Reader, T>(r: &mut R) -> IoResult<Option<DrawObjectIndex<T>>> {
+    match try!(r.read_le_i32()) {
+        -1 => Ok(None),
+        idx => Ok(Some(DrawObjectIndex::from_i32(idx))),
+    }
+}
+
+fn write_filter<W: Writer>(w: &mut W, kind: FilterKind) -> IoResult<()> {
+    let (tag, settings) = match kind {
+        FilterKind::Passthrough => (FILTER_PASSTHROUGH, [0f32, 0f32, 0f32]),
+        FilterKind::MovingAverage(window) => (FILTER_MOVING_AVERAGE, [window as f32, 0f32, 0f32]),
+        FilterKind::Exponential(alpha) => (FILTER_EXPONENTIAL, [alpha, 0f32, 0f32]),
+        FilterKind::OneEuro { min_cutoff, beta, derivative_cutoff } => (FILTER_ONE_EURO, [min_cutoff, beta, derivative_cutoff]),
+        FilterKind::LazyBrush(radius) => (FILTER_LAZY_BRUSH, [radius, 0f32, 0f32]),
+    };
+    try!(w.write_u8(tag));
+    for setting in settings.iter() {
+        try!(w.write_le_f32(*setting));
+    }
+    Ok(())
+}
+
+fn read_filter<R: Reader>(r: &mut R) -> IoResult<FilterKind> {
+    let tag = try!(r.read_u8());
+    let (a, b, c) = (try!(r.read_le_f32()), try!(r.read_le_f32()), try!(r.read_le_f32()));
+    let kind = match tag {
+        FILTER_PASSTHROUGH => FilterKind::Passthrough,
+        FILTER_MOVING_AVERAGE => FilterKind::MovingAverage(a as usize),
+        FILTER_EXPONENTIAL => FilterKind::Exponential(a),
+        FILTER_ONE_EURO => FilterKind::OneEuro { min_cutoff: a, beta: b, derivative_cutoff: c },
+        FILTER_LAZY_BRUSH => FilterKind::LazyBrush(a),
+        other => return Err(invalid("unknown filter type", format!("filter type {}", other))),
+    };
+    Ok(kind)
+}
+
+fn write_point<W: Writer>(w: &mut W, point: &PointEntry) -> IoResult<()> {
+    try!(w.write_le_i32(point.index));
+    match point.entry {
+        PointInfo::Stop => w.write_u8(POINT_STOP),
+        PointInfo::FrameStop => w.write_u8(POINT_FRAMESTOP),
+        PointInfo::Point(p) => {
+            try!(w.write_u8(POINT_POINT));
+            try!(w.write_le_f32(p.pos.x));
+            try!(w.write_le_f32(p.pos.y));
+            try!(w.write_le_f32(p.time));
+            try!(w.write_le_f32(p.size));
+            try!(w.write_le_f32(p.pressure));
+            try!(w.write_le_f32(p.tilt));
+            try!(w.write_le_f32(p.orientation));
+            try!(w.write_u8(point.tool as u8));
+            w.write_le_u32(point.buttons)
+        },
+    }
+}
+
+fn read_point<R: Reader>(r: &mut R) -> IoResult<PointEntry> {
+    let index = try!(r.read_le_i32());
+    let mut tool = ToolType::Unknown;
+    let mut buttons = 0;
+    let entry = match try!(r.read_u8()) {
+        POINT_STOP => PointInfo::Stop,
+        POINT_FRAMESTOP => PointInfo::FrameStop,
+        POINT_POINT => {
+            let x = try!(r.read_le_f32());
+            let y = try!(r.read_le_f32());
+            let time = try!(r.read_le_f32());
+            let size = try!(r.read_le_f32());
+            let pressure = try!(r.read_le_f32());
+            let tilt = try!(r.read_le_f32());
+            let orientation = try!(r.read_le_f32());
+            tool = ToolType::from_u8(try!(r.read_u8()));
+            buttons = try!(r.read_le_u32());
+            PointInfo::Point(PaintPoint {
+                pos: Coordinate { x: x, y: y }, time: time, size: size,
+                pressure: pressure, tilt: tilt, orientation: orientation,
+            })
+        },
+        other => return Err(invalid("unknown point type", format!("point type {}", other))),
+    };
+    Ok(PointEntry { index: index, entry: entry, tool: tool, buttons: buttons })
+}
+
+fn write_event<W: Writer>(w: &mut W, event: &DrawEvent) -> IoResult<()> {
+    match *event {
+        DrawEvent::UseAnimShader(ref idx) => {
+            try!(w.write_u8(EVENT_USE_ANIM_SHADER));
+            w.write_le_i32(idx.to_i32())
+        },
+        DrawEvent::UseCopyShader(ref idx) => {
+            try!(w.write_u8(EVENT_USE_COPY_SHADER));
+            w.write_le_i32(idx.to_i32())
+        },
+        DrawEvent::UsePointShader(ref idx) => {
+            try!(w.write_u8(EVENT_USE_POINT_SHADER));
+            w.write_le_i32(idx.to_i32())
+        },
+        DrawEvent::UseBrush(ref idx) => {
+            try!(w.write_u8(EVENT_USE_BRUSH));
+            w.write_le_i32(idx.to_i32())
+        },
+        DrawEvent::UseInterpolator(ref idx) => {
+            try!(w.write_u8(EVENT_USE_INTERPOLATOR));
+            w.write_le_i32(idx.to_i32())
+        },
+        DrawEvent::BrushColor(color) => {
+            try!(w.write_u8(EVENT_BRUSH_COLOR));
+            w.write_le_i32(color)
+        },
+        DrawEvent::BrushSize(size) => {
+            try!(w.write_u8(EVENT_BRUSH_SIZE));
+            w.write_le_f32(size)
+        },
+        DrawEvent::EraserMode(mode) => {
+            try!(w.write_u8(EVENT_ERASER_MODE));
+            w.write_u8(match mode { EraserMode::Paint => 0, EraserMode::Erase => 1 })
+        },
+        DrawEvent::StrokeFilter(filter) => {
+            try!(w.write_u8(EVENT_STROKE_FILTER));
+            try!(write_filter(w, filter.position));
+            try!(write_filter(w, filter.size));
+            write_filter(w, filter.speed)
+        },
+        DrawEvent::Point(ref point) => {
+            try!(w.write_u8(EVENT_POINT));
+            write_point(w, point)
+        },
+        DrawEvent::AddLayer(ref copyshader, ref pointshader, pointidx) => {
+            try!(w.write_u8(EVENT_ADD_LAYER));
+            try!(write_index(w, copyshader));
+            try!(write_index(w, pointshader));
+            w.write_le_i32(pointidx)
+        },
+        DrawEvent::ClearLayers => w.write_u8(EVENT_CLEAR_LAYERS),
+        DrawEvent::LayerStyle(layer, style) => {
+            try!(w.write_u8(EVENT_LAYER_STYLE));
+            try!(w.write_le_i32(layer));
+            try!(w.write_u8(style.blend.to_i32() as u8));
+            try!(w.write_le_f32(style.opacity));
+            w.write_u8(style.visible as u8)
+        },
+        DrawEvent::RemoveLayer(layer) => {
+            try!(w.write_u8(EVENT_REMOVE_LAYER));
+            w.write_le_i32(layer)
+        },
+        DrawEvent::MoveLayer(from, to) => {
+            try!(w.write_u8(EVENT_MOVE_LAYER));
+            try!(w.write_le_i32(from));
+            w.write_le_i32(to)
+        },
+        DrawEvent::MergeLayer(layer) => {
+            try!(w.write_u8(EVENT_MERGE_LAYER));
+            w.write_le_i32(layer)
+        },
+        DrawEvent::DuplicateLayer(layer) => {
+            try!(w.write_u8(EVENT_DUPLICATE_LAYER));
+            w.write_le_i32(layer)
+        },
+        DrawEvent::LayerImage(layer, image, placement) => {
+            try!(w.write_u8(EVENT_LAYER_IMAGE));
+            try!(w.write_le_i32(layer));
+            try!(w.write_le_i32(image));
+            for &x in [placement.a, placement.b, placement.c, placement.d, placement.tx, placement.ty].iter() {
+                try!(w.write_le_f32(x));
+            }
+            Ok(())
+        },
+        DrawEvent::Image(image, rotation) => {
+            try!(w.write_u8(EVENT_IMAGE));
+            try!(w.write_le_i32(image));
+            w.write_u8(rotation as u8)
+        },
+        DrawEvent::PushUndo => w.write_u8(EVENT_PUSH_UNDO),
+        DrawEvent::LoadUndo(idx) => {
+            try!(w.write_u8(EVENT_LOAD_UNDO));
+            w.write_le_i32(idx)
+        },
+        DrawEvent::ClearUndo => w.write_u8(EVENT_CLEAR_UNDO),
+        DrawEvent::Clear => w.write_u8(EVENT_CLEAR),
+        DrawEvent::Frame => w.write_u8(EVENT_FRAME),
+        DrawEvent::Reframe(reframe) => {
+            try!(w.write_u8(EVENT_REFRAME));
+            for &x in [reframe.width, reframe.height, reframe.x, reframe.y, reframe.scaled_width, reframe.scaled_height].iter() {
+                try!(w.write_le_i32(x));
+            }
+            Ok(())
+        },
+    }
+}
+
+fn read_event<R: Reader>(r: &mut R) -> IoResult<DrawEvent> {
+    let event = match try!(r.read_u8()) {
+        EVENT_USE_ANIM_SHADER => DrawEvent::UseAnimShader(DrawObjectIndex::from_i32(try!(r.read_le_i32()))),
+        EVENT_USE_COPY_SHADER => DrawEvent::UseCopyShader(DrawObjectIndex::from_i32(try!(r.read_le_i32()))),
+        EVENT_USE_POINT_SHADER => DrawEvent::UsePointShader(DrawObjectIndex::from_i32(try!(r.read_le_i32()))),
+        EVENT_USE_BRUSH => DrawEvent::UseBrush(DrawObjectIndex::from_i32(try!(r.read_le_i32()))),
+        EVENT_USE_INTERPOLATOR => DrawEvent::UseInterpolator(DrawObjectIndex::from_i32(try!(r.read_le_i32()))),
+        EVENT_BRUSH_COLOR => DrawEvent::BrushColor(try!(r.read_le_i32())),
+        EVENT_BRUSH_SIZE => DrawEvent::BrushSize(try!(r.read_le_f32())),
+        EVENT_ERASER_MODE => DrawEvent::EraserMode(match try!(r.read_u8()) {
+            0 => EraserMode::Paint,
+            1 => EraserMode::Erase,
+            other => return Err(invalid("unknown eraser mode", format!("eraser mode {}", other))),
+        }),
+        EVENT_STROKE_FILTER => DrawEvent::StrokeFilter(StrokeFilter {
+            position: try!(read_filter(r)),
+            size: try!(read_filter(r)),
+            speed: try!(read_filter(r)),
+        }),
+        EVENT_POINT => DrawEvent::Point(try!(read_point(r))),
+        EVENT_ADD_LAYER => {
+            let copyshader = try!(read_index(r));
+            let pointshader = try!(read_index(r));
+            DrawEvent::AddLayer(copyshader, pointshader, try!(r.read_le_i32()))
+        },
+        EVENT_CLEAR_LAYERS => DrawEvent::ClearLayers,
+        EVENT_LAYER_STYLE => {
+            let layer = try!(r.read_le_i32());
+            let blend = try!(r.read_u8());
+            let blend = try!(BlendMode::from_i32(blend as i32).ok_or(invalid("unknown blend mode", format!("blend mode {}", blend))));
+            DrawEvent::LayerStyle(layer, LayerStyle {
+                blend: blend,
+                opacity: try!(r.read_le_f32()),
+                visible: try!(r.read_u8()) != 0,
+            })
+        },
+        EVENT_REMOVE_LAYER => DrawEvent::RemoveLayer(try!(r.read_le_i32())),
+        EVENT_MOVE_LAYER => {
+            let from = try!(r.read_le_i32());
+            DrawEvent::MoveLayer(from, try!(r.read_le_i32()))
+        },
+        EVENT_MERGE_LAYER => DrawEvent::MergeLayer(try!(r.read_le_i32())),
+        EVENT_DUPLICATE_LAYER => DrawEvent::DuplicateLayer(try!(r.read_le_i32())),
+        EVENT_LAYER_IMAGE => {
+            let layer = try!(r.read_le_i32());
+            let image = try!(r.read_le_i32());
+            DrawEvent::LayerImage(layer, image, Affine {
+                a: try!(r.read_le_f32()),
+                b: try!(r.read_le_f32()),
+                c: try!(r.read_le_f32()),
+                d: try!(r.read_le_f32()),
+                tx: try!(r.read_le_f32()),
+                ty: try!(r.read_le_f32()),
+            })
+        },
+        EVENT_IMAGE => {
+            let image = try!(r.read_le_i32());
+            let rotation = try!(r.read_u8());
+            let rotation = try!(Rotation::from_i32(rotation as i32).ok_or(invalid("unknown rotation", format!("rotation {}", rotation))));
+            DrawEvent::Image(image, rotation)
+        },
+        EVENT_PUSH_UNDO => DrawEvent::PushUndo,
+        EVENT_LOAD_UNDO => DrawEvent::LoadUndo(try!(r.read_le_i32())),
+        EVENT_CLEAR_UNDO => DrawEvent::ClearUndo,
+        EVENT_CLEAR => DrawEvent::Clear,
+        EVENT_FRAME => DrawEvent::Frame,
+        EVENT_REFRAME => DrawEvent::Reframe(Reframe {
+            width: try!(r.read_le_i32()),
+            height: try!(r.read_le_i32()),
+            x: try!(r.read_le_i32()),
+            y: try!(r.read_le_i32()),
+            scaled_width: try!(r.read_le_i32()),
+            scaled_height: try!(r.read_le_i32()),
+        }),
+        other => return Err(invalid("unknown event type", format!("event type {}", other))),
+    };
+    Ok(event)
+}
+
+fn write_brush<W: Writer>(w: &mut W, brush: &BrushInitValues) -> IoResult<()> {
+    let (format, (width, height), ref pixels) = *brush;
+    try!(w.write_le_u32(format as u32));
+    try!(w.write_le_i32(width));
+    try!(w.write_le_i32(height));
+    try!(w.write_le_u32(pixels.len() as u32));
+    w.write_all(pixels.as_slice())
+}
+
+fn read_brush<R: Reader>(r: &mut R) -> IoResult<BrushInitValues> {
+    let format = match try!(r.read_le_u32()) {
+        gl2::RGBA => PixelFormat::RGBA,
+        gl2::RGB => PixelFormat::RGB,
+        gl2::ALPHA => PixelFormat::ALPHA,
+        other => return Err(invalid("unknown pixel format", format!("pixel format 0x{:x}", other))),
+    };
+    let width = try!(r.read_le_i32());
+    let height = try!(r.read_le_i32());
+    let pixels = try!(read_bytes(r));
+    Ok((format, (width, height), pixels))
+}
+
+fn write_image<W: Writer>(w: &mut W, image: &LayerImageValues) -> IoResult<()> {
+    let ((width, height), ref pixels) = *image;
+    try!(w.write_le_i32(width));
+    try!(w.write_le_i32(height));
+    try!(w.write_le_u32(pixels.len() as u32));
+    w.write_all(pixels.as_slice())
+}
+
+fn read_image<R: Reader>(r: &mut R) -> IoResult<LayerImageValues> {
+    let width = try!(r.read_le_i32());
+    let height = try!(r.read_le_i32());
+    let pixels = try!(read_bytes(r));
+    if width <= 0 || height <= 0 || pixels.len() != (width * height * 4) as usize {
+        return Err(invalid("bad image", format!("{} bytes for a {}x{} image", pixels.len(), width, height)));
+    }
+    Ok(((width, height), pixels))
+}
+
+fn write_session_io<W: Writer>(w: &mut W, session: &SessionData) -> IoResult<()> {
+    try!(w.write_all(MAGIC));
+    try!(w.write_le_u32(VERSION));
+    let (width, height) = session.canvas.unwrap_or((0, 0));
+    try!(w.write_le_i32(width));
+    try!(w.write_le_i32(height));
+
+    try!(w.write_le_u32(session.pointshaders.len() as u32));
+    for &(ref vert, ref frag) in session.pointshaders.iter() {
+        try!(write_str(w, vert.as_slice()));
+        try!(write_str(w, frag.as_slice()));
+    }
+    try!(w.write_le_u32(session.copyshaders.len() as u32));
+    for &(ref vert, ref frag) in session.copyshaders.iter() {
+        try!(write_str(w, vert.as_slice()));
+        try!(write_str(w, frag.as_slice()));
+    }
+    try!(w.write_le_u32(session.textures.len() as u32));
+    for brush in session.textures.iter() {
+        try!(write_brush(w, brush));
+    }
+    try!(w.write_le_u32(session.luascripts.len() as u32));
+    for script in session.luascripts.iter() {
+        try!(write_str(w, script.as_slice()));
+    }
+    try!(w.write_le_u32(session.images.len() as u32));
+    for image in session.images.iter() {
+        try!(write_image(w, image));
+    }
+    try!(w.write_le_u32(session.events.len() as u32));
+    for event in session.events.iter() {
+        try!(write_event(w, event));
+    }
+    Ok(())
+}
+
+fn read_session_io<R: Reader>(r: &mut R) -> IoResult<SessionData> {
+    let magic = try!(r.read_exact(MAGIC.len()));
+    if magic.as_slice() != MAGIC {
+        return Err(invalid("not a session file", format!("bad header {:?}", magic)));
+    }
+    let version = try!(r.read_le_u32());
+    if version != VERSION {
+        return Err(invalid("unsupported session version", format!("version {}, expected {}", version, VERSION)));
+    }
+    let canvas = {
+        let width = try!(r.read_le_i32());
+        let height = try!(r.read_le_i32());
+        if width > 0 && height > 0 { Some((width, height)) } else { None }
+    };
+
+    let mut pointshaders = Vec::new();
+    for _ in range(0, try!(r.read_le_u32())) {
+        let vert = try!(read_str(r));
+        let frag = try!(read_str(r));
+        pointshaders.push((vert, frag));
+    }
+    let mut copyshaders = Vec::new();
+    for _ in range(0, try!(r.read_le_u32())) {
+        let vert = try!(read_str(r));
+        let frag = try!(read_str(r));
+        copyshaders.push((vert, frag));
+    }
+    let mut textures = Vec::new();
+    for _ in range(0, try!(r.read_le_u32())) {
+        textures.push(try!(read_brush(r)));
+    }
+    let mut luascripts = Vec::new();
+    for _ in range(0, try!(r.read_le_u32())) {
+        luascripts.push(try!(read_str(r)));
+    }
+    let mut images = Vec::new();
+    for _ in range(0, try!(r.read_le_u32())) {
+        images.push(try!(read_image(r)));
+    }
+    let mut events = Vec::new();
+    for _ in range(0, try!(r.read_le_u32())) {
+        events.push(try!(read_event(r)));
+    }
+    Ok(SessionData {
+        canvas: canvas,
+        pointshaders: pointshaders,
+        copyshaders: copyshaders,
+        textures: textures,
+        luascripts: luascripts,
+        images: images,
+        events: events,
+    })
+}
+
+pub fn write_session<W: Writer>(w: &mut W, session: &SessionData) -> GLResult<()> {
+    to_glresult(write_session_io(w, session))
+}
+
+pub fn read_session<R: Reader>(r: &mut R) -> GLResult<SessionData> {
+    to_glresult(read_session_io(r))
+}
+
+pub fn save_to_file(path: &str, session: &SessionData) -> GLResult<()> {
+    let file = try!(to_glresult(File::create(&Path::new(path))));
+    let mut writer = BufferedWriter::new(file);
+    try!(write_session(&mut writer, session));
+    to_glresult(writer.flush())
+}
+
+pub fn load_from_file(path: &str) -> GLResult<SessionData> {
+    let file = try!(to_glresult(File::open(&Path::new(path))));
+    read_session(&mut BufferedReader::new(file))
+}
+
+#[cfg(test)]
+fn test_session() -> SessionData {
+    let mut pointshaders = Vec::new();
+    pointshaders.push(("vert".into_cow(), "frag".into_cow()));
+    let mut textures = Vec::new();
+    textures.push((PixelFormat::ALPHA, (2, 1), (&[0u8, 255u8]).to_vec()));
+    let mut luascripts = Vec::new();
+    luascripts.push("function onmove(a, b) end".into_cow());
+    let mut images = Vec::new();
+    images.push(((1, 2), (&[255u8, 0u8, 0u8, 255u8, 0u8, 0u8, 0u8, 0u8]).to_vec()));
+    let mut events = Vec::new();
+    events.push(DrawEvent::Image(0, Rotation::Rotation90));
+    events.push(DrawEvent::UsePointShader(DrawObjectIndex::from_i32(0)));
+    events.push(DrawEvent::UseBrush(DrawObjectIndex::from_i32(0)));
+    events.push(DrawEvent::BrushColor(0x00ff8000));
+    events.push(DrawEvent::AddLayer(None, Some(DrawObjectIndex::from_i32(0)), 1));
+    events.push(DrawEvent::LayerStyle(1, LayerStyle { blend: BlendMode::Screen, opacity: 0.5f32, visible: false }));
+    events.push(DrawEvent::DuplicateLayer(1));
+    events.push(DrawEvent::MoveLayer(2, 1));
+    events.push(DrawEvent::MergeLayer(2));
+    events.push(DrawEvent::RemoveLayer(1));
+    events.push(DrawEvent::LayerImage(1, 0, Affine { a: 2f32, b: 0.5f32, c: -1f32, d: 2f32, tx: 10f32, ty: -4f32 }));
+    events.push(DrawEvent::EraserMode(EraserMode::Paint));
+    events.push(DrawEvent::StrokeFilter(StrokeFilter {
+        position: FilterKind::OneEuro { min_cutoff: 1f32, beta: 0.5f32, derivative_cutoff: 2f32 },
+        size: FilterKind::Exponential(0.25f32),
+        speed: FilterKind::LazyBrush(4f32),
+    }));
+    events.push(DrawEvent::Point(PointEntry { index: 3, entry: PointInfo::Point(PaintPoint {
+        pos: Coordinate { x: 1.5f32, y: 20f32 }, time: 0.25f32, size: 0.5f32,
+        pressure: 0.75f32, tilt: 0.5f32, orientation: -1f32,
+    }), tool: ToolType::Eraser, buttons: 0x20 }));
+    events.push(DrawEvent::Point(PointEntry::stop(3)));
+    events.push(DrawEvent::Frame);
+    events.push(DrawEvent::Reframe(Reframe::crop((640, 480), -10, 20, 800, 400)));
+    SessionData {
+        canvas: Some((640, 480)),
+        pointshaders: pointshaders,
+        copyshaders: Vec::new(),
+        textures: textures,
+        luascripts: luascripts,
+        images: images,
+        events: events,
+    }
+}
+
+#[test]
+fn session_roundtrip() {
+    use std::old_io::{MemReader, MemWriter};
+    let mut writer = MemWriter::new();
+    write_session(&mut writer, &test_session()).unwrap();
+    let bytes = writer.into_inner();
+    let loaded = read_session(&mut MemReader::new(bytes.clone())).unwrap();
+    assert_eq!(loaded.canvas, Some((640, 480)));
+    assert_eq!(loaded.events.len(), 17);
+    assert_eq!(loaded.images.len(), 1);
+    let mut rewriter = MemWriter::new();
+    write_session(&mut rewriter, &loaded).unwrap();
+    assert_eq!(bytes, rewriter.into_inner());
+}
+
+#[test]
+fn bad_header_fails() {
+    use std::old_io::MemReader;
+    let mut reader = MemReader::new(b"GIF89a".to_vec());
+    assert!(read_session(&mut reader).is_err());
+}
+
+#[test]
+fn oversized_lengths_fail() {
+    use std::old_io::{MemReader, MemWriter};
+    let mut writer = MemWriter::new();
+    writer.write_all(MAGIC).unwrap();
+    writer.write_le_u32(VERSION).unwrap();
+    writer.write_le_i32(0).unwrap();
+    writer.write_le_i32(0).unwrap();
+    writer.write_le_u32(1).unwrap();
+    writer.write_le_u32(0xffffffff).unwrap();
+    assert!(read_session(&mut MemReader::new(writer.into_inner())).is_err());
+}
@@ -0,0 +1,271 @@
+/// Stroke smoothing filters, applied to each pointer's input before it reaches the interpolator.
+///
+/// Every pointer gets fresh filter state when it goes down, built from the StrokeFilter that was
+/// selected at the time, so changing filters never disturbs a stroke in progress.
+
+use core::prelude::*;
+use core::num::Float;
+use core::f32;
+use core::borrow::IntoCow;
+use collections::vec::Vec;
+use serialize::json::Json;
+
+use point::Coordinate;
+use glcommon::GLResult;
+
+/// Which filter to use for one kind of value, and its settings.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum FilterKind {
+    /// Values pass through untouched.
+    Passthrough,
+    /// Mean of the last n values.
+    MovingAverage(usize),
+    /// Each output moves this fraction of the way to the new value, 0 to 1.
+    Exponential(f32),
+    /// Casiez et al's One Euro filter: a low-pass filter whose cutoff rises with speed, so slow movements
+    /// are steadied without adding lag to fast ones.  Cutoffs are in Hz.
+    OneEuro { min_cutoff: f32, beta: f32, derivative_cutoff: f32 },
+    /// Pulled-string stabilizer: the output stays put until the input gets further than the
+    /// radius away, then gets dragged along behind it.
+    LazyBrush(f32),
+}
+
+/// Filters for each value in a point.  Pressure and tilt are smoothed like size, and orientation
+/// like speed.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct StrokeFilter {
+    pub position: FilterKind,
+    pub size: FilterKind,
+    pub speed: FilterKind,
+}
+
+impl StrokeFilter {
+    /// What strokes have always had: position untouched, and a 16-sample average of the rest.
+    pub fn default() -> StrokeFilter {
+        StrokeFilter {
+            position: FilterKind::Passthrough,
+            size: FilterKind::MovingAverage(16),
+            speed: FilterKind::MovingAverage(16),
+        }
+    }
+}
+
+/// Values the filters know how to blend.
+pub trait Smoothable: Copy {
+    fn zero() -> Self;
+    fn plus(self, other: Self) -> Self;
+    fn minus(self, other: Self) -> Self;
+    fn scale(self, by: f32) -> Self;
+    fn magnitude(self) -> f32;
+}
+
+impl Smoothable for f32 {
+    fn zero() -> f32 { 0f32 }
+    fn plus(self, other: f32) -> f32 { self + other }
+    fn minus(self, other: f32) -> f32 { self - other }
+    fn scale(self, by: f32) -> f32 { self * by }
+    fn magnitude(self) -> f32 { self.abs() }
+}
+
+impl Smoothable for Coordinate {
+    fn zero() -> Coordinate { Coordinate { x: 0f32, y: 0f32 } }
+    fn plus(self, other: Coordinate) -> Coordinate { self + other }
+    fn minus(self, other: Coordinate) -> Coordinate { self - other }
+    fn scale(self, by: f32) -> Coordinate { self * by }
+    fn magnitude(self) -> f32 { (self.x * self.x + self.y * self.y).sqrt() }
+}
+
+#[inline]
+fn lerp<T: Smoothable>(from: T, to: T, t: f32) -> T {
+    from.plus(to.minus(from).scale(t))
+}
+
+/// Used when two samples share a timestamp, or time runs backwards.
+const FALLBACK_INTERVAL: f32 = 1f32 / 120f32;
+
+fn one_euro_alpha(cutoff: f32, interval: f32) -> f32 {
+    let tau = 1f32 / (2f32 * f32::consts::PI * cutoff);
+    1f32 / (1f32 + tau / interval)
+}
+
+/// Running state for one value of one pointer.
+pub enum Filter<T> {
+    Passthrough,
+    MovingAverage { entries: Vec<T>, window: usize, sum: T, pos: usize },
+    Exponential { alpha: f32, last: Option<T> },
+    OneEuro { min_cutoff: f32, beta: f32, derivative_cutoff: f32, last: Option<(T, T, f32)> },
+    LazyBrush { radius: f32, last: Option<T> },
+}
+
+impl<T: Smoothable> Filter<T> {
+    pub fn new(kind: FilterKind) -> Filter<T> {
+        match kind {
+            FilterKind::Passthrough => Filter::Passthrough,
+            FilterKind::MovingAverage(window) => {
+                let window = if window == 0 { 1 } else { window };
+                Filter::MovingAverage { entries: Vec::with_capacity(window), window: window, sum: Smoothable::zero(), pos: 0 }
+            },
+            FilterKind::Exponential(alpha) => Filter::Exponential { alpha: alpha, last: None },
+            FilterKind::OneEuro { min_cutoff, beta, derivative_cutoff } =>
+                Filter::OneEuro { min_cutoff: min_cutoff, beta: beta, derivative_cutoff: derivative_cutoff, last: None },
+            FilterKind::LazyBrush(radius) => Filter::LazyBrush { radius: radius, last: None },
+        }
+    }
+
+    /// Adds a sample taken at time (in seconds) and returns the filtered value.
+    pub fn push(&mut self, value: T, time: f32) -> T {
+        match *self {
+            Filter::Passthrough => value,
+            Filter::MovingAverage { ref mut entries, window, ref mut sum, ref mut pos } => {
+                if entries.len() < window {
+                    entries.push(value);
+                } else {
+                    *sum = (*sum).minus(entries[*pos]);
+                    entries[*pos] = value;
+                }
+                *sum = (*sum).plus(value);
+                *pos = (*pos + 1) % window;
+                (*sum).scale(1f32 / entries.len() as f32)
+            },
+            Filter::Exponential { alpha, ref mut last } => {
+                let out = match *last {
+                    Some(prev) => lerp(prev, value, alpha),
+                    None => value,
+                };
+                *last = Some(out);
+                out
+            },
+            Filter::OneEuro { min_cutoff, beta, derivative_cutoff, ref mut last } => {
+                let (out, derivative) = match *last {
+                    Some((prev, prevderivative, prevtime)) => {
+                        let interval = if time > prevtime { time - prevtime } else { FALLBACK_INTERVAL };
+                        let rawderivative = value.minus(prev).scale(1f32 / interval);
+                        let derivative = lerp(prevderivative, rawderivative, one_euro_alpha(derivative_cutoff, interval));
+                        let cutoff = min_cutoff + beta * derivative.magnitude();
+                        (lerp(prev, value, one_euro_alpha(cutoff, interval)), derivative)
+                    },
+                    None => (value, Smoothable::zero()),
+                };
+                *last = Some((out, derivative, time));
+                out
+            },
+            Filter::LazyBrush { radius, ref mut last } => {
+                let out = match *last {
+                    Some(prev) => {
+                        let offset = value.minus(prev);
+                        let distance = offset.magnitude();
+                        if distance > radius {
+                            prev.plus(offset.scale((distance - radius) / distance))
+                        } else {
+                            prev
+                        }
+                    },
+                    None => value,
+                };
+                *last = Some(out);
+                out
+            },
+        }
+    }
+}
+
+fn get_f32(json: &Json, key: &str, default: f32) -> GLResult<f32> {
+    match json.find(key) {
+        Some(value) => match value.as_f64() {
+            Some(x) => Ok(x as f32),
+            None => Err(format!("smoothing: \"{}\" needs a number", key).into_cow()),
+        },
+        None => Ok(default),
+    }
+}
+
+/// Reads a filter description like {"type": "oneeuro", "mincutoff": 1, "beta": 0.007}.
+/// Settings that are left out get reasonable defaults.
+pub fn parse_filter(json: &Json) -> GLResult<FilterKind> {
+    let kind = match json.find("type").and_then(|x| x.as_string()) {
+        Some(kind) => kind,
+        None => return Err("smoothing: filter needs a \"type\"".into_cow()),
+    };
+    match kind {
+        "none" => Ok(FilterKind::Passthrough),
+        "average" => {
+            let window = try!(get_f32(json, "window", 16f32));
+            if window < 1f32 {
+                return Err("smoothing: average window must be at least 1".into_cow());
+            }
+            Ok(FilterKind::MovingAverage(window as usize))
+        },
+        "exponential" => Ok(FilterKind::Exponential(try!(get_f32(json, "alpha", 0.5f32)))),
+        "oneeuro" => Ok(FilterKind::OneEuro {
+            min_cutoff: try!(get_f32(json, "mincutoff", 1f32)),
+            beta: try!(get_f32(json, "beta", 0.007f32)),
+            derivative_cutoff: try!(get_f32(json, "dcutoff", 1f32)),
+        }),
+        "lazybrush" => Ok(FilterKind::LazyBrush(try!(get_f32(json, "radius", 8f32)))),
+        x => Err(format!("smoothing: unknown filter type \"{}\"", x).into_cow()),
+    }
+}
+
+/// Reads {"position": filter, "size": filter, "speed": filter}, where anything left out keeps
+/// its StrokeFilter::default() setting.
+pub fn parse_stroke_filter(json: &Json) -> GLResult<StrokeFilter> {
+    let obj = match json.as_object() {
+        Some(obj) => obj,
+        None => return Err("smoothing: expected an object".into_cow()),
+    };
+    let mut filter = StrokeFilter::default();
+    for (key, value) in obj.iter() {
+        match key.as_slice() {
+            "position" => filter.position = try!(parse_filter(value)),
+            "size" => filter.size = try!(parse_filter(value)),
+            "speed" => filter.speed = try!(parse_filter(value)),
+            x => return Err(format!("smoothing: unknown key \"{}\"", x).into_cow()),
+        }
+    }
+    Ok(filter)
+}
+
+pub fn parse_stroke_filter_str(text: &str) -> GLResult<StrokeFilter> {
+    match Json::from_str(text) {
+        Ok(json) => parse_stroke_filter(&json),
+        Err(e) => Err(format!("smoothing: invalid json: {:?}", e).into_cow()),
+    }
+}
+
+#[test]
+fn moving_average_window() {
+    let mut filter: Filter<f32> = Filter::new(FilterKind::MovingAverage(2));
+    assert_eq!(filter.push(2f32, 0f32), 2f32);
+    assert_eq!(filter.push(4f32, 0f32), 3f32);
+    assert_eq!(filter.push(8f32, 0f32), 6f32);
+}
+
+#[test]
+fn lazy_brush_trails_by_radius() {
+    let mut filter: Filter<Coordinate> = Filter::new(FilterKind::LazyBrush(5f32));
+    let origin = Coordinate { x: 0f32, y: 0f32 };
+    assert_eq!(filter.push(origin, 0f32), origin);
+    assert_eq!(filter.push(Coordinate { x: 3f32, y: 0f32 }, 0f32), origin);
+    assert_eq!(filter.push(Coordinate { x: 12f32, y: 0f32 }, 0f32), Coordinate { x: 7f32, y: 0f32 });
+}
+
+#[test]
+fn one_euro_follows_steady_input() {
+    let mut filter: Filter<f32> = Filter::new(FilterKind::OneEuro { min_cutoff: 1f32, beta: 0f32, derivative_cutoff: 1f32 });
+    filter.push(0f32, 0f32);
+    let first = filter.push(10f32, 0.01f32);
+    assert!(first > 0f32 && first < 10f32);
+    let mut out = first;
+    for i in range(2, 500) {
+        out = filter.push(10f32, i as f32 * 0.01f32);
+    }
+    assert!((out - 10f32).abs() < 0.01f32);
+}
+
+#[test]
+fn parse_fills_defaults() {
+    let filter = parse_stroke_filter_str("{\"position\": {\"type\": \"lazybrush\", \"radius\": 12}}").unwrap();
+    assert_eq!(filter.position, FilterKind::LazyBrush(12f32));
+    assert_eq!(filter.size, FilterKind::MovingAverage(16));
+    assert!(parse_stroke_filter_str("{\"position\": {\"type\": \"wobbly\"}}").is_err());
+}
@@ -0,0 +1,264 @@
+/// Loads unibrush packages and standalone assets from disk, outside of android.
+///
+/// Mirrors UniBrush.scala: a package is a brush.json plus the files it names.  On the device
+/// these are zipped; here they're read from the unpacked directories in the source tree.
+
+use core::prelude::*;
+use core::borrow::IntoCow;
+use collections::vec::Vec;
+use collections::string::String;
+use std::old_io::{File, IoResult};
+use std::old_path::Path;
+use serialize::json::Json;
+
+use glcommon::{GLResult, MString};
+use gltexture::PixelFormat;
+use paintlayer::{LayerStyle, BlendMode};
+use pngfile;
+use smoothing::{self, StrokeFilter};
+
+/// (vertex, fragment); None means the default for that half.
+pub type ShaderSource = (Option<MString>, Option<MString>);
+
+/// A brush image as android would load it: one alpha byte per pixel.
+pub struct BrushSource {
+    pub dimensions: (i32, i32),
+    pub pixels: Vec<u8>,
+}
+
+impl BrushSource {
+    pub fn format(&self) -> PixelFormat { PixelFormat::ALPHA }
+}
+
+pub struct LayerSource {
+    pub pointshader: Option<usize>,
+    pub copyshader: Option<usize>,
+    pub pointsrc: i32,
+    pub style: LayerStyle,
+}
+
+/// Everything a package can specify.  Anything left as None keeps whatever was selected before.
+pub struct UniBrushSource {
+    pub brush: Option<BrushSource>,
+    pub pointshaders: Vec<ShaderSource>,
+    pub animshaders: Vec<ShaderSource>,
+    pub basepointshader: Option<ShaderSource>,
+    pub baseanimshader: Option<ShaderSource>,
+    pub basecopyshader: Option<ShaderSource>,
+    pub interpolator: Option<MString>,
+    pub layers: Vec<LayerSource>,
+    pub smoothing: Option<StrokeFilter>,
+}
+
+impl UniBrushSource {
+    pub fn empty() -> UniBrushSource {
+        UniBrushSource {
+            brush: None,
+            pointshaders: Vec::new(),
+            animshaders: Vec::new(),
+            basepointshader: None,
+            baseanimshader: None,
+            basecopyshader: None,
+            interpolator: None,
+            layers: Vec::new(),
+            smoothing: None,
+        }
+    }
+}
+
+fn to_glresult<T>(result: IoResult<T>, path: &Path) -> GLResult<T> {
+    result.map_err(|e| -> MString { format!("unibrush: can't read {}: {}", path.display(), e).into_cow() })
+}
+
+fn read_text(path: &Path) -> GLResult<MString> {
+    let text = try!(to_glresult(File::open(path).read_to_string(), path));
+    Ok(text.into_cow())
+}
+
+pub fn load_brush(path: &Path) -> GLResult<BrushSource> {
+    let image = try!(pngfile::load_png(format!("{}", path.display()).as_slice()));
+    Ok(BrushSource { dimensions: (image.width, image.height), pixels: image.to_alpha() })
+}
+
+/// Same rule as DrawFiles.halfShaderPair: a lone shader file is a vertex shader if it sets
+/// gl_Position, and a fragment shader if it sets gl_FragColor.
+pub fn half_shader_pair(source: MString) -> GLResult<ShaderSource> {
+    if source.as_slice().contains("gl_Position") {
+        Ok((Some(source), None))
+    } else if source.as_slice().contains("gl_FragColor") {
+        Ok((None, Some(source)))
+    } else {
+        Err("unibrush: shader file sets neither gl_Position nor gl_FragColor".into_cow())
+    }
+}
+
+fn file_in(dir: &Path, json: &Json, kind: &str) -> GLResult<Path> {
+    match json.as_string() {
+        Some(name) => Ok(dir.join(name)),
+        None => Err(format!("unibrush: expected a file name for {}", kind).into_cow()),
+    }
+}
+
+fn read_shader(dir: &Path, json: &Json) -> GLResult<ShaderSource> {
+    let obj = match json.as_object() {
+        Some(obj) => obj,
+        None => return Err("unibrush: expected a shader object".into_cow()),
+    };
+    let mut shader: ShaderSource = (None, None);
+    for (key, value) in obj.iter() {
+        match key.as_slice() {
+            "vertexshader" => shader.0 = Some(try!(read_text(&try!(file_in(dir, value, "vertex shader"))))),
+            "fragmentshader" => shader.1 = Some(try!(read_text(&try!(file_in(dir, value, "fragment shader"))))),
+            x => return Err(format!("unibrush: unknown shader key \"{}\"", x).into_cow()),
+        }
+    }
+    Ok(shader)
+}
+
+fn read_shaders(dir: &Path, json: &Json) -> GLResult<Vec<ShaderSource>> {
+    match json.as_array() {
+        Some(list) => {
+            let mut shaders = Vec::new();
+            for item in list.iter() {
+                shaders.push(try!(read_shader(dir, item)));
+            }
+            Ok(shaders)
+        },
+        None => Err("unibrush: expected a list of shaders".into_cow()),
+    }
+}
+
+fn read_layer(json: &Json) -> GLResult<LayerSource> {
+    let obj = match json.as_object() {
+        Some(obj) => obj,
+        None => return Err("unibrush: expected a layer object".into_cow()),
+    };
+    let mut layer = LayerSource { pointshader: None, copyshader: None, pointsrc: 0, style: LayerStyle::default() };
+    for (key, value) in obj.iter() {
+        match key.as_slice() {
+            "blend" => layer.style.blend = match value.as_string() {
+                Some("normal") => BlendMode::Normal,
+                Some("multiply") => BlendMode::Multiply,
+                Some("screen") => BlendMode::Screen,
+                Some("add") => BlendMode::Add,
+                Some("erase") => BlendMode::Erase,
+                _ => return Err(format!("unibrush: no blend mode named {}", value).into_cow()),
+            },
+            "opacity" => layer.style.opacity = match value.as_f64() {
+                Some(x) => x as f32,
+                None => return Err("unibrush: layer opacity needs a number".into_cow()),
+            },
+            "visible" => layer.style.visible = match value.as_boolean() {
+                Some(x) => x,
+                None => return Err("unibrush: layer visibility needs to be true or false".into_cow()),
+            },
+            _ => {
+                let num = match value.as_u64() {
+                    Some(x) => x,
+                    None => return Err(format!("unibrush: layer key \"{}\" needs a number", key).into_cow()),
+                };
+                match key.as_slice() {
+                    "pointshader" => layer.pointshader = Some(num as usize),
+                    "copyshader" => layer.copyshader = Some(num as usize),
+                    "pointsrc" => layer.pointsrc = num as i32,
+                    x => return Err(format!("unibrush: unknown layer key \"{}\"", x).into_cow()),
+                }
+            },
+        }
+    }
+    Ok(layer)
+}
+
+/// Reads dir/brush.json and everything it refers to.
+pub fn load_from_dir(dir: &Path) -> GLResult<UniBrushSource> {
+    let jsonpath = dir.join("brush.json");
+    let text = try!(read_text(&jsonpath));
+    let json = match Json::from_str(text.as_slice()) {
+        Ok(json) => json,
+        Err(e) => return Err(format!("unibrush: invalid brush.json: {:?}", e).into_cow()),
+    };
+    let obj = match json.as_object() {
+        Some(obj) => obj,
+        None => return Err("unibrush: brush.json isn't an object".into_cow()),
+    };
+    let mut brush = UniBrushSource::empty();
+    for (key, value) in obj.iter() {
+        match key.as_slice() {
+            "brushpath" => brush.brush = Some(try!(load_brush(&try!(file_in(dir, value, "brush"))))),
+            "pointshaders" => brush.pointshaders = try!(read_shaders(dir, value)),
+            "animshaders" => brush.animshaders = try!(read_shaders(dir, value)),
+            "basepointshader" => brush.basepointshader = Some(try!(read_shader(dir, value))),
+            "baseanimshader" => brush.baseanimshader = Some(try!(read_shader(dir, value))),
+            "basecopyshader" => brush.basecopyshader = Some(try!(read_shader(dir, value))),
+            "interpolator" => brush.interpolator = Some(try!(read_text(&try!(file_in(dir, value, "lua script"))))),
+            "smoothing" => brush.smoothing = Some(try!(smoothing::parse_stroke_filter(value))),
+            "layers" => {
+                let list = match value.as_array() {
+                    Some(list) => list,
+                    None => return Err("unibrush: expected a list of layers".into_cow()),
+                };
+                for layer in list.iter() {
+                    brush.layers.push(try!(read_layer(layer)));
+                }
+            },
+            x => return Err(format!("unibrush: unknown key \"{}\"", x).into_cow()),
+        }
+    }
+    for layer in brush.layers.iter() {
+        if let Some(ps) = layer.pointshader {
+            if ps >= brush.pointshaders.len() {
+                return Err(format!("unibrush: no point shader numbered {}", ps).into_cow());
+            }
+        }
+        if let Some(cs) = layer.copyshader {
+            if cs >= brush.animshaders.len() {
+                return Err(format!("unibrush: no anim shader numbered {}", cs).into_cow());
+            }
+        }
+    }
+    Ok(brush)
+}
+
+/// Wraps a single preinstalled asset file as a package that only sets that one thing.
+/// kind is the assets/ subdirectory it came from.
+pub fn load_asset(kind: &str, path: &Path) -> GLResult<UniBrushSource> {
+    let mut brush = UniBrushSource::empty();
+    match kind {
+        "brushes" => brush.brush = Some(try!(load_brush(path))),
+        "pointshaders" => brush.basepointshader = Some(try!(half_shader_pair(try!(read_text(path))))),
+        "animshaders" => brush.baseanimshader = Some(try!(half_shader_pair(try!(read_text(path))))),
+        "interpolators" => brush.interpolator = Some(try!(read_text(path))),
+        x => return Err(format!("unibrush: unknown asset kind \"{}\"", x).into_cow()),
+    }
+    Ok(brush)
+}
+
+/// A name for reports and reference images, from the last path component.
+pub fn package_name(path: &Path) -> String {
+    match path.filename_str() {
+        Some(name) => String::from_str(name),
+        None => String::from_str("unnamed"),
+    }
+}
+
+#[test]
+fn load_shipped_unibrushes() {
+    for name in ["Neon", "Kittens", "Bezier-O-Matic", "Smear", "Emboss"].iter() {
+        let dir = Path::new("../unibrushes").join(*name);
+        assert!(load_from_dir(&dir).is_ok(), "failed to load {}", name);
+    }
+    let neon = load_from_dir(&Path::new("../unibrushes/Neon")).unwrap();
+    assert_eq!(neon.layers.len(), 1);
+    assert_eq!(neon.brush.unwrap().pixels.len(), 64 * 64);
+}
+
+#[test]
+fn layer_styles() {
+    let json = Json::from_str(r#"{"pointsrc": 1, "blend": "screen", "opacity": 0.5, "visible": false}"#).unwrap();
+    let layer = read_layer(&json).unwrap();
+    assert_eq!(layer.pointsrc, 1);
+    assert_eq!(layer.style, LayerStyle { blend: BlendMode::Screen, opacity: 0.5f32, visible: false });
+    let plain = read_layer(&Json::from_str(r#"{"pointshader": 0}"#).unwrap()).unwrap();
+    assert_eq!(plain.style, LayerStyle::default());
+    assert!(read_layer(&Json::from_str(r#"{"blend": "overlay"}"#).unwrap()).is_err());
+}
@@ -6,7 +6,8 @@ import android.os.Bundle
 import android.content.DialogInterface
 import java.lang.Runnable
 import scala.collection.mutable.ArrayBuffer
-import android.util.{JsonReader, JsonWriter}
+import android.util.{JsonReader, JsonWriter, JsonToken}
+import java.io.StringWriter
 
 trait AndroidImplicits {
   //dialog
@@ -62,5 +63,29 @@ trait AndroidImplicits {
       j.endArray()
       arr
     }
+    // for values that get handed to native code as-is
+    def readRawJson(): String = {
+      val out = new StringWriter()
+      val w = new JsonWriter(out)
+      def copyValue(): Unit = j.peek() match {
+        case JsonToken.BEGIN_OBJECT => {
+          w.beginObject()
+          readObject(name => { w.name(name); copyValue() })
+          w.endObject()
+        }
+        case JsonToken.BEGIN_ARRAY => {
+          w.beginArray()
+          readArray(_ => copyValue())
+          w.endArray()
+        }
+        case JsonToken.STRING => w.value(j.nextString())
+        case JsonToken.NUMBER => w.value(j.nextDouble())
+        case JsonToken.BOOLEAN => w.value(j.nextBoolean())
+        case _ => { j.nextNull(); w.nullValue() }
+      }
+      copyValue()
+      w.close()
+      out.toString()
+    }
   }
 }
@@ -34,7 +34,8 @@ object LuaSyntaxHighlightProcessor {
     
     val apimethods = joinKeywords(Array(
       "pushpoint", "pushline", "pushcatmullrom", "pushcubicbezier",
-      "loglua", "clearlayer", "savelayers", "saveundo"))
+      "loglua", "clearlayer", "savelayers", "saveundo",
+      "removelayer", "movelayer", "mergelayer", "duplicatelayer"))
 
     // not actually used!
     val bannedmethods = joinKeywords(Array(
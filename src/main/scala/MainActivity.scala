@@ -154,14 +154,20 @@ class MainActivity extends Activity with TypedActivity with AndroidImplicits {
     populatePickers(producer, thread, gl)
     thread.setBrushColor(gl, colorPicker.getColor())
     thread.setBrushSize(gl, colorPicker.getScale())
-    val listener = new ToggleableMotionEventListener(producer)
+    val listener = createViewTouchListener(producer)
     drawerToggle.setMotionEventListener(listener)
     content.setOnTouchListener(listener)
+    content.setOnHoverListener(listener)
     Log.i("everybody-draws", "main thread: finished texture setup")
   }
 
   def createViewTouchListener(producer: MotionEventProducer) = {
-    new ToggleableMotionEventListener(producer)
+    // two fingers tapped undoes, three redoes
+    new ToggleableMotionEventListener(producer, (fingers: Int) => fingers match {
+      case 2 => moveUndo(-1)
+      case 3 => moveUndo(1)
+      case _ => { }
+    })
   }
 
   override def onCreate(bundle: Bundle) {
@@ -243,6 +249,7 @@ class MainActivity extends Activity with TypedActivity with AndroidImplicits {
     if (drawerToggle.onOptionsItemSelected(item)) true
     else item.getItemId() match {
       case R.id.menu_save => saveFile()
+      case R.id.menu_save_transparent => saveFile(keepAlpha = true)
       case R.id.menu_load => loadFile()
       //case R.id.menu_replay => startReplay()
       case R.id.menu_clear => this.clearScreen()
@@ -537,11 +544,15 @@ class MainActivity extends Activity with TypedActivity with AndroidImplicits {
     for (layer <- unibrush.layers) {
       thread.addLayer(gl, layer.copyshader, layer.pointshader, layer.pointsrc)
     }
+    for ((layer, idx) <- unibrush.layers.zipWithIndex if layer.hasStyle) {
+      thread.setLayerStyle(gl, idx + 1, layer.blend, layer.opacity, layer.visible)
+    }
     brush.foreach(thread.setBrushTexture(gl, _))
     anim.foreach(thread.setAnimShader(gl, _))
     point.foreach(thread.setPointShader(gl, _))
     copy.foreach(thread.setCopyShader(gl, _))
     interp.foreach(thread.setInterpScript(gl, _))
+    thread.setStrokeFilter(gl, unibrush.smoothing.getOrElse("{}")) // an empty object means the default filters
     loadUniBrushControls(unibrush) // now that we're done, update which controls are enabled
     ()
   }
@@ -562,7 +573,7 @@ class MainActivity extends Activity with TypedActivity with AndroidImplicits {
     startActivityForResult(chooser, ACTIVITY_CHOOSE_IMAGE) 
   }
 
-  def saveFile() = {
+  def saveFile(keepAlpha: Boolean = false) = {
     val name = new Date().toString() + ".png"
     Toast.makeText(this, "Saving as \"%s\"...".format(name), Toast.LENGTH_LONG).show()
     textureThread.foreach(thread => {
@@ -576,7 +587,7 @@ class MainActivity extends Activity with TypedActivity with AndroidImplicits {
                 }
               } catch { case _: Exception => { } }
             }(saveThread)
-          })
+          }, keepAlpha)
       })
   }
 
@@ -662,7 +673,7 @@ class MainActivity extends Activity with TypedActivity with AndroidImplicits {
        }
 
        val unisrc = 
-         getGL(gl, controls.unipicker).getOrElse(UniBrush(None, None, None, None, None, Array()))
+         getGL(gl, controls.unipicker).getOrElse(UniBrush(None, None, None, None, None, Array(), None))
        val anim = unisrc.baseanimshader.orElse(getGL(gl, controls.animpicker))
        val copy = unisrc.basecopyshader.orElse(getGL(gl, controls.copypicker))
        val paint = unisrc.basepointshader.orElse(getGL(gl, controls.paintpicker))
@@ -809,13 +820,21 @@ object MainActivity {
     final val ACTIVITY_CHOOSE_IMAGE = 0x1;
   }
 
-  class ToggleableMotionEventListener(producer: MotionEventProducer)
-  extends View.OnTouchListener {
+  class ToggleableMotionEventListener(producer: MotionEventProducer, onTap: (Int) => Unit)
+  extends View.OnTouchListener with View.OnHoverListener {
     def setForwardEvents(forwardEvents: Boolean): Unit = {
       this.forwardEvents = forwardEvents
     }
     final private var forwardEvents = true
     override def onTouch(v: View, evt: MotionEvent) = {
+      if (forwardEvents) MotionEventProducer.nativeAppendMotionEvent(producer, evt) match {
+        case 0 => { }
+        case fingers => onTap(fingers)
+      }
+      true
+    }
+    // hovering tells palm rejection when a stylus is in range
+    override def onHover(v: View, evt: MotionEvent) = {
       if (forwardEvents) MotionEventProducer.nativeAppendMotionEvent(producer, evt)
       true
     }
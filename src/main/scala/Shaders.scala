@@ -86,9 +86,10 @@ object LuaScript {
 }
 
 object GLInit {
-  @native def initGL(width: Int, height: Int, callback: UndoCallback): Int;
-  def apply(width: Int, height: Int, callback: UndoCallback): GLInit = {
-    new GLInit(initGL(width, height, callback))
+  @native def initGL(width: Int, height: Int, canvasWidth: Int, canvasHeight: Int, callback: UndoCallback): Int;
+  // a canvas size of 0 makes the canvas the same size as the surface
+  def apply(width: Int, height: Int, callback: UndoCallback, canvasWidth: Int = 0, canvasHeight: Int = 0): GLInit = {
+    new GLInit(initGL(width, height, canvasWidth, canvasHeight, callback))
   }
   // helper for texturesurfacethread
   def fromMessage(m: Message) = {
@@ -98,15 +99,26 @@ object GLInit {
 }
 
 object MotionEventProducer {
-  @native def nativeAppendMotionEvent(producer: MotionEventProducer, evt: MotionEvent): Unit
+  // returns the number of fingers in a tap gesture, or 0
+  @native def nativeAppendMotionEvent(producer: MotionEventProducer, evt: MotionEvent): Int
   @native def nativePauseMotionEvent(handler: MotionEventProducer): Unit
+  // excluded holds left, top, right, bottom for each area; a maxContactSize of 0 turns that rule off
+  @native def nativeSetRejectionRules(producer: MotionEventProducer, maxContactSize: Float, rejectFingersNearStylus: Boolean, excluded: Array[Float]): Unit
 }
 
 object Replay {
   @native def init(data: GLInit): Replay
   @native def destroy(replay: Replay): Unit
-  @native def advanceFrame(data: GLInit, replay: Replay, matrix: Array[Float]): Boolean
+  @native def advanceFrame(data: GLInit, replay: Replay, matrix: Array[Float], uptimeMillis: Long): Boolean
+  @native def setSpeed(replay: Replay, mode: Int, factor: Float): Unit
+  @native def seek(data: GLInit, replay: Replay, matrix: Array[Float], position: Int, uptimeMillis: Long): Boolean
+  @native def eventCount(replay: Replay): Int
+  @native def position(replay: Replay): Int
   val nullReplay = new Replay(0)
+  // modes for setSpeed
+  val FollowFrames = 0
+  val FollowTimestamps = 1
+  val RunToEnd = 2
 }
 
 case class MotionEventHandlerPair(
@@ -4,7 +4,7 @@ import java.util.concurrent.atomic.AtomicBoolean
 import android.graphics.SurfaceTexture
 import android.os.{Handler, Looper, Message, SystemClock}
 import android.util.Log
-import android.graphics.Bitmap
+import android.graphics.{Bitmap, Matrix}
 import unibrush.Layer
 import MainActivity.Rotation
 
@@ -32,16 +32,34 @@ extends Thread with Handler.Callback with AndroidImplicits {
   @native protected def nativeSetCopyShader(data: GLInit, shader: CopyShader): Boolean
   @native protected def nativeSetPointShader(data: GLInit, shader: PointShader): Boolean
   @native protected def nativeSetBrushTexture(data: GLInit, t: TexturePtr): Unit
-  @native protected def nativeExportPixels(data: GLInit): Bitmap
+  @native protected def nativeExportPixels(data: GLInit, keepAlpha: Boolean): Bitmap
+  @native protected def nativeExportLayerPixels(data: GLInit, layer: Int, keepAlpha: Boolean): Bitmap
+  @native protected def nativeDrawLayerImage(data: GLInit, layer: Int, bitmap: Bitmap, placement: Array[Float]): Unit
   @native protected def nativeSetInterpolator(data: GLInit, script: LuaScript): Unit
   @native protected def nativeAddLayer(data: GLInit, copyshader: CopyShader, pointshader: PointShader, pointidx: Int): Unit
   @native protected def nativeClearLayers(data: GLInit): Unit
+  @native protected def nativeSetLayerStyle(data: GLInit, layer: Int, blend: Int, opacity: Float, visible: Boolean): Unit
+  @native protected def nativeRemoveLayer(data: GLInit, layer: Int): Unit
+  @native protected def nativeMoveLayer(data: GLInit, from: Int, to: Int): Unit
+  @native protected def nativeMergeLayer(data: GLInit, layer: Int): Unit
+  @native protected def nativeDuplicateLayer(data: GLInit, layer: Int): Unit
   @native protected def nativeLoadUndo(data: GLInit, pos: Int): Unit
   @native protected def nativePushUndoFrame(data: GLInit): Int
   @native protected def nativeClearUndoFrames(data: GLInit): Unit
+  @native protected def nativeUndo(data: GLInit): Int
+  @native protected def nativeRedo(data: GLInit): Int
+  @native protected def nativeSetUndoBudget(data: GLInit, gpuMegabytes: Int, cpuMegabytes: Int): Unit
   //@native protected def nativeSetBrushProperties(props: BrushProperties): Unit
   @native protected def nativeSetBrushColor(data: GLInit, color: Int): Unit
   @native protected def nativeSetBrushSize(data: GLInit, size: Float): Unit
+  @native protected def nativeSetEraserMode(data: GLInit, erase: Boolean): Unit
+  @native protected def nativeSetStrokeFilter(data: GLInit, json: String): Unit
+  @native protected def nativeSetPrediction(data: GLInit, millis: Float): Unit
+  @native protected def nativeResetView(data: GLInit): Unit
+  @native protected def nativeResizeCanvas(data: GLInit, width: Int, height: Int): Unit
+  @native protected def nativeCropCanvas(data: GLInit, x: Int, y: Int, width: Int, height: Int): Unit
+  @native protected def nativeSaveSession(data: GLInit, path: String): Unit
+  @native protected def nativeLoadSession(data: GLInit, path: String): Unit
 
   override def run() = {
     Looper.prepare()
@@ -136,7 +154,8 @@ extends Thread with Handler.Callback with AndroidImplicits {
   }
 
   // callback runs on gl thread
-  def getBitmap(cb: (GLInit, Bitmap)=>Any) = withGL(gl => cb(gl, nativeExportPixels(gl)))
+  // with keepAlpha, the bitmap isn't premultiplied and keeps the canvas's transparency
+  def getBitmap(cb: (GLInit, Bitmap)=>Any, keepAlpha: Boolean = false) = withGL(gl => cb(gl, nativeExportPixels(gl, keepAlpha)))
 
   def getBitmapSynchronized() = {
     var bitmap: Bitmap = null
@@ -159,6 +178,16 @@ extends Thread with Handler.Callback with AndroidImplicits {
     nativeDrawImage(gl, bitmap, MainActivity.NoRotation)
   }
 
+  // layer 0 is the canvas itself; layers come out without their copy shader or style
+  def getLayerBitmap(gl: GLInit, layer: Int, keepAlpha: Boolean = false): Bitmap = nativeExportLayerPixels(gl, layer, keepAlpha)
+
+  // draws over what's already on the layer; placement takes bitmap pixels to canvas pixels
+  def drawLayerBitmap(gl: GLInit, layer: Int, bitmap: Bitmap, placement: Matrix) = {
+    val values = new Array[Float](9)
+    placement.getValues(values)
+    nativeDrawLayerImage(gl, layer, bitmap, values)
+  }
+
   // private
   private def drawQueuedPoints(g: GLInit) = {
     nativeDrawQueuedPoints(g, motionHandler, matrix)
@@ -172,7 +201,7 @@ extends Thread with Handler.Callback with AndroidImplicits {
   }
 
   private def drawReplayFrame(gl: GLInit, r: Replay) = {
-    val finished = Replay.advanceFrame(gl, r, matrix)
+    val finished = Replay.advanceFrame(gl, r, matrix, SystemClock.uptimeMillis())
     if (finished) {
       Replay.destroy(r)
       this.replay = Replay.nullReplay
@@ -187,9 +216,32 @@ extends Thread with Handler.Callback with AndroidImplicits {
     nativeSetBrushTexture(gl, texture.ptr)
   }
 
-  def beginReplay() {
+  def beginReplay(mode: Int = Replay.FollowFrames, factor: Float = 1f) {
     withGL(gl => {
       replay = Replay.init(gl)
+      Replay.setSpeed(replay, mode, factor)
+    })
+  }
+
+  def seekReplay(position: Int) {
+    withGL(gl => {
+      if (replay != Replay.nullReplay && Replay.seek(gl, replay, matrix, position, SystemClock.uptimeMillis())) {
+        Replay.destroy(replay)
+        replay = Replay.nullReplay
+      }
+    })
+  }
+
+  // (position, total) for a timeline slider, if a replay is running
+  def getReplayProgress(cb: Option[(Int, Int)] => Unit) {
+    withGL(gl => {
+      cb(if (replay == Replay.nullReplay) None else Some((Replay.position(replay), Replay.eventCount(replay))))
+    })
+  }
+
+  def setReplaySpeed(mode: Int, factor: Float) {
+    withGL(gl => {
+      if (replay != Replay.nullReplay) Replay.setSpeed(replay, mode, factor)
     })
   }
 
@@ -199,9 +251,29 @@ extends Thread with Handler.Callback with AndroidImplicits {
     nativeAddLayer(gl, copyshader, pointshader, pointidx)
   }
 
+  // layers are numbered from 1; blend is one of the unibrush.BlendMode constants
+  def setLayerStyle(gl: GLInit, layer: Int, blend: Int, opacity: Float, visible: Boolean) = {
+    nativeSetLayerStyle(gl, layer, blend, opacity, visible)
+  }
+
+  def removeLayer(gl: GLInit, layer: Int) = nativeRemoveLayer(gl, layer)
+  // the layer ends up numbered to, with the ones in between shifted along
+  def moveLayer(gl: GLInit, from: Int, to: Int) = nativeMoveLayer(gl, from, to)
+  // merges into the layer below, or onto the canvas for layer 1
+  def mergeLayer(gl: GLInit, layer: Int) = nativeMergeLayer(gl, layer)
+  // the copy goes just above the original
+  def duplicateLayer(gl: GLInit, layer: Int) = nativeDuplicateLayer(gl, layer)
+
   def loadUndo(gl: GLInit, pos: Int) = nativeLoadUndo(gl, pos)
   def pushUndoFrame(gl: GLInit): Int = nativePushUndoFrame(gl)
   def clearUndoFrames(gl: GLInit) = nativeClearUndoFrames(gl)
+  // both return the undo position afterwards, or -1 if there was nothing to undo or redo
+  def undo(gl: GLInit): Int = nativeUndo(gl)
+  def redo(gl: GLInit): Int = nativeRedo(gl)
+  // older undo frames are compressed into cpu memory past the first limit, and forgotten past the second
+  def setUndoBudget(gl: GLInit, gpuMegabytes: Int, cpuMegabytes: Int) = nativeSetUndoBudget(gl, gpuMegabytes, cpuMegabytes)
+  def saveSession(gl: GLInit, path: String) = nativeSaveSession(gl, path)
+  def loadSession(gl: GLInit, path: String) = nativeLoadSession(gl, path)
 
   // only set values, could maybe run on main thread
   def setAnimShader(gl: GLInit, shader: CopyShader) = nativeSetAnimShader(gl, shader)
@@ -210,6 +282,16 @@ extends Thread with Handler.Callback with AndroidImplicits {
   def setCopyShader(gl: GLInit, shader: CopyShader) = nativeSetCopyShader(gl, shader)
   def setBrushColor(gl: GLInit, color: Int) = nativeSetBrushColor(gl, color)
   def setBrushSize(gl: GLInit, size: Float) = nativeSetBrushSize(gl, size)
+  def setEraserMode(gl: GLInit, erase: Boolean) = nativeSetEraserMode(gl, erase)
+  def setStrokeFilter(gl: GLInit, json: String) = nativeSetStrokeFilter(gl, json)
+  // how far ahead to draw where strokes are heading, 0 turns prediction off
+  def setPrediction(gl: GLInit, millis: Float) = nativeSetPrediction(gl, millis)
+  // undoes any zooming, panning or rotating, showing the canvas as it was at the start
+  def resetView(gl: GLInit) = nativeResetView(gl)
+  // stretches the canvas, layers and undo history to a new size
+  def resizeCanvas(gl: GLInit, width: Int, height: Int) = nativeResizeCanvas(gl, width, height)
+  // keeps the given area at its current scale; it can reach past the edges to grow the canvas
+  def cropCanvas(gl: GLInit, x: Int, y: Int, width: Int, height: Int) = nativeCropCanvas(gl, x, y, width, height)
 
   def withGL(cb: (GLInit) => Unit) = {
     val stack = new RuntimeException();
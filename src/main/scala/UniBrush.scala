@@ -38,10 +38,24 @@ object ShaderSource {
   }
 }
 
+// matches paintlayer::BlendMode
+object BlendMode {
+  val Normal = 0
+  val Multiply = 1
+  val Screen = 2
+  val Add = 3
+  val Erase = 4
+
+  val byName = Map("normal" -> Normal, "multiply" -> Multiply, "screen" -> Screen, "add" -> Add, "erase" -> Erase)
+}
+
 case class LayerSource(
   pointshader: Option[Int],
   copyshader: Option[Int],
-  pointsrc: Option[Int]
+  pointsrc: Option[Int],
+  blend: Option[Int],
+  opacity: Option[Float],
+  visible: Option[Boolean]
 )
 
 object LayerSource {
@@ -49,22 +63,36 @@ object LayerSource {
     var pointshader: Option[Int] = None
     var copyshader: Option[Int] = None
     var pointsrc: Option[Int] = None
+    var blend: Option[Int] = None
+    var opacity: Option[Float] = None
+    var visible: Option[Boolean] = None
     j.beginObject()
       while (j.hasNext()) j.nextName() match {
         case "pointshader" => pointshader = Some(j.nextInt())
         case "copyshader" => copyshader = Some(j.nextInt())
         case "pointsrc" => pointsrc = Some(j.nextInt())
+        case "blend" => {
+          val name = j.nextString()
+          blend = Some(BlendMode.byName.get(name).getOrElse(UniBrush.logAbort(s"no blend mode named ${name}")))
+        }
+        case "opacity" => opacity = Some(j.nextDouble().toFloat)
+        case "visible" => visible = Some(j.nextBoolean())
       }
     j.endObject()
-    LayerSource(pointshader, copyshader, pointsrc)
+    LayerSource(pointshader, copyshader, pointsrc, blend, opacity, visible)
   }
 }
 
 case class Layer(
   pointshader: PointShader,
   copyshader: CopyShader,
-  pointsrc: Int
-)
+  pointsrc: Int,
+  blend: Int,
+  opacity: Float,
+  visible: Boolean
+) {
+  def hasStyle = blend != BlendMode.Normal || opacity != 1f || !visible
+}
 
 case class UniBrushSource (
   brush: Option[Bitmap],
@@ -74,7 +102,8 @@ case class UniBrushSource (
   baseanimshader: Option[ShaderSource],
   basecopyshader: Option[ShaderSource],
   interpolator: Option[String],
-  layers: Array[LayerSource]
+  layers: Array[LayerSource],
+  smoothing: Option[String]
 )
 object UniBrushSource extends AndroidImplicits {
   def readFromJson(j: JsonReader, sourceFiles: Map[String, Array[Byte]]) = {
@@ -86,6 +115,7 @@ object UniBrushSource extends AndroidImplicits {
     var basecopyshader: Option[ShaderSource] = None
     var interpolator: Option[String] = None
     var layers: Array[LayerSource] = Array()
+    var smoothing: Option[String] = None
     j.beginObject()
       while (j.hasNext()) j.nextName() match {
         case "brushpath" => {
@@ -102,6 +132,7 @@ object UniBrushSource extends AndroidImplicits {
         case "basecopyshader" => basecopyshader = Some(ShaderSource.readFromJson(j, sourceFiles))
         case "interpolator" => interpolator = Some(new String(UniBrush.bytesOrException(j.nextString(), sourceFiles, "lua script")))
         case "layers" => layers = j.readArray(LayerSource.readFromJson).toArray
+        case "smoothing" => smoothing = Some(j.readRawJson())
       }
     j.endObject()
     for (layer <- layers) {
@@ -109,7 +140,7 @@ object UniBrushSource extends AndroidImplicits {
       for (cs <- layer.copyshader) { if (cs < 0 || cs >= animshaders.length) UniBrush.logAbort(s"no anim shader numbered ${cs}") }
     }
     UniBrushSource(brush, pointshaders, animshaders, basepointshader,
-      baseanimshader, basecopyshader, interpolator, layers)
+      baseanimshader, basecopyshader, interpolator, layers, smoothing)
   }
 }
 
@@ -119,7 +150,8 @@ case class UniBrush(
   baseanimshader: Option[CopyShader],
   basecopyshader: Option[CopyShader],
   interpolator: Option[LuaScript],
-  layers: Array[Layer])
+  layers: Array[Layer],
+  smoothing: Option[String])
 
 object UniBrush {
   def logAbort[T](s: String): GLResult[T] = {
@@ -196,7 +228,7 @@ object UniBrush {
       val point = l.pointshader.map(x => pointshaders(x)).getOrElse(PointShader(data, null, null))
       val copy = l.copyshader.map(x => copyshaders(x)).getOrElse(CopyShader(data, null, null))
       val idx = l.pointsrc.getOrElse(0)
-      Layer(point, copy, idx)
+      Layer(point, copy, idx, l.blend.getOrElse(BlendMode.Normal), l.opacity.getOrElse(1f), l.visible.getOrElse(true))
     })
   }
 
@@ -209,6 +241,6 @@ object UniBrush {
     val basepointshader = s.basepointshader.map(_.compile(data, PointShader))
     val interpolator = s.interpolator.map(LuaScript(data, _))
     val layers = getLayers(data, pointshaders.toArray, copyshaders.toArray, s.layers)
-    UniBrush(brush, basepointshader, baseanimshader, basecopyshader, interpolator, layers)
+    UniBrush(brush, basepointshader, baseanimshader, basecopyshader, interpolator, layers, s.smoothing)
   }
 }